@@ -0,0 +1,347 @@
+//! Access logs, with a line per response in one of the usual formats.
+
+use std::{
+  fmt,
+  fs::OpenOptions,
+  io::{self, Write},
+  net::IpAddr,
+  path::Path,
+  str::FromStr,
+  sync::Mutex,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::http::Request;
+
+/// How each line of the access log is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+  /// Apache's Common Log Format
+  #[default]
+  Common,
+  /// Common, plus the `Referer` and `User-Agent`
+  Combined,
+  /// One JSON object per line
+  Json,
+}
+
+#[derive(Debug)]
+pub struct LogFormatFail(pub String);
+
+impl fmt::Display for LogFormatFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "'{}' isn't a log format; try common, combined, or json",
+      self.0
+    )
+  }
+}
+
+impl FromStr for LogFormat {
+  type Err = LogFormatFail;
+
+  fn from_str(s: &str) -> Result<LogFormat, LogFormatFail> {
+    match &s.to_ascii_lowercase()[..] {
+      "common" => Ok(LogFormat::Common),
+      "combined" => Ok(LogFormat::Combined),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(LogFormatFail(s.into())),
+    }
+  }
+}
+
+/// How much gets logged. Each level includes everything below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+  /// Nothing at all
+  #[default]
+  Quiet,
+  /// Startup messages, warnings, and the access log
+  Normal,
+  /// Also every request's headers, how it was resolved to a file, the
+  /// response's headers, and a `curl` command to send it again, on stderr
+  Trace,
+}
+
+#[derive(Debug)]
+pub struct VerbosityFail(pub String);
+
+impl fmt::Display for VerbosityFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "'{}' isn't a verbosity; try quiet, normal, or trace",
+      self.0
+    )
+  }
+}
+
+impl FromStr for Verbosity {
+  type Err = VerbosityFail;
+
+  fn from_str(s: &str) -> Result<Verbosity, VerbosityFail> {
+    match &s.to_ascii_lowercase()[..] {
+      "quiet" => Ok(Verbosity::Quiet),
+      "normal" => Ok(Verbosity::Normal),
+      "trace" => Ok(Verbosity::Trace),
+      _ => Err(VerbosityFail(s.into())),
+    }
+  }
+}
+
+impl fmt::Display for Verbosity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Verbosity::Quiet => "quiet",
+      Verbosity::Normal => "normal",
+      Verbosity::Trace => "trace",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// Everything logged about a response, filled in as it's served.
+#[derive(Debug, Clone)]
+pub(crate) struct Record {
+  /// When the request arrived
+  pub time: SystemTime,
+  pub begin: Instant,
+  pub peer: Option<IpAddr>,
+  pub method: String,
+  pub path: String,
+  pub query: String,
+  pub protocol: &'static str,
+  pub referer: Option<String>,
+  pub user_agent: Option<String>,
+  pub status: u16,
+  /// Bytes of body sent
+  pub bytes: u64,
+}
+
+impl Record {
+  pub fn new(
+    request: &Request,
+    protocol: &'static str,
+    begin: Instant,
+  ) -> Record {
+    Record {
+      time: SystemTime::now() - begin.elapsed(),
+      begin,
+      peer: request.peer,
+      method: request.method.clone(),
+      path: request.path.clone(),
+      query: request.query.clone(),
+      protocol,
+      referer: request.header("Referer").map(Into::into),
+      user_agent: request.header("User-Agent").map(Into::into),
+      status: 0,
+      bytes: 0,
+    }
+  }
+
+  /// The request line as it came in, more or less
+  fn target(&self) -> String {
+    match &self.query[..] {
+      "" => format!("/{}", self.path),
+      query => format!("/{}?{}", self.path, query),
+    }
+  }
+
+  pub fn format(&self, format: LogFormat, duration: Duration) -> String {
+    let peer = self.peer.map_or("-".into(), |p| p.to_string());
+    let common = || {
+      let bytes = match self.bytes {
+        0 => "-".into(),
+        n => n.to_string(),
+      };
+      format!(
+        "{} - - [{}] \"{} {} {}\" {} {}",
+        peer,
+        clf_time(self.time),
+        quote_escape(&self.method),
+        quote_escape(&self.target()),
+        self.protocol,
+        self.status,
+        bytes
+      )
+    };
+    let or_dash = |h: &Option<String>| match h {
+      Some(h) => quote_escape(h),
+      None => "-".into(),
+    };
+    match format {
+      LogFormat::Common => format!("{}\n", common()),
+      LogFormat::Combined => format!(
+        "{} \"{}\" \"{}\"\n",
+        common(),
+        or_dash(&self.referer),
+        or_dash(&self.user_agent)
+      ),
+      LogFormat::Json => format!(
+        "{{\"time\":\"{}\",\"peer\":{},\"method\":\"{}\",\"path\":\"{}\",\
+         \"query\":\"{}\",\"status\":{},\"bytes\":{},\"duration_us\":{}}}\n",
+        iso_time(self.time),
+        self.peer.map_or("null".into(), |p| format!("\"{}\"", p)),
+        json_escape(&self.method),
+        json_escape(&format!("/{}", self.path)),
+        json_escape(&self.query),
+        self.status,
+        self.bytes,
+        duration.as_micros()
+      ),
+    }
+  }
+}
+
+/// Escape the inside of a quoted field in Common/Combined logs, so requests
+/// can't forge log lines.
+fn quote_escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+pub(crate) fn json_escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// Split a time into UTC year, month, day, hour, minute, second
+fn civil(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+  let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+  let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+  // Howard Hinnant's days_from_civil, in reverse
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+/// Like `10/Oct/2000:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+  const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+  ];
+  let (y, mo, d, h, mi, s) = civil(time);
+  let month = MONTHS[mo as usize - 1];
+  format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000", d, month, y, h, mi, s)
+}
+
+/// Like `2000-10-10T13:55:36Z`
+fn iso_time(time: SystemTime) -> String {
+  let (y, mo, d, h, mi, s) = civil(time);
+  format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, mo, d, h, mi, s)
+}
+
+/// Where access logs go. Each line is written with a single call, so lines
+/// from concurrent requests never mix.
+pub(crate) struct AccessLog {
+  format: LogFormat,
+  out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+  /// Log to `file`, appending, or stdout if there isn't one.
+  pub fn open(format: LogFormat, file: Option<&Path>) -> io::Result<AccessLog> {
+    let out: Box<dyn Write + Send> = match file {
+      Some(path) => {
+        Box::new(OpenOptions::new().append(true).create(true).open(path)?)
+      }
+      None => Box::new(io::stdout()),
+    };
+    Ok(AccessLog {
+      format,
+      out: Mutex::new(out),
+    })
+  }
+
+  pub fn write(&self, record: &Record) {
+    let line = record.format(self.format, record.begin.elapsed());
+    let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+    // losing a log line isn't worth failing a request over
+    let _ = out.write_all(line.as_bytes()).and_then(|_| out.flush());
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn record() -> Record {
+    let request = Request {
+      method: "GET".into(),
+      path: "a b/c.html".into(),
+      query: "x=1".into(),
+      headers: vec![
+        ("User-Agent".into(), "curl/8 \"quoted\"".into()),
+        ("Referer".into(), "http://x/".into()),
+      ],
+      peer: Some([127, 0, 0, 1].into()),
+    };
+    let mut record = Record::new(&request, "HTTP/1.1", Instant::now());
+    record.time = UNIX_EPOCH + Duration::from_secs(971185536);
+    record.status = 200;
+    record.bytes = 2326;
+    record
+  }
+
+  #[test]
+  fn formats() {
+    let record = record();
+    let took = Duration::from_micros(123);
+    assert_eq!(
+      record.format(LogFormat::Common, took),
+      "127.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \"GET /a b/c.html?x=1 \
+       HTTP/1.1\" 200 2326\n"
+    );
+    assert_eq!(
+      record.format(LogFormat::Combined, took),
+      "127.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \"GET /a b/c.html?x=1 \
+       HTTP/1.1\" 200 2326 \"http://x/\" \"curl/8 \\\"quoted\\\"\"\n"
+    );
+    assert_eq!(
+      record.format(LogFormat::Json, took),
+      "{\"time\":\"2000-10-10T13:45:36Z\",\"peer\":\"127.0.0.1\",\
+       \"method\":\"GET\",\"path\":\"/a b/c.html\",\"query\":\"x=1\",\
+       \"status\":200,\"bytes\":2326,\"duration_us\":123}\n"
+    );
+  }
+
+  #[test]
+  fn dates() {
+    let at = |secs| iso_time(UNIX_EPOCH + Duration::from_secs(secs));
+    assert_eq!(at(0), "1970-01-01T00:00:00Z");
+    assert_eq!(at(951782400), "2000-02-29T00:00:00Z");
+    assert_eq!(at(1709251199), "2024-02-29T23:59:59Z");
+    assert!("JSON".parse::<LogFormat>().is_ok(), "format case-sensitive");
+    assert!("apache".parse::<LogFormat>().is_err(), "made up a format");
+    for verbosity in [Verbosity::Quiet, Verbosity::Normal, Verbosity::Trace] {
+      let parsed = verbosity.to_string().parse::<Verbosity>();
+      assert_eq!(parsed.ok(), Some(verbosity), "verbosity didn't round-trip");
+    }
+  }
+}
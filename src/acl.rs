@@ -0,0 +1,195 @@
+use std::{
+  fmt,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr},
+  str::FromStr,
+};
+
+#[derive(Debug)]
+pub struct CidrFail(pub String);
+
+impl fmt::Display for CidrFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "'{}' is not an address or CIDR range", self.0)
+  }
+}
+
+/// A range of IP addresses, like `192.168.0.0/16` or `fe80::/10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+  addr: IpAddr,
+  prefix: u8,
+}
+
+impl Cidr {
+  pub fn new(addr: IpAddr, prefix: u8) -> Option<Cidr> {
+    let max = match addr {
+      IpAddr::V4(_) => 32,
+      IpAddr::V6(_) => 128,
+    };
+    if prefix > max {
+      return None;
+    }
+    Some(Cidr { addr, prefix })
+  }
+
+  pub fn contains(&self, addr: IpAddr) -> bool {
+    match (self.addr, normalize(addr)) {
+      (IpAddr::V4(net), IpAddr::V4(addr)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        u32::from(net) & mask == u32::from(addr) & mask
+      }
+      (IpAddr::V6(net), IpAddr::V6(addr)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        u128::from(net) & mask == u128::from(addr) & mask
+      }
+      _ => false,
+    }
+  }
+
+  /// The ranges a development server should talk to if nobody says otherwise:
+  /// loopback, the private ranges, and link-local.
+  pub fn local_ranges() -> Vec<Cidr> {
+    let v4 = |a, b, p| Cidr::new(Ipv4Addr::new(a, b, 0, 0).into(), p);
+    let v6 =
+      |seg, p| Cidr::new(Ipv6Addr::new(seg, 0, 0, 0, 0, 0, 0, 0).into(), p);
+    vec![
+      v4(127, 0, 8),
+      v4(10, 0, 8),
+      v4(172, 16, 12),
+      v4(192, 168, 16),
+      v4(169, 254, 16),
+      Cidr::new(Ipv6Addr::LOCALHOST.into(), 128),
+      v6(0xfc00, 7),
+      v6(0xfe80, 10),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+  }
+}
+
+/// Treat IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), as seen by dual-stack
+/// sockets, as the IPv4 addresses they are.
+fn normalize(addr: IpAddr) -> IpAddr {
+  match addr {
+    IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+      Some(v4) => IpAddr::V4(v4),
+      None => addr,
+    },
+    v4 => v4,
+  }
+}
+
+impl FromStr for Cidr {
+  type Err = CidrFail;
+
+  fn from_str(s: &str) -> Result<Cidr, CidrFail> {
+    let fail = || CidrFail(s.into());
+    let (addr, prefix) = match s.find('/') {
+      Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+      None => (s, None),
+    };
+    let addr = normalize(addr.parse::<IpAddr>().map_err(|_| fail())?);
+    let prefix = match (prefix, addr) {
+      (Some(p), _) => p.parse().map_err(|_| fail())?,
+      (None, IpAddr::V4(_)) => 32,
+      (None, IpAddr::V6(_)) => 128,
+    };
+    Cidr::new(addr, prefix).ok_or_else(fail)
+  }
+}
+
+impl fmt::Display for Cidr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}/{}", self.addr, self.prefix)
+  }
+}
+
+/// Which peers may talk to the server at all.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+  pub allow: Vec<Cidr>,
+  pub deny: Vec<Cidr>,
+}
+
+impl Acl {
+  /// Denials win over allows; an empty allow list allows everyone.
+  pub fn permits(&self, addr: IpAddr) -> bool {
+    if self.deny.iter().any(|c| c.contains(addr)) {
+      return false;
+    }
+    self.allow.is_empty() || self.allow.iter().any(|c| c.contains(addr))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn parse() {
+    assert_eq!(
+      "10.1.2.3".parse::<Cidr>().unwrap().to_string(),
+      "10.1.2.3/32"
+    );
+    assert_eq!(
+      "fe80::/10".parse::<Cidr>().unwrap().to_string(),
+      "fe80::/10"
+    );
+    assert_eq!(
+      "::ffff:10.0.0.0/8".parse::<Cidr>().unwrap().to_string(),
+      "10.0.0.0/8",
+      "mapped address not normalized"
+    );
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err(), "overlong prefix");
+    assert!("10.0.0/8".parse::<Cidr>().is_err(), "bad address");
+    assert!("10.0.0.0/x".parse::<Cidr>().is_err(), "bad prefix");
+  }
+
+  #[test]
+  fn contains() {
+    let net: Cidr = "192.168.0.0/16".parse().unwrap();
+    assert!(net.contains(ip("192.168.44.1")));
+    assert!(
+      net.contains(ip("::ffff:192.168.44.1")),
+      "mapped v4 not matched"
+    );
+    assert!(!net.contains(ip("192.169.0.1")));
+    assert!(!net.contains(ip("::1")));
+    let all: Cidr = "0.0.0.0/0".parse().unwrap();
+    assert!(all.contains(ip("8.8.8.8")), "/0 doesn't match everything");
+    let v6: Cidr = "fc00::/7".parse().unwrap();
+    assert!(v6.contains(ip("fd12:3456::1")));
+    assert!(!v6.contains(ip("2001:db8::1")));
+  }
+
+  #[test]
+  fn local_ranges() {
+    let acl = Acl {
+      allow: Cidr::local_ranges(),
+      deny: vec![],
+    };
+    for local in &["127.0.0.1", "10.9.8.7", "172.20.0.1", "192.168.1.1", "::1"]
+    {
+      assert!(acl.permits(ip(local)), "{} not allowed", local);
+    }
+    for remote in &["8.8.8.8", "172.32.0.1", "2001:db8::1"] {
+      assert!(!acl.permits(ip(remote)), "{} allowed", remote);
+    }
+  }
+
+  #[test]
+  fn deny_wins() {
+    let acl = Acl {
+      allow: vec!["10.0.0.0/8".parse().unwrap()],
+      deny: vec!["10.0.0.13".parse().unwrap()],
+    };
+    assert!(acl.permits(ip("10.0.0.12")));
+    assert!(!acl.permits(ip("10.0.0.13")), "denied address allowed");
+    assert!(Acl::default().permits(ip("8.8.8.8")), "empty acl denies");
+  }
+}
@@ -0,0 +1,588 @@
+//! Serving what's inside zip and tar files as though they were directories,
+//! so `bundle.zip/docs/index.html` works without unpacking anything.
+
+use std::{
+  collections::BTreeMap,
+  io::{self, Read, Seek, SeekFrom},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use crate::{
+  http::Body,
+  inflate::inflate,
+  vfs::{self, DirEntry, Metadata, Vfs},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+  Zip,
+  Tar,
+}
+
+impl Kind {
+  fn of(path: &Path) -> Option<Kind> {
+    let ext = path.extension()?.to_str()?;
+    if ext.eq_ignore_ascii_case("zip") {
+      Some(Kind::Zip)
+    } else if ext.eq_ignore_ascii_case("tar") {
+      Some(Kind::Tar)
+    } else {
+      None
+    }
+  }
+}
+
+fn corrupt(why: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("bad archive: {}", why))
+}
+
+fn not_found() -> io::Error {
+  io::Error::new(io::ErrorKind::NotFound, "not in archive")
+}
+
+trait Source: Read + Seek {}
+impl<T: Read + Seek> Source for T {}
+
+/// How a file's contents are stored in the archive
+#[derive(Debug, Clone, Copy)]
+enum Method {
+  Stored,
+  Deflated,
+  Unsupported,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+  method: Method,
+  /// Where the data starts, or for zips, the entry's local header
+  offset: u64,
+  /// How much space it takes in the archive
+  size: u64,
+  /// How long it is once extracted
+  len: u64,
+  crc: Option<u32>,
+}
+
+/// What's in an archive, by key. Directories are `None`.
+type Entries = BTreeMap<String, Option<Entry>>;
+
+/// An archive, and what's in it
+struct Archive<'a> {
+  vfs: &'a dyn Vfs,
+  path: PathBuf,
+  entries: Arc<Entries>,
+}
+
+fn u16_at(b: &[u8], at: usize) -> u16 {
+  u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+  u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+const CRC_TABLE: [u32; 256] = {
+  let mut table = [0; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut crc = i as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = match crc & 1 {
+        1 => 0xedb88320 ^ (crc >> 1),
+        _ => crc >> 1,
+      };
+      bit += 1;
+    }
+    table[i] = crc;
+    i += 1;
+  }
+  table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+  !data.iter().fold(!0, |crc, &b| {
+    CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+  })
+}
+
+/// Add an entry, unless its name tries to climb out of the archive
+fn insert(entries: &mut Entries, name: &str, entry: Option<Entry>) {
+  if name.split('/').any(|part| part == "..") {
+    return;
+  }
+  let key = vfs::key(Path::new(name));
+  if !key.is_empty() {
+    entries.insert(key, entry);
+  }
+}
+
+fn read_zip(src: &mut dyn Source) -> io::Result<Entries> {
+  // the end of central directory record is last, but may have a comment of
+  // up to 64KiB after it
+  let end = src.seek(SeekFrom::End(0))?;
+  let tail_len = end.min(22 + 0xffff);
+  src.seek(SeekFrom::Start(end - tail_len))?;
+  let mut tail = vec![0; tail_len as usize];
+  src.read_exact(&mut tail)?;
+  let eocd = (0..tail.len().saturating_sub(21))
+    .rev()
+    .find(|&i| tail[i..i + 4] == *b"PK\x05\x06")
+    .ok_or_else(|| corrupt("no central directory"))?;
+  let eocd = &tail[eocd..];
+  let count = u16_at(eocd, 10);
+  let dir_size = u32_at(eocd, 12);
+  let dir_start = u32_at(eocd, 16);
+  if count == 0xffff || dir_start == 0xffffffff {
+    return Err(corrupt("zip64 isn't supported"));
+  }
+  // the size comes from the file, so don't trust it to allocate
+  if dir_start as u64 + dir_size as u64 > end {
+    return Err(corrupt("bad central directory"));
+  }
+  src.seek(SeekFrom::Start(dir_start as u64))?;
+  let mut dir = vec![0; dir_size as usize];
+  src.read_exact(&mut dir)?;
+
+  let mut entries = BTreeMap::new();
+  let mut pos = 0;
+  for _ in 0..count {
+    let header = dir
+      .get(pos..pos + 46)
+      .filter(|h| h[..4] == *b"PK\x01\x02")
+      .ok_or_else(|| corrupt("bad central directory"))?;
+    let name_len = u16_at(header, 28) as usize;
+    let extra_len = u16_at(header, 30) as usize + u16_at(header, 32) as usize;
+    let name = dir
+      .get(pos + 46..pos + 46 + name_len)
+      .ok_or_else(|| corrupt("bad central directory"))?;
+    let name = String::from_utf8_lossy(name);
+    let entry = Entry {
+      method: match u16_at(header, 10) {
+        0 => Method::Stored,
+        8 => Method::Deflated,
+        _ => Method::Unsupported,
+      },
+      offset: u32_at(header, 42) as u64,
+      size: u32_at(header, 20) as u64,
+      len: u32_at(header, 24) as u64,
+      crc: Some(u32_at(header, 16)),
+    };
+    match name.ends_with('/') {
+      true => insert(&mut entries, &name, None),
+      false => insert(&mut entries, &name, Some(entry)),
+    }
+    pos += 46 + name_len + extra_len;
+  }
+  Ok(entries)
+}
+
+/// A NUL-terminated string in a tar header
+fn tar_str(field: &[u8]) -> String {
+  let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+  String::from_utf8_lossy(&field[..end]).into()
+}
+
+/// A path set in a pax extended header, if any
+fn pax_path(records: &[u8]) -> Option<String> {
+  // each record is `<length> <key>=<value>\n`
+  let records = String::from_utf8_lossy(records);
+  let mut rest = &records[..];
+  while let Some((len, _)) = rest.split_once(' ') {
+    let record = rest.get(..len.parse().ok()?)?;
+    rest = &rest[record.len()..];
+    let (_, kv) = record.split_once(' ')?;
+    if let Some(path) = kv.strip_prefix("path=") {
+      return Some(path.trim_end_matches('\n').into());
+    }
+  }
+  None
+}
+
+fn read_tar(src: &mut dyn Source) -> io::Result<Entries> {
+  let mut entries = BTreeMap::new();
+  // set by a previous header, for names too long to fit in their own
+  let mut long_name = None;
+  let mut pos = 0;
+  loop {
+    src.seek(SeekFrom::Start(pos))?;
+    let mut header = [0; 512];
+    match src.read_exact(&mut header) {
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+      other => other?,
+    }
+    if header.iter().all(|&b| b == 0) {
+      break;
+    }
+    let size = tar_str(&header[124..136]);
+    let size = u64::from_str_radix(size.trim(), 8)
+      .map_err(|_| corrupt("bad tar size"))?;
+    let data = pos + 512;
+    pos = data + size.div_ceil(512) * 512;
+    let mut name = tar_str(&header[..100]);
+    // only POSIX ustar has a prefix field; GNU uses it for other things
+    if header[257..263] == *b"ustar\0" && header[345] != 0 {
+      name = format!("{}/{}", tar_str(&header[345..500]), name);
+    }
+    let name = long_name.take().unwrap_or(name);
+    match header[156] {
+      // GNU long name, or pax extended header, for the next entry
+      b'L' | b'x' => {
+        let mut extra = vec![];
+        src.seek(SeekFrom::Start(data))?;
+        src.take(size).read_to_end(&mut extra)?;
+        long_name = match header[156] {
+          b'L' => Some(tar_str(&extra)),
+          _ => pax_path(&extra),
+        };
+      }
+      b'0' | b'\0' | b'7' => {
+        let entry = Entry {
+          method: Method::Stored,
+          offset: data,
+          size,
+          len: size,
+          crc: None,
+        };
+        insert(&mut entries, &name, Some(entry));
+      }
+      b'5' => insert(&mut entries, &name, None),
+      // links, devices, etc. aren't worth supporting
+      _ => (),
+    }
+  }
+  Ok(entries)
+}
+
+/// Open the archive at `path` to read from
+fn open(vfs: &dyn Vfs, path: &Path) -> io::Result<Box<dyn Source>> {
+  Ok(match vfs.open(path)? {
+    Body::File(file) => Box::new(file),
+    Body::Bytes(bytes) => Box::new(bytes),
+    Body::Stream(mut stream) => {
+      let mut bytes = vec![];
+      stream.read_to_end(&mut bytes)?;
+      Box::new(io::Cursor::new(bytes))
+    }
+  })
+}
+
+impl Archive<'_> {
+  fn metadata(&self, key: &str) -> io::Result<Metadata> {
+    match self.entries.get(key) {
+      Some(Some(entry)) => Ok(Metadata {
+        is_dir: false,
+        len: entry.len,
+      }),
+      Some(None) => Ok(Metadata {
+        is_dir: true,
+        len: 0,
+      }),
+      None => match vfs::has_dir(self.entries.keys().map(|k| &k[..]), key) {
+        true => Ok(Metadata {
+          is_dir: true,
+          len: 0,
+        }),
+        false => Err(not_found()),
+      },
+    }
+  }
+
+  fn read_dir(&self, key: &str) -> io::Result<Vec<DirEntry>> {
+    if !self.metadata(key)?.is_dir {
+      return Err(io::Error::other("not a directory"));
+    }
+    let keys = self.entries.iter().map(|(k, e)| (&k[..], e.is_none()));
+    Ok(vfs::list(keys, key))
+  }
+
+  fn extract(&self, key: &str) -> io::Result<Vec<u8>> {
+    let entry = match self.entries.get(key) {
+      Some(Some(entry)) => *entry,
+      _ => return Err(not_found()),
+    };
+    let mut src = open(self.vfs, &self.path)?;
+    let mut offset = entry.offset;
+    if entry.crc.is_some() {
+      // zips have a local header before the data, repeating the name
+      let mut local = [0; 30];
+      src.seek(SeekFrom::Start(offset))?;
+      src.read_exact(&mut local)?;
+      if local[..4] != *b"PK\x03\x04" {
+        return Err(corrupt("bad local header"));
+      }
+      offset += 30 + u16_at(&local, 26) as u64 + u16_at(&local, 28) as u64;
+    }
+    // the sizes in the header can't be trusted to size buffers with, but
+    // the archive can't hold more than what's left of it
+    let end = src.seek(SeekFrom::End(0))?;
+    let size = entry.size.min(end.saturating_sub(offset));
+    src.seek(SeekFrom::Start(offset))?;
+    let mut raw = Vec::with_capacity(size as usize);
+    src.take(size).read_to_end(&mut raw)?;
+    if raw.len() as u64 != entry.size {
+      return Err(corrupt("truncated"));
+    }
+    let data = match entry.method {
+      Method::Stored => raw,
+      Method::Deflated => inflate(&raw, entry.len as usize)?,
+      Method::Unsupported => {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          "only stored and deflated zip entries are supported",
+        ))
+      }
+    };
+    if data.len() as u64 != entry.len {
+      return Err(corrupt("wrong length"));
+    }
+    if entry.crc.is_some_and(|crc| crc != crc32(&data)) {
+      return Err(corrupt("wrong checksum"));
+    }
+    Ok(data)
+  }
+}
+
+/// Another `Vfs`, but with archives in it treated as directories. An archive
+/// itself is still a file, so it can be downloaded, but paths inside it work.
+/// Each archive's contents are only read once, so this is made anew for each
+/// request, to see archives that have changed since.
+#[derive(Debug)]
+pub(crate) struct Archives<'a> {
+  vfs: &'a dyn Vfs,
+  read: Mutex<BTreeMap<PathBuf, Arc<Entries>>>,
+}
+
+impl<'a> Archives<'a> {
+  pub fn new(vfs: &'a dyn Vfs) -> Archives<'a> {
+    Archives {
+      vfs,
+      read: Mutex::default(),
+    }
+  }
+
+  /// The archive at `path`, reading what's in it if that hasn't been yet
+  fn archive(&self, path: PathBuf, kind: Kind) -> io::Result<Archive<'a>> {
+    let mut read = self.read.lock().unwrap_or_else(|e| e.into_inner());
+    let entries = match read.get(&path) {
+      Some(entries) => entries.clone(),
+      None => {
+        let mut src = open(self.vfs, &path)?;
+        let entries = Arc::new(match kind {
+          Kind::Zip => read_zip(&mut src)?,
+          Kind::Tar => read_tar(&mut src)?,
+        });
+        read.insert(path.clone(), entries.clone());
+        entries
+      }
+    };
+    Ok(Archive {
+      vfs: self.vfs,
+      path,
+      entries,
+    })
+  }
+
+  /// Split a path at the archive it goes into, if any, returning the
+  /// archive's path and the key of the rest inside it.
+  fn split(&self, path: &Path) -> Option<(PathBuf, Kind, String)> {
+    let mut outer = PathBuf::new();
+    let mut parts = path.components();
+    while let Some(part) = parts.next() {
+      outer.push(part);
+      if let Some(kind) = Kind::of(&outer) {
+        if self.vfs.metadata(&outer).is_ok_and(|m| !m.is_dir) {
+          return Some((outer, kind, vfs::key(parts.as_path())));
+        }
+      }
+    }
+    None
+  }
+
+  /// Whether the path is an archive or something in one
+  pub fn in_archive(&self, path: &Path) -> bool {
+    self.split(path).is_some()
+  }
+}
+
+impl Vfs for Archives<'_> {
+  fn open(&self, path: &Path) -> io::Result<Body> {
+    match self.split(path) {
+      Some((outer, kind, key)) if !key.is_empty() => {
+        let data = self.archive(outer, kind)?.extract(&key)?;
+        Ok(Body::Bytes(io::Cursor::new(data)))
+      }
+      _ => self.vfs.open(path),
+    }
+  }
+
+  fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+    self.vfs.metadata(path).or_else(|e| match self.split(path) {
+      Some((outer, kind, key)) => self.archive(outer, kind)?.metadata(&key),
+      None => Err(e),
+    })
+  }
+
+  fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+    match self.split(path) {
+      Some((outer, kind, key)) => self.archive(outer, kind)?.read_dir(&key),
+      None => self.vfs.read_dir(path),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::Disk;
+
+  fn names(entries: Vec<DirEntry>) -> Vec<String> {
+    entries
+      .into_iter()
+      .map(|e| e.name + if e.is_dir { "/" } else { "" })
+      .collect()
+  }
+
+  /// A zip of one file, saying it's whatever size and length it's told to
+  fn zip(
+    name: &str,
+    deflated: bool,
+    data: &[u8],
+    len: u32,
+    crc: u32,
+  ) -> Vec<u8> {
+    let method: u16 = if deflated { 8 } else { 0 };
+    let size = data.len() as u32;
+    let mut sizes = crc.to_le_bytes().to_vec();
+    sizes.extend(size.to_le_bytes());
+    sizes.extend(len.to_le_bytes());
+    let name_len = (name.len() as u16).to_le_bytes();
+
+    let mut out = b"PK\x03\x04\x14\0\0\0".to_vec();
+    out.extend(method.to_le_bytes());
+    out.extend([0; 4]);
+    out.extend(&sizes);
+    out.extend(name_len);
+    out.extend([0; 2]);
+    out.extend(name.as_bytes());
+    out.extend(data);
+
+    let dir_start = out.len() as u32;
+    out.extend(b"PK\x01\x02\x14\0\x14\0\0\0");
+    out.extend(method.to_le_bytes());
+    out.extend([0; 4]);
+    out.extend(&sizes);
+    out.extend(name_len);
+    out.extend([0; 12]);
+    out.extend(0u32.to_le_bytes());
+    out.extend(name.as_bytes());
+    let dir_size = out.len() as u32 - dir_start;
+
+    out.extend(b"PK\x05\x06\0\0\0\0\x01\0\x01\0");
+    out.extend(dir_size.to_le_bytes());
+    out.extend(dir_start.to_le_bytes());
+    out.extend([0; 2]);
+    out
+  }
+
+  #[test]
+  fn lying_headers() {
+    // 100,000 zeros in 114 bytes
+    let mut bomb = vec![
+      237, 193, 49, 1, 0, 0, 0, 194, 160, 245, 79, 109, 13, 15, 160,
+    ];
+    bomb.resize(111, 0);
+    bomb.extend([128, 87, 3]);
+    let zeros = crc32(&[0; 100_000]);
+    let mut fs = crate::MemoryFs::new();
+    fs.insert("honest.zip", zip("z", true, &bomb, 100_000, zeros));
+    fs.insert("bomb.zip", zip("z", true, &bomb, 1000, zeros));
+    let mut huge = zip("z", false, b"hello", 5, crc32(b"hello"));
+    // the size in the central directory, claiming almost 4GiB
+    let at = huge.len() - 22 - 47 + 20;
+    huge[at..at + 4].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+    fs.insert("huge.zip", huge);
+    let mut sprawling = zip("z", false, b"hello", 5, crc32(b"hello"));
+    // the size of the central directory itself
+    let at = sprawling.len() - 22 + 12;
+    sprawling[at..at + 4].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+    fs.insert("sprawling.zip", sprawling);
+    let fs = Archives::new(&fs);
+
+    let mut out = vec![];
+    let mut honest =
+      fs.open(Path::new("honest.zip/z")).expect("failed to open");
+    honest.read_to_end(&mut out).unwrap();
+    assert_eq!(out.len(), 100_000, "wrong length");
+    assert!(fs.open(Path::new("bomb.zip/z")).is_err(), "bomb went off");
+    assert!(fs.open(Path::new("huge.zip/z")).is_err(), "size believed");
+    let listing = fs.read_dir(Path::new("sprawling.zip"));
+    assert!(listing.is_err(), "directory size believed");
+  }
+
+  /// Counts how many times files are opened
+  #[derive(Debug, Default)]
+  struct Counting(std::sync::atomic::AtomicUsize);
+
+  impl Vfs for Counting {
+    fn open(&self, path: &Path) -> io::Result<Body> {
+      self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+      Disk.open(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+      Disk.metadata(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+      Disk.read_dir(path)
+    }
+  }
+
+  #[test]
+  fn read_once() {
+    let counting = Counting::default();
+    let fs = Archives::new(&counting);
+    let root = Path::new("tests/webroot/bundle.zip");
+    for entry in fs.read_dir(root).unwrap() {
+      fs.metadata(&root.join(&entry.name)).unwrap();
+    }
+    fs.open(&root.join("style.css")).unwrap();
+    // once for the listing, once for the contents
+    assert_eq!(counting.0.into_inner(), 2, "archive read more than once");
+  }
+
+  #[test]
+  fn crc() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+  }
+
+  #[test]
+  fn zip_and_tar() {
+    let fs = Archives::new(&Disk);
+    for archive in ["bundle.zip", "bundle.tar"] {
+      let root = Path::new("tests/webroot").join(archive);
+      assert!(!fs.metadata(&root).unwrap().is_dir, "archive isn't a file");
+      assert!(fs.in_archive(&root), "{} not recognized", archive);
+      assert_eq!(
+        names(fs.read_dir(&root).unwrap()),
+        vec!["docs/", "index.html", "style.css"],
+        "wrong listing of {}",
+        archive,
+      );
+      assert_eq!(
+        names(fs.read_dir(&root.join("docs")).unwrap()),
+        vec!["readme.txt"]
+      );
+      let readme = root.join("docs/readme.txt");
+      let mut text = String::new();
+      fs.open(&readme).unwrap().read_to_string(&mut text).unwrap();
+      assert!(text.starts_with("line 0\nline 1\n"), "wrong contents");
+      assert_eq!(fs.metadata(&readme).unwrap().len, text.len() as u64);
+      assert!(fs.metadata(&root.join("nope")).is_err(), "found a ghost");
+    }
+    assert!(!fs.in_archive(Path::new("tests/webroot/file")));
+  }
+}
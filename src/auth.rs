@@ -0,0 +1,348 @@
+use std::{fmt, fs, io, path::Path};
+
+/// A set of users allowed in via HTTP Basic authentication.
+///
+/// Passwords are only ever stored as SHA-256 digests, and every check hashes
+/// the offered password and compares digests in constant time, so plaintext
+/// and `htpasswd`-style entries behave identically.
+#[derive(Clone)]
+pub struct Auth {
+  pub realm: String,
+  users: Vec<(String, [u8; 32])>,
+}
+
+impl fmt::Debug for Auth {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Auth")
+      .field("realm", &self.realm)
+      .field(
+        "users",
+        &self.users.iter().map(|u| &u.0).collect::<Vec<_>>(),
+      )
+      .finish()
+  }
+}
+
+impl Default for Auth {
+  fn default() -> Self {
+    Self::new("httpserv")
+  }
+}
+
+impl Auth {
+  pub fn new(realm: &str) -> Auth {
+    Auth {
+      realm: realm.into(),
+      users: vec![],
+    }
+  }
+
+  /// Allow a user in with the given plaintext password.
+  pub fn add_user(&mut self, user: &str, pass: &str) {
+    self.users.push((user.into(), sha256(pass.as_bytes())));
+  }
+
+  /// Add a `user:pass` pair, as given on the command line.
+  pub fn add_pair(&mut self, pair: &str) -> Option<()> {
+    let colon = pair.find(':')?;
+    let (user, pass) = (&pair[..colon], &pair[colon + 1..]);
+    if user.is_empty() {
+      return None;
+    }
+    self.add_user(user, pass);
+    Some(())
+  }
+
+  /// Load users from an `htpasswd`-style file. Each non-empty line which
+  /// doesn't start with `#` must be `user:{SHA256}digest`, where the digest is
+  /// either hex (as from `sha256sum`) or base64.
+  pub fn load_file(&mut self, path: &Path) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    for (idx, line) in contents.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let bad_line = || {
+        io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!(
+            "{}:{}: expected user:{{SHA256}}digest",
+            path.display(),
+            idx + 1
+          ),
+        )
+      };
+      let colon = line.find(':').ok_or_else(bad_line)?;
+      let (user, hash) = (&line[..colon], &line[colon + 1..]);
+      let hash = hash.strip_prefix("{SHA256}").ok_or_else(bad_line)?;
+      let digest = match hash.len() {
+        64 => hex_decode(hash),
+        _ => base64_decode(hash),
+      };
+      match digest {
+        Some(d) if d.len() == 32 && !user.is_empty() => {
+          let mut hash = [0; 32];
+          hash.copy_from_slice(&d);
+          self.users.push((user.into(), hash));
+        }
+        _ => return Err(bad_line()),
+      }
+    }
+    Ok(())
+  }
+
+  /// Check the value of an `Authorization` header against the known users.
+  pub fn check(&self, header: Option<&str>) -> bool {
+    let creds = match header.and_then(parse_basic) {
+      Some(c) => c,
+      None => return false,
+    };
+    let colon = match creds.iter().position(|&b| b == b':') {
+      Some(c) => c,
+      None => return false,
+    };
+    let (user, pass) = (&creds[..colon], &creds[colon + 1..]);
+    let offered = sha256(pass);
+    // check every user, so the time taken doesn't depend on which one matched
+    let mut found = false;
+    for (name, hash) in &self.users {
+      let matched = ct_eq(name.as_bytes(), user) & ct_eq(hash, &offered);
+      found |= matched;
+    }
+    found
+  }
+}
+
+/// Extract the decoded `user:pass` from a `Basic` authorization header.
+fn parse_basic(header: &str) -> Option<Vec<u8>> {
+  let header = header.trim();
+  let space = header.find(' ')?;
+  let (scheme, encoded) = header.split_at(space);
+  if !scheme.eq_ignore_ascii_case("basic") {
+    return None;
+  }
+  base64_decode(encoded.trim())
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch.
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn hex_decode(inp: &str) -> Option<Vec<u8>> {
+  if !inp.len().is_multiple_of(2) || !inp.is_ascii() {
+    return None;
+  }
+  (0..inp.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&inp[i..i + 2], 16).ok())
+    .collect()
+}
+
+/// Decode standard (RFC 4648) base64, with or without padding.
+pub(crate) fn base64_decode(inp: &str) -> Option<Vec<u8>> {
+  fn value(c: u8) -> Option<u32> {
+    Some(match c {
+      b'A'..=b'Z' => c - b'A',
+      b'a'..=b'z' => c - b'a' + 26,
+      b'0'..=b'9' => c - b'0' + 52,
+      b'+' => 62,
+      b'/' => 63,
+      _ => return None,
+    } as u32)
+  }
+
+  let inp = inp.trim_end_matches('=').as_bytes();
+  if inp.len() % 4 == 1 {
+    return None;
+  }
+  let mut out = Vec::with_capacity(inp.len() * 3 / 4);
+  for chunk in inp.chunks(4) {
+    let mut acc = 0;
+    for (i, &c) in chunk.iter().enumerate() {
+      acc |= value(c)? << (18 - 6 * i);
+    }
+    let bytes = acc.to_be_bytes();
+    out.extend_from_slice(&bytes[1..chunk.len()]);
+  }
+  Some(out)
+}
+
+/// A plain, unoptimized SHA-256, per FIPS 180-4.
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+  const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+  ];
+  let mut h: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+    0x1f83d9ab, 0x5be0cd19,
+  ];
+
+  let mut msg = data.to_vec();
+  msg.push(0x80);
+  while msg.len() % 64 != 56 {
+    msg.push(0);
+  }
+  msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+  for block in msg.chunks(64) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7)
+        ^ w[i - 15].rotate_right(18)
+        ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17)
+        ^ w[i - 2].rotate_right(19)
+        ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16]
+        .wrapping_add(s0)
+        .wrapping_add(w[i - 7])
+        .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let ch = (e & f) ^ (!e & g);
+      let t1 = hh
+        .wrapping_add(s1)
+        .wrapping_add(ch)
+        .wrapping_add(K[i])
+        .wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let maj = (a & b) ^ (a & c) ^ (b & c);
+      let t2 = s0.wrapping_add(maj);
+      hh = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(t1);
+      d = c;
+      c = b;
+      b = a;
+      a = t1.wrapping_add(t2);
+    }
+    for (hv, v) in h.iter_mut().zip(&[a, b, c, d, e, f, g, hh]) {
+      *hv = hv.wrapping_add(*v);
+    }
+  }
+
+  let mut out = [0; 32];
+  for (chunk, v) in out.chunks_mut(4).zip(&h) {
+    chunk.copy_from_slice(&v.to_be_bytes());
+  }
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  #[test]
+  fn sha256_vectors() {
+    assert_eq!(
+      hex(&sha256(b"")),
+      "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+      hex(&sha256(b"abc")),
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+      hex(&sha256(
+        b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+      )),
+      "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+  }
+
+  #[test]
+  fn base64() {
+    assert_eq!(base64_decode(""), Some(vec![]));
+    assert_eq!(base64_decode("Zg=="), Some(b"f".to_vec()));
+    assert_eq!(base64_decode("Zm8="), Some(b"fo".to_vec()));
+    assert_eq!(base64_decode("Zm9v"), Some(b"foo".to_vec()));
+    assert_eq!(base64_decode("Zm9vYg"), Some(b"foob".to_vec()));
+    assert_eq!(base64_decode("dXNlcjpwYXNz"), Some(b"user:pass".to_vec()));
+    assert_eq!(base64_decode("Zm9v!"), None, "invalid character accepted");
+    assert_eq!(base64_decode("Zm9vY"), None, "truncated input accepted");
+  }
+
+  #[test]
+  fn check_pair() {
+    let mut auth = Auth::default();
+    auth.add_pair("user:pass").expect("valid pair rejected");
+    assert!(
+      auth.check(Some("Basic dXNlcjpwYXNz")),
+      "right creds rejected"
+    );
+    assert!(
+      auth.check(Some("basic  dXNlcjpwYXNz ")),
+      "scheme is case-sensitive"
+    );
+    assert!(
+      !auth.check(Some("Basic dXNlcjpwYXNT")),
+      "wrong pass accepted"
+    );
+    assert!(
+      !auth.check(Some("Bearer dXNlcjpwYXNz")),
+      "wrong scheme accepted"
+    );
+    assert!(!auth.check(None), "missing header accepted");
+    assert!(Auth::default().add_pair("nocolon").is_none());
+  }
+
+  #[test]
+  fn check_file() {
+    let path = std::env::temp_dir().join("httpserv-auth-test.htpasswd");
+    fs::write(
+      &path,
+      concat!(
+        "# comment\n",
+        "\n",
+        // sha256("pass")
+        "user:{SHA256}d74ff0ee8da3b9806b18c877dbf29bbde50b5bd8e4dad7a3a725000feb82e8f1\n",
+        "other:{SHA256}10/w7o2juYBrGMh32/KbveULW9jk2tejpyUAD+uC6PE=\n",
+      ),
+    )
+    .unwrap();
+    let mut auth = Auth::default();
+    auth.load_file(&path).expect("failed to load valid file");
+    assert!(
+      auth.check(Some("Basic dXNlcjpwYXNz")),
+      "hex digest not loaded"
+    );
+    // other:pass
+    assert!(
+      auth.check(Some("Basic b3RoZXI6cGFzcw==")),
+      "base64 digest not loaded"
+    );
+
+    fs::write(&path, "user:pass\n").unwrap();
+    assert!(
+      Auth::default().load_file(&path).is_err(),
+      "plaintext accepted"
+    );
+    let _ = fs::remove_file(&path);
+  }
+}
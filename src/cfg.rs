@@ -1,9 +1,252 @@
-use std::{collections::HashMap, ffi::OsString, path::PathBuf};
+use std::{
+  collections::HashMap,
+  ffi::OsString,
+  fmt,
+  path::{Path, PathBuf},
+  str::FromStr,
+  sync::Arc,
+  time::Duration,
+};
+
+use crate::{Auth, Cidr, Disk, LogFormat, Verbosity, Vfs};
+
+/// A directory served under a URL prefix, besides the root.
+#[derive(Debug, Clone)]
+pub struct Mount {
+  /// Without leading or trailing slashes, e.g. `static` or `docs/api`
+  pub prefix: String,
+  pub dir: PathBuf,
+  /// MIME types for files in this mount, overriding `Config::mappings`
+  pub mappings: HashMap<OsString, String>,
+  /// Where `dir` is, if not on disk
+  pub vfs: Option<Arc<dyn Vfs>>,
+}
+
+impl PartialEq for Mount {
+  fn eq(&self, other: &Mount) -> bool {
+    let same_vfs = match (&self.vfs, &other.vfs) {
+      (None, None) => true,
+      (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+      _ => false,
+    };
+    self.prefix == other.prefix
+      && self.dir == other.dir
+      && self.mappings == other.mappings
+      && same_vfs
+  }
+}
+
+impl Eq for Mount {}
+
+impl Mount {
+  pub fn new(prefix: &str, dir: impl Into<PathBuf>) -> Mount {
+    Mount {
+      prefix: prefix.trim_matches('/').into(),
+      dir: dir.into(),
+      mappings: HashMap::new(),
+      vfs: None,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct MountFail(pub String);
+
+impl fmt::Display for MountFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "'{}' isn't a valid prefix=directory mount", self.0)
+  }
+}
+
+impl FromStr for Mount {
+  type Err = MountFail;
+
+  /// Parse `prefix=directory`, like `/static=../assets`
+  fn from_str(s: &str) -> Result<Mount, MountFail> {
+    match s.split_once('=') {
+      Some((prefix, dir)) if !dir.is_empty() => Ok(Mount::new(prefix, dir)),
+      _ => Err(MountFail(s.into())),
+    }
+  }
+}
 
 #[derive(Debug)]
 pub struct Config {
   pub root: PathBuf,
-  pub hostname: String,
+  /// Where `root` is, if not on disk, e.g. a `MemoryFs` of embedded assets
+  pub vfs: Option<Arc<dyn Vfs>>,
+  /// Other directories to serve under their own prefixes. The longest prefix
+  /// matching a request wins, falling back to `root`.
+  pub mounts: Vec<Mount>,
+  /// Addresses to listen on. Each is bound on every address it resolves to,
+  /// so `localhost` covers both `127.0.0.1` and `::1`. `unix:/path/to.sock`
+  /// listens on a Unix domain socket instead.
+  pub hostnames: Vec<String>,
+  /// If a port is taken, try the next one up instead of failing. Port 0 always
+  /// gets whichever free port the OS picks.
+  pub port_fallback: bool,
   pub mappings: HashMap<OsString, String>,
-  pub log: bool,
+  /// The charset of text files which don't say what theirs is with a byte
+  /// order mark or `<meta charset>`. If unset, it's UTF-8 if they're valid
+  /// UTF-8, or Windows-1252 if not.
+  pub charset: Option<String>,
+  /// Like `charset`, but for files ending in each extension
+  pub charsets: HashMap<OsString, String>,
+  /// Pick between versions of a file, like `hero.avif` and `hero.png`, by
+  /// what the client accepts, even when it asks for one by name. Requests
+  /// without an extension, like `hero`, are always negotiated.
+  pub negotiate: bool,
+  /// The language to serve, out of versions like `index.en.html` and
+  /// `index.de.html`, when the client doesn't accept any of them. Versions
+  /// without a language, like `index.html`, still come first.
+  pub default_language: Option<String>,
+  /// How much to log, from nothing up to every detail of every request
+  pub verbosity: Verbosity,
+  pub log_format: LogFormat,
+  /// Append the access log here instead of printing it
+  pub log_file: Option<PathBuf>,
+  /// Count what's served, and report it at `/__httpserv/metrics` in
+  /// Prometheus's text format, or JSON with `?format=json`. A real file at
+  /// that path is still served instead.
+  pub metrics: bool,
+  /// If set, every request must carry valid Basic credentials
+  pub auth: Option<Auth>,
+  /// Peers allowed to connect. If empty, everyone may when listening on a
+  /// specific address, but only local ranges may on a wildcard one.
+  pub allow: Vec<Cidr>,
+  /// Peers never allowed to connect, even if they're also in `allow`
+  pub deny: Vec<Cidr>,
+  /// How long requests in progress get to finish once shutdown is requested
+  pub shutdown_grace: Duration,
+  /// If set, serve HTTPS instead of HTTP
+  #[cfg(feature = "tls")]
+  pub tls: Option<crate::Tls>,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      root: ".".into(),
+      vfs: None,
+      mounts: vec![],
+      hostnames: vec!["localhost:8080".into()],
+      port_fallback: false,
+      mappings: HashMap::new(),
+      charset: None,
+      charsets: HashMap::new(),
+      negotiate: false,
+      default_language: None,
+      verbosity: Verbosity::Quiet,
+      log_format: LogFormat::default(),
+      log_file: None,
+      metrics: false,
+      auth: None,
+      allow: vec![],
+      deny: vec![],
+      shutdown_grace: Duration::from_secs(10),
+      #[cfg(feature = "tls")]
+      tls: None,
+    }
+  }
+}
+
+/// Where a request path points, according to the mounts.
+pub(crate) struct Target<'a> {
+  pub dir: &'a Path,
+  /// The rest of the path, within `dir`
+  pub rest: &'a str,
+  /// The mount's MIME overrides, if any
+  pub mappings: Option<&'a HashMap<OsString, String>>,
+  pub vfs: &'a dyn Vfs,
+}
+
+impl Config {
+  /// Find which directory a request path is for.
+  pub(crate) fn mount_for<'a>(&'a self, path: &'a str) -> Target<'a> {
+    let found = self
+      .mounts
+      .iter()
+      .filter_map(|m| {
+        let rest = path.strip_prefix(&m.prefix[..])?;
+        // only match whole segments, so `static` doesn't catch `staticky`
+        match rest.strip_prefix('/') {
+          Some(rest) => Some((m, rest)),
+          None if rest.is_empty() || m.prefix.is_empty() => Some((m, rest)),
+          None => None,
+        }
+      })
+      .max_by_key(|(m, _)| m.prefix.len());
+    match found {
+      Some((m, rest)) => Target {
+        dir: &m.dir,
+        rest,
+        mappings: Some(&m.mappings),
+        vfs: m.vfs.as_deref().unwrap_or(&Disk),
+      },
+      None => Target {
+        dir: &self.root,
+        rest: path,
+        mappings: None,
+        vfs: self.vfs.as_deref().unwrap_or(&Disk),
+      },
+    }
+  }
+}
+
+/// `hostname` with its port replaced by `port`, or given `port` (or 8080, if
+/// that's `None`) if it didn't have one. Bare IPv6 addresses, like `::1`, are
+/// put in brackets.
+pub fn with_port(hostname: &str, port: Option<u16>) -> String {
+  let (host, old) = match hostname.rsplit_once(':') {
+    Some((host, old)) if hostname.starts_with('[') && host.ends_with(']') => {
+      (host.to_string(), Some(old))
+    }
+    _ if hostname.starts_with('[') => (hostname.into(), None),
+    Some((host, _)) if host.contains(':') => (format!("[{}]", hostname), None),
+    Some((host, old)) => (host.into(), Some(old)),
+    None => (hostname.into(), None),
+  };
+  match (port, old) {
+    (Some(port), _) => format!("{}:{}", host, port),
+    (None, Some(old)) => format!("{}:{}", host, old),
+    (None, None) => format!("{}:8080", host),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn longest_prefix() {
+    let cfg = Config {
+      root: "root".into(),
+      mounts: vec![
+        "/static=assets".parse().unwrap(),
+        "/static/img/=images".parse().unwrap(),
+      ],
+      ..Default::default()
+    };
+    let find = |p| {
+      let target = cfg.mount_for(p);
+      (target.dir.to_str().unwrap(), target.rest)
+    };
+    assert_eq!(find("index.html"), ("root", "index.html"));
+    assert_eq!(find("static"), ("assets", ""));
+    assert_eq!(find("static/a.css"), ("assets", "a.css"));
+    assert_eq!(find("static/img/b.png"), ("images", "b.png"));
+    assert_eq!(find("static/imgs/c"), ("assets", "imgs/c"));
+    assert_eq!(find("staticky"), ("root", "staticky"));
+    assert!("nodir=".parse::<Mount>().is_err(), "empty dir accepted");
+  }
+
+  #[test]
+  fn ports() {
+    assert_eq!(with_port("localhost", None), "localhost:8080");
+    assert_eq!(with_port("localhost:1", Some(2)), "localhost:2");
+    assert_eq!(with_port("[::1]:1", None), "[::1]:1");
+    assert_eq!(with_port("[::1]", Some(2)), "[::1]:2");
+    assert_eq!(with_port("::1", Some(2)), "[::1]:2");
+    assert_eq!(with_port("fe80::1", None), "[fe80::1]:8080");
+  }
 }
@@ -0,0 +1,121 @@
+use std::{
+  fmt,
+  io::{self, Read, Write},
+  net::{self, IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr},
+  time::Duration,
+};
+
+/// A connection, however it arrived. Requests are read from it and responses
+/// written to it without caring what's underneath.
+pub trait Transport: Read + Write + Send {
+  /// The address it came from, if it came over IP
+  fn peer(&self) -> Option<IpAddr>;
+  /// Another handle to the same connection, e.g. for shutting it down from
+  /// another thread
+  fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+  /// Close both directions, interrupting anything blocked on it
+  fn shutdown(&self) -> io::Result<()>;
+}
+
+/// Somewhere connections come from. Besides the addresses in
+/// `Config::hostnames`, a server can listen on any of these given to
+/// `Httpserv::with_listeners`.
+pub trait Listener: fmt::Debug + Send + Sync {
+  fn accept(&self) -> io::Result<Box<dyn Transport>>;
+  /// Make a thread blocked in `accept` return, so it notices it's been
+  /// shut down.
+  fn wake(&self);
+}
+
+impl Transport for net::TcpStream {
+  fn peer(&self) -> Option<IpAddr> {
+    self.peer_addr().ok().map(|a| a.ip())
+  }
+
+  fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+    Ok(Box::new(net::TcpStream::try_clone(self)?))
+  }
+
+  fn shutdown(&self) -> io::Result<()> {
+    net::TcpStream::shutdown(self, Shutdown::Both)
+  }
+}
+
+impl Listener for net::TcpListener {
+  fn accept(&self) -> io::Result<Box<dyn Transport>> {
+    let (tcp, _) = net::TcpListener::accept(self)?;
+    Ok(Box::new(tcp))
+  }
+
+  fn wake(&self) {
+    let local = match self.local_addr() {
+      Ok(l) => l,
+      Err(_) => return,
+    };
+    // accepting blocks, so connect to make it return
+    let ip = match local.ip() {
+      IpAddr::V4(ip) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+      IpAddr::V6(ip) if ip.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+      ip => ip,
+    };
+    let addr = SocketAddr::new(ip, local.port());
+    let _ = net::TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+  }
+}
+
+/// An accepted connection, possibly wrapped in TLS.
+pub(crate) enum Conn {
+  Plain(Box<dyn Transport>),
+  #[cfg(feature = "tls")]
+  Tls(Box<rustls::StreamOwned<rustls::ServerConnection, Box<dyn Transport>>>),
+}
+
+impl Conn {
+  pub fn peer(&self) -> Option<IpAddr> {
+    match self {
+      Conn::Plain(s) => s.peer(),
+      #[cfg(feature = "tls")]
+      Conn::Tls(s) => s.sock.peer(),
+    }
+  }
+
+  /// Cleanly end the connection once the response is written.
+  pub fn close(&mut self) -> io::Result<()> {
+    match self {
+      Conn::Plain(s) => s.flush(),
+      #[cfg(feature = "tls")]
+      Conn::Tls(s) => {
+        s.conn.send_close_notify();
+        s.flush()
+      }
+    }
+  }
+}
+
+impl Read for Conn {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Conn::Plain(s) => s.read(buf),
+      #[cfg(feature = "tls")]
+      Conn::Tls(s) => s.read(buf),
+    }
+  }
+}
+
+impl Write for Conn {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      Conn::Plain(s) => s.write(buf),
+      #[cfg(feature = "tls")]
+      Conn::Tls(s) => s.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Conn::Plain(s) => s.flush(),
+      #[cfg(feature = "tls")]
+      Conn::Tls(s) => s.flush(),
+    }
+  }
+}
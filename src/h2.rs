@@ -0,0 +1,646 @@
+//! HTTP/2, per RFC 9113, on top of the same request resolution as HTTP/1.1.
+//!
+//! Everything runs on the connection's thread: after each frame is handled,
+//! every stream with a response pending gets a DATA frame in turn, for as
+//! long as the flow control windows allow. Once nothing more can be sent, we
+//! block on the next frame, which is usually the `WINDOW_UPDATE` that lets us
+//! continue.
+
+use std::{
+  collections::HashMap,
+  fmt,
+  io::{self, BufReader, Read, Write},
+  sync::atomic::{AtomicUsize, Ordering},
+  time::Instant,
+};
+
+use crate::{
+  access::Record,
+  hpack,
+  http::{parse_target, Body, ReqFail, Request, Response},
+};
+
+/// What clients send to start HTTP/2, which is meant to look like an HTTP/1
+/// request for `*` with method `PRI` and then fail to parse.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// The defaults from section 6.5.2, which is also what we advertise
+const DEFAULT_WINDOW: i64 = 65_535;
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+const HEADER_TABLE_SIZE: usize = 4_096;
+const MAX_STREAMS: usize = 100;
+/// Don't let a client make us buffer an endless header block
+const MAX_HEADER_BLOCK: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum H2Fail {
+  Io(io::Error),
+  /// The peer broke the protocol badly enough to end the whole connection
+  Connection(u32, &'static str),
+}
+
+impl fmt::Display for H2Fail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      H2Fail::Io(e) => write!(f, "{}", e),
+      H2Fail::Connection(code, why) => {
+        write!(f, "HTTP/2 connection error {:#x}: {}", code, why)
+      }
+    }
+  }
+}
+
+impl From<io::Error> for H2Fail {
+  fn from(ioe: io::Error) -> Self {
+    Self::Io(ioe)
+  }
+}
+
+type Result<T> = std::result::Result<T, H2Fail>;
+
+struct Frame {
+  kind: u8,
+  flags: u8,
+  stream: u32,
+  payload: Vec<u8>,
+}
+
+/// Read one frame, or `None` if the connection closed cleanly between them.
+fn read_frame(input: &mut impl Read) -> Result<Option<Frame>> {
+  let mut head = [0; 9];
+  match input.read(&mut head[..1])? {
+    0 => return Ok(None),
+    _ => input.read_exact(&mut head[1..])?,
+  }
+  let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+  if len > DEFAULT_FRAME_SIZE {
+    return Err(H2Fail::Connection(FRAME_SIZE_ERROR, "frame too large"));
+  }
+  let stream =
+    u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+  let mut payload = vec![0; len];
+  input.read_exact(&mut payload)?;
+  Ok(Some(Frame {
+    kind: head[3],
+    flags: head[4],
+    stream,
+    payload,
+  }))
+}
+
+/// Remove the padding from a `PADDED` frame's payload.
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8]> {
+  if flags & PADDED == 0 {
+    return Ok(payload);
+  }
+  let (&pad, rest) = payload
+    .split_first()
+    .ok_or(H2Fail::Connection(PROTOCOL_ERROR, "missing pad length"))?;
+  if pad as usize > rest.len() {
+    return Err(H2Fail::Connection(PROTOCOL_ERROR, "padding too long"));
+  }
+  Ok(&rest[..rest.len() - pad as usize])
+}
+
+/// A response being sent back, once its headers are out.
+struct Outgoing {
+  id: u32,
+  window: i64,
+  body: Body,
+  /// Bytes left to send, or `usize::MAX` if that's unknown
+  remaining: usize,
+  record: Record,
+}
+
+/// A request whose headers have arrived, but whose body is still coming.
+struct Incoming {
+  request: Request,
+  begin: Instant,
+}
+
+pub struct Session<'a, S, F, L> {
+  io: &'a mut BufReader<S>,
+  respond: F,
+  log: L,
+  /// Kept up to date with how many streams are open, for graceful shutdown
+  busy: &'a AtomicUsize,
+  decoder: hpack::Decoder,
+  /// Header block being put back together from `CONTINUATION`s
+  partial: Option<(u32, u8, Vec<u8>)>,
+  incoming: HashMap<u32, Incoming>,
+  outgoing: Vec<Outgoing>,
+  last_stream: u32,
+  send_window: i64,
+  initial_window: i64,
+  max_frame: usize,
+  going_away: bool,
+}
+
+impl<'a, S, F, L> Session<'a, S, F, L>
+where
+  S: Read + Write,
+  F: FnMut(Request) -> io::Result<Response>,
+  L: FnMut(Record),
+{
+  /// Start a session on a connection whose client preface has already been
+  /// read. `respond` resolves each request, `log` is told about each response
+  /// once it's completely sent, and `busy` is kept set to the number of
+  /// streams in progress.
+  pub fn new(
+    io: &'a mut BufReader<S>,
+    respond: F,
+    log: L,
+    busy: &'a AtomicUsize,
+  ) -> Self {
+    Session {
+      io,
+      respond,
+      log,
+      busy,
+      decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+      partial: None,
+      incoming: HashMap::new(),
+      outgoing: vec![],
+      last_stream: 0,
+      send_window: DEFAULT_WINDOW,
+      initial_window: DEFAULT_WINDOW,
+      max_frame: DEFAULT_FRAME_SIZE,
+      going_away: false,
+    }
+  }
+
+  /// Serve the connection until the client closes it or says it's going away.
+  ///
+  /// For connections upgraded from HTTP/1.1 via `Upgrade: h2c`, `upgrade` is
+  /// the request that asked for it, which becomes stream 1, and the decoded
+  /// `HTTP2-Settings` it sent.
+  pub fn run(mut self, upgrade: Option<(Request, Vec<u8>)>) -> Result<()> {
+    let mut settings = vec![];
+    for &(id, val) in &[
+      (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32),
+      (SETTINGS_HEADER_TABLE_SIZE, HEADER_TABLE_SIZE as u32),
+    ] {
+      settings.extend_from_slice(&id.to_be_bytes());
+      settings.extend_from_slice(&val.to_be_bytes());
+    }
+    self.send(SETTINGS, 0, 0, &settings)?;
+    if let Some((request, settings)) = upgrade {
+      self.apply_settings(&settings)?;
+      self.last_stream = 1;
+      self.respond_to(1, request, Instant::now())?;
+    }
+
+    let result = self.serve();
+    let code = match &result {
+      Ok(()) => NO_ERROR,
+      Err(H2Fail::Connection(code, _)) => *code,
+      Err(H2Fail::Io(_)) => return result,
+    };
+    // the client might already be gone, so this is best-effort
+    let mut goaway = self.last_stream.to_be_bytes().to_vec();
+    goaway.extend_from_slice(&code.to_be_bytes());
+    let _ = self.send(GOAWAY, 0, 0, &goaway);
+    let _ = self.io.get_mut().flush();
+    result
+  }
+
+  fn serve(&mut self) -> Result<()> {
+    loop {
+      self.update_busy();
+      self.pump()?;
+      if self.going_away && self.outgoing.is_empty() {
+        return Ok(());
+      }
+      self.update_busy();
+      match read_frame(self.io)? {
+        Some(frame) => self.handle(frame)?,
+        None => return Ok(()),
+      }
+    }
+  }
+
+  fn update_busy(&self) {
+    let streams = self.incoming.len() + self.outgoing.len();
+    self.busy.store(streams, Ordering::SeqCst);
+  }
+
+  fn send(
+    &mut self,
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: &[u8],
+  ) -> Result<()> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream.to_be_bytes());
+    frame.extend_from_slice(payload);
+    self.io.get_mut().write_all(&frame)?;
+    Ok(())
+  }
+
+  fn reset(&mut self, stream: u32, code: u32) -> Result<()> {
+    self.incoming.remove(&stream);
+    self.outgoing.retain(|o| o.id != stream);
+    self.send(RST_STREAM, 0, stream, &code.to_be_bytes())
+  }
+
+  fn handle(&mut self, frame: Frame) -> Result<()> {
+    if self.partial.is_some() && frame.kind != CONTINUATION {
+      return Err(H2Fail::Connection(PROTOCOL_ERROR, "expected CONTINUATION"));
+    }
+    match frame.kind {
+      DATA => self.on_data(frame),
+      HEADERS => self.on_headers(frame),
+      CONTINUATION => {
+        let (stream, flags, mut block) = self
+          .partial
+          .take()
+          .ok_or(H2Fail::Connection(PROTOCOL_ERROR, "stray CONTINUATION"))?;
+        if stream != frame.stream {
+          return Err(H2Fail::Connection(
+            PROTOCOL_ERROR,
+            "interleaved headers",
+          ));
+        }
+        block.extend_from_slice(&frame.payload);
+        if block.len() > MAX_HEADER_BLOCK {
+          return Err(H2Fail::Connection(PROTOCOL_ERROR, "headers too large"));
+        }
+        match frame.flags & END_HEADERS {
+          0 => self.partial = Some((stream, flags, block)),
+          _ => self.on_header_block(stream, flags, &block)?,
+        }
+        Ok(())
+      }
+      PRIORITY => Ok(()),
+      RST_STREAM => {
+        self.incoming.remove(&frame.stream);
+        self.outgoing.retain(|o| o.id != frame.stream);
+        Ok(())
+      }
+      SETTINGS => {
+        if frame.stream != 0 {
+          return Err(H2Fail::Connection(PROTOCOL_ERROR, "SETTINGS on stream"));
+        }
+        if frame.flags & ACK == 0 {
+          self.apply_settings(&frame.payload)?;
+          self.send(SETTINGS, ACK, 0, &[])?;
+        }
+        Ok(())
+      }
+      PUSH_PROMISE => {
+        Err(H2Fail::Connection(PROTOCOL_ERROR, "clients can't push"))
+      }
+      PING => {
+        if frame.payload.len() != 8 {
+          return Err(H2Fail::Connection(FRAME_SIZE_ERROR, "bad PING"));
+        }
+        if frame.flags & ACK == 0 {
+          self.send(PING, ACK, 0, &frame.payload)?;
+        }
+        Ok(())
+      }
+      GOAWAY => {
+        self.going_away = true;
+        Ok(())
+      }
+      WINDOW_UPDATE => self.on_window_update(frame),
+      // section 5.5: unknown frame types must be ignored
+      _ => Ok(()),
+    }
+  }
+
+  fn apply_settings(&mut self, payload: &[u8]) -> Result<()> {
+    if !payload.len().is_multiple_of(6) {
+      return Err(H2Fail::Connection(FRAME_SIZE_ERROR, "bad SETTINGS"));
+    }
+    for setting in payload.chunks(6) {
+      let id = u16::from_be_bytes([setting[0], setting[1]]);
+      let val =
+        u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+      match id {
+        SETTINGS_INITIAL_WINDOW_SIZE => {
+          if val > 0x7fff_ffff {
+            return Err(H2Fail::Connection(
+              FLOW_CONTROL_ERROR,
+              "window too big",
+            ));
+          }
+          let delta = val as i64 - self.initial_window;
+          self.initial_window = val as i64;
+          for out in &mut self.outgoing {
+            out.window += delta;
+          }
+        }
+        SETTINGS_MAX_FRAME_SIZE => {
+          if !(DEFAULT_FRAME_SIZE as u32..=0xff_ffff).contains(&val) {
+            return Err(H2Fail::Connection(PROTOCOL_ERROR, "bad frame size"));
+          }
+          self.max_frame = val as usize;
+        }
+        // we never use the dynamic table when encoding, and everything else
+        // is either about push (which we don't do) or advisory
+        _ => (),
+      }
+    }
+    Ok(())
+  }
+
+  fn on_window_update(&mut self, frame: Frame) -> Result<()> {
+    if frame.payload.len() != 4 {
+      return Err(H2Fail::Connection(FRAME_SIZE_ERROR, "bad WINDOW_UPDATE"));
+    }
+    let p = &frame.payload;
+    let inc = u32::from_be_bytes([p[0], p[1], p[2], p[3]]) & 0x7fff_ffff;
+    if frame.stream == 0 {
+      if inc == 0 {
+        return Err(H2Fail::Connection(PROTOCOL_ERROR, "zero window update"));
+      }
+      self.send_window += inc as i64;
+      if self.send_window > 0x7fff_ffff {
+        return Err(H2Fail::Connection(FLOW_CONTROL_ERROR, "window overflow"));
+      }
+      return Ok(());
+    }
+    let stream = frame.stream;
+    let overflow = match self.outgoing.iter_mut().find(|o| o.id == stream) {
+      Some(out) => {
+        out.window += inc as i64;
+        inc == 0 || out.window > 0x7fff_ffff
+      }
+      // updates for streams we've finished with can still be in flight
+      None => false,
+    };
+    if overflow {
+      self.reset(stream, FLOW_CONTROL_ERROR)?;
+    }
+    Ok(())
+  }
+
+  fn on_data(&mut self, frame: Frame) -> Result<()> {
+    if frame.stream == 0 {
+      return Err(H2Fail::Connection(PROTOCOL_ERROR, "DATA on stream 0"));
+    }
+    // we don't use request bodies, but we do have to let the client keep
+    // sending them, so immediately give back all the window they took up
+    let len = frame.payload.len() as u32;
+    if len > 0 {
+      self.send(WINDOW_UPDATE, 0, 0, &len.to_be_bytes())?;
+      if self.incoming.contains_key(&frame.stream)
+        && frame.flags & END_STREAM == 0
+      {
+        self.send(WINDOW_UPDATE, 0, frame.stream, &len.to_be_bytes())?;
+      }
+    }
+    if frame.flags & END_STREAM != 0 {
+      if let Some(inc) = self.incoming.remove(&frame.stream) {
+        self.respond_to(frame.stream, inc.request, inc.begin)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn on_headers(&mut self, frame: Frame) -> Result<()> {
+    if frame.stream == 0 || frame.stream.is_multiple_of(2) {
+      return Err(H2Fail::Connection(PROTOCOL_ERROR, "bad stream ID"));
+    }
+    let mut block = unpad(frame.flags, &frame.payload)?;
+    if frame.flags & PRIORITY_FLAG != 0 {
+      if block.len() < 5 {
+        return Err(H2Fail::Connection(FRAME_SIZE_ERROR, "bad priority"));
+      }
+      block = &block[5..];
+    }
+    match frame.flags & END_HEADERS {
+      0 => self.partial = Some((frame.stream, frame.flags, block.to_vec())),
+      _ => self.on_header_block(frame.stream, frame.flags, block)?,
+    }
+    Ok(())
+  }
+
+  fn on_header_block(
+    &mut self,
+    stream: u32,
+    flags: u8,
+    block: &[u8],
+  ) -> Result<()> {
+    let begin = Instant::now();
+    // decode even if we'll ignore it, to keep the dynamic table in sync
+    let headers = self
+      .decoder
+      .decode(block)
+      .map_err(|_| H2Fail::Connection(COMPRESSION_ERROR, "bad HPACK"))?;
+    if stream <= self.last_stream {
+      // trailers for a request we're still receiving, which have to end it
+      if !self.incoming.contains_key(&stream) {
+        return Err(H2Fail::Connection(PROTOCOL_ERROR, "reused stream ID"));
+      }
+      if flags & END_STREAM == 0 {
+        return self.reset(stream, PROTOCOL_ERROR);
+      }
+      return match self.incoming.remove(&stream) {
+        Some(inc) => self.respond_to(stream, inc.request, inc.begin),
+        None => Ok(()),
+      };
+    }
+    self.last_stream = stream;
+    if self.going_away {
+      return Ok(());
+    }
+    if self.incoming.len() + self.outgoing.len() >= MAX_STREAMS {
+      return self.reset(stream, REFUSED_STREAM);
+    }
+    let request = match to_request(headers) {
+      Ok(r) => r,
+      // just like HTTP/1.1 drops the connection
+      Err(_) => return self.reset(stream, PROTOCOL_ERROR),
+    };
+    match flags & END_STREAM {
+      0 => {
+        self.incoming.insert(stream, Incoming { request, begin });
+        Ok(())
+      }
+      _ => self.respond_to(stream, request, begin),
+    }
+  }
+
+  fn respond_to(
+    &mut self,
+    stream: u32,
+    request: Request,
+    begin: Instant,
+  ) -> Result<()> {
+    let mut record = Record::new(&request, "HTTP/2.0", begin);
+    let response = match (self.respond)(request) {
+      Ok(r) => r,
+      Err(_) => return self.reset(stream, INTERNAL_ERROR),
+    };
+    record.status = response.code();
+    let status = record.status.to_string();
+    let headers = response.headers();
+    let headers: Vec<_> = headers
+      .iter()
+      .map(|(n, v)| (n.to_ascii_lowercase(), v))
+      .collect();
+    let block = hpack::encode(
+      Some((":status", &status[..]))
+        .into_iter()
+        .chain(headers.iter().map(|(n, v)| (&n[..], &v[..]))),
+    );
+    let (len, body) = match response {
+      Response::Ok { body_len, body, .. } => {
+        (body_len.unwrap_or(usize::MAX), Some(body))
+      }
+      _ => (0, None),
+    };
+    let end = match (len, &body) {
+      (0, _) | (_, None) => END_STREAM,
+      _ => 0,
+    };
+
+    let mut chunks = block.chunks(self.max_frame).peekable();
+    let mut kind = HEADERS;
+    while let Some(chunk) = chunks.next() {
+      let mut flags = if kind == HEADERS { end } else { 0 };
+      if chunks.peek().is_none() {
+        flags |= END_HEADERS;
+      }
+      self.send(kind, flags, stream, chunk)?;
+      kind = CONTINUATION;
+    }
+
+    match body {
+      Some(body) if len > 0 => self.outgoing.push(Outgoing {
+        id: stream,
+        window: self.initial_window,
+        body,
+        remaining: len,
+        record,
+      }),
+      _ => (self.log)(record),
+    }
+    Ok(())
+  }
+
+  /// Send as much of the pending responses as flow control allows, taking
+  /// turns between streams.
+  fn pump(&mut self) -> Result<()> {
+    let mut buf = vec![];
+    loop {
+      let mut sent = false;
+      let mut idx = 0;
+      while idx < self.outgoing.len() && self.send_window > 0 {
+        let out = &mut self.outgoing[idx];
+        if out.window <= 0 {
+          idx += 1;
+          continue;
+        }
+        let max = self
+          .max_frame
+          .min(self.send_window as usize)
+          .min(out.window as usize)
+          .min(out.remaining);
+        buf.resize(max, 0);
+        let read = out.body.read(&mut buf)?;
+        // either a stream ended, or the file shrank; both mean the end
+        out.remaining = match read {
+          0 => 0,
+          n => out.remaining - n,
+        };
+        out.window -= read as i64;
+        out.record.bytes += read as u64;
+        self.send_window -= read as i64;
+        let (id, done) = (out.id, out.remaining == 0);
+        let flags = if done { END_STREAM } else { 0 };
+        let chunk = buf[..read].to_vec();
+        self.send(DATA, flags, id, &chunk)?;
+        sent = true;
+        if done {
+          let out = self.outgoing.remove(idx);
+          (self.log)(out.record);
+        } else {
+          idx += 1;
+        }
+      }
+      if !sent {
+        break;
+      }
+    }
+    self.io.get_mut().flush()?;
+    Ok(())
+  }
+}
+
+/// Build a request out of the decoded headers of a stream.
+fn to_request(
+  headers: Vec<hpack::Header>,
+) -> std::result::Result<Request, ReqFail> {
+  let (mut method, mut path, mut authority) = (None, None, None);
+  let mut regular = vec![];
+  for (name, value) in headers {
+    let (name, value) =
+      match (String::from_utf8(name), String::from_utf8(value)) {
+        (Ok(n), Ok(v)) => (n, v),
+        (Err(e), _) | (_, Err(e)) => {
+          return Err(ReqFail::InvalidFormat(
+            String::from_utf8_lossy(e.as_bytes()).into(),
+          ))
+        }
+      };
+    match &name[..] {
+      ":method" => method = Some(value),
+      ":path" => path = Some(parse_target(&value)?),
+      ":authority" => authority = Some(value),
+      n if n.starts_with(':') => (),
+      _ => regular.push((name, value)),
+    }
+  }
+  if let Some(authority) = authority {
+    if !regular.iter().any(|(n, _)| n == "host") {
+      regular.push(("host".into(), authority));
+    }
+  }
+  match (method, path) {
+    (Some(method), Some((path, query))) => Ok(Request {
+      method,
+      path,
+      query,
+      headers: regular,
+      peer: None,
+    }),
+    _ => Err(ReqFail::InvalidFormat("missing :method or :path".into())),
+  }
+}
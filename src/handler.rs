@@ -0,0 +1,245 @@
+//! Customizing what happens between a request arriving and the response going
+//! out.
+//!
+//! Every request goes through a stack of `Layer`s, outermost first: the
+//! built-in access list and authentication, then any added with
+//! `Httpserv::layer`, then the routes added with `Httpserv::route`. Whatever
+//! makes it through all of them reaches the `Handler` at the bottom, which
+//! serves files from `Config::root` unless replaced with `Httpserv::handler`.
+
+use std::io;
+
+use crate::{
+  acl::Acl,
+  http::{Request, Response},
+  trace::Trace,
+  Auth, Config,
+};
+
+/// Turns a request into a response. Implemented for any suitable closure.
+pub trait Handler: Send + Sync {
+  fn handle(&self, request: Request) -> io::Result<Response>;
+}
+
+impl<F> Handler for F
+where
+  F: Fn(Request) -> io::Result<Response> + Send + Sync,
+{
+  fn handle(&self, request: Request) -> io::Result<Response> {
+    self(request)
+  }
+}
+
+/// Wraps the rest of the stack, and can change the request on the way in,
+/// the response on the way out, or skip `next` and answer directly.
+/// Implemented for any suitable closure.
+pub trait Layer: Send + Sync {
+  fn handle(
+    &self,
+    request: Request,
+    next: &dyn Handler,
+  ) -> io::Result<Response>;
+}
+
+impl<F> Layer for F
+where
+  F: Fn(Request, &dyn Handler) -> io::Result<Response> + Send + Sync,
+{
+  fn handle(
+    &self,
+    request: Request,
+    next: &dyn Handler,
+  ) -> io::Result<Response> {
+    self(request, next)
+  }
+}
+
+/// Handlers for specific methods and paths. Anything that doesn't match falls
+/// through to the next handler.
+#[derive(Default)]
+pub struct Router {
+  routes: Vec<(String, String, Box<dyn Handler>)>,
+}
+
+impl Router {
+  /// Send requests with this method and path to `handler`. The path may end
+  /// with `*` to match everything starting with what's before it. Routes are
+  /// tried in the order they're added.
+  pub fn route(
+    &mut self,
+    method: &str,
+    path: &str,
+    handler: impl Handler + 'static,
+  ) {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    self
+      .routes
+      .push((method.into(), path.into(), Box::new(handler)));
+  }
+
+  fn find(&self, request: &Request) -> Option<&dyn Handler> {
+    self
+      .routes
+      .iter()
+      .find(|(method, path, _)| {
+        let path_matches = match path.strip_suffix('*') {
+          Some(prefix) => request.path.starts_with(prefix),
+          None => request.path == *path,
+        };
+        path_matches && request.method.eq_ignore_ascii_case(method)
+      })
+      .map(|(_, _, handler)| &**handler)
+  }
+}
+
+impl Layer for Router {
+  fn handle(
+    &self,
+    request: Request,
+    next: &dyn Handler,
+  ) -> io::Result<Response> {
+    match self.find(&request) {
+      Some(handler) => handler.handle(request),
+      None => next.handle(request),
+    }
+  }
+}
+
+impl Layer for Acl {
+  fn handle(
+    &self,
+    request: Request,
+    next: &dyn Handler,
+  ) -> io::Result<Response> {
+    match request.peer {
+      Some(peer) if !self.permits(peer) => Ok(Response::Forbidden),
+      _ => next.handle(request),
+    }
+  }
+}
+
+impl Layer for Auth {
+  fn handle(
+    &self,
+    request: Request,
+    next: &dyn Handler,
+  ) -> io::Result<Response> {
+    match self.check(request.header("Authorization")) {
+      true => next.handle(request),
+      false => Ok(Response::Unauthorized(self.realm.clone())),
+    }
+  }
+}
+
+/// The default handler, serving files as configured.
+pub(crate) struct Files<'a> {
+  pub cfg: &'a Config,
+  /// Where to explain how files are picked, if anywhere
+  pub trace: Option<&'a Trace>,
+}
+
+impl Handler for Files<'_> {
+  fn handle(&self, request: Request) -> io::Result<Response> {
+    match self.trace {
+      Some(trace) => {
+        Response::explained(request, self.cfg, &mut |why| trace.note(why))
+      }
+      None => Response::to(request, self.cfg),
+    }
+  }
+}
+
+/// Some layers around a handler, which is itself a handler.
+pub(crate) struct Chain<'a> {
+  pub layers: &'a [&'a dyn Layer],
+  pub inner: &'a dyn Handler,
+}
+
+impl Handler for Chain<'_> {
+  fn handle(&self, request: Request) -> io::Result<Response> {
+    match self.layers.split_first() {
+      Some((layer, rest)) => layer.handle(
+        request,
+        &Chain {
+          layers: rest,
+          inner: self.inner,
+        },
+      ),
+      None => self.inner.handle(request),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::http::Body;
+
+  fn get(path: &str) -> Request {
+    Request {
+      method: "GET".into(),
+      path: path.into(),
+      query: String::new(),
+      headers: vec![],
+      peer: None,
+    }
+  }
+
+  fn text(resp: Response) -> String {
+    match resp {
+      Response::Ok {
+        body: Body::Bytes(b),
+        ..
+      } => String::from_utf8(b.into_inner()).unwrap(),
+      other => panic!("unexpected response {:?}", other),
+    }
+  }
+
+  #[test]
+  fn chain_order() {
+    let tag = |t: &'static str| {
+      move |req: Request, next: &dyn Handler| {
+        Ok(match next.handle(req)? {
+          Response::Ok {
+            body: Body::Bytes(b),
+            ..
+          } => {
+            let mut b = b.into_inner();
+            b.extend_from_slice(t.as_bytes());
+            Response::bytes("text/plain", b)
+          }
+          other => other,
+        })
+      }
+    };
+    let (outer, inner) = (tag("outer"), tag("inner"));
+    let chain = Chain {
+      layers: &[&outer, &inner],
+      inner: &|_| Ok(Response::bytes("text/plain", "base,")),
+    };
+    let resp = chain.handle(get("")).unwrap();
+    assert_eq!(text(resp), "base,innerouter", "layers ran out of order");
+  }
+
+  #[test]
+  fn routing() {
+    let mut router = Router::default();
+    router.route("GET", "/exact", |_| Ok(Response::bytes("text/plain", "e")));
+    router.route("POST", "/exact", |_| Ok(Response::Forbidden));
+    router.route("GET", "/api/*", |r: Request| {
+      Ok(Response::bytes("text/plain", r.path))
+    });
+    let fallback = |_| Ok(Response::NotFound);
+    let chain = Chain {
+      layers: &[&router],
+      inner: &fallback,
+    };
+    assert_eq!(text(chain.handle(get("exact")).unwrap()), "e");
+    assert_eq!(text(chain.handle(get("api/a/b")).unwrap()), "api/a/b");
+    let resp = chain.handle(get("exact/no")).unwrap();
+    assert_eq!(resp.code(), 404, "prefix matched an exact route");
+    let mut post = get("exact");
+    post.method = "POST".into();
+    assert_eq!(chain.handle(post).unwrap().code(), 403, "method ignored");
+  }
+}
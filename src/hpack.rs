@@ -0,0 +1,515 @@
+//! HPACK header compression for HTTP/2, per RFC 7541.
+//!
+//! The decoder is complete, since we have to understand whatever browsers
+//! send. The encoder only ever emits literals without indexing, which is
+//! always valid and plenty for the handful of headers we send back.
+
+use std::{collections::VecDeque, fmt, sync::OnceLock};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HpackFail {
+  Truncated,
+  IntegerOverflow,
+  BadIndex(usize),
+  BadHuffman,
+  TableSizeTooBig(usize),
+}
+
+impl fmt::Display for HpackFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      HpackFail::Truncated => write!(f, "header block ended early"),
+      HpackFail::IntegerOverflow => write!(f, "integer too large"),
+      HpackFail::BadIndex(i) => write!(f, "no header table entry {}", i),
+      HpackFail::BadHuffman => write!(f, "invalid Huffman-coded string"),
+      HpackFail::TableSizeTooBig(s) => {
+        write!(f, "table size {} over the advertised maximum", s)
+      }
+    }
+  }
+}
+
+pub type Header = (Vec<u8>, Vec<u8>);
+
+/// Appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+  (":authority", ""),
+  (":method", "GET"),
+  (":method", "POST"),
+  (":path", "/"),
+  (":path", "/index.html"),
+  (":scheme", "http"),
+  (":scheme", "https"),
+  (":status", "200"),
+  (":status", "204"),
+  (":status", "206"),
+  (":status", "304"),
+  (":status", "400"),
+  (":status", "404"),
+  (":status", "500"),
+  ("accept-charset", ""),
+  ("accept-encoding", "gzip, deflate"),
+  ("accept-language", ""),
+  ("accept-ranges", ""),
+  ("accept", ""),
+  ("access-control-allow-origin", ""),
+  ("age", ""),
+  ("allow", ""),
+  ("authorization", ""),
+  ("cache-control", ""),
+  ("content-disposition", ""),
+  ("content-encoding", ""),
+  ("content-language", ""),
+  ("content-length", ""),
+  ("content-location", ""),
+  ("content-range", ""),
+  ("content-type", ""),
+  ("cookie", ""),
+  ("date", ""),
+  ("etag", ""),
+  ("expect", ""),
+  ("expires", ""),
+  ("from", ""),
+  ("host", ""),
+  ("if-match", ""),
+  ("if-modified-since", ""),
+  ("if-none-match", ""),
+  ("if-range", ""),
+  ("if-unmodified-since", ""),
+  ("last-modified", ""),
+  ("link", ""),
+  ("location", ""),
+  ("max-forwards", ""),
+  ("proxy-authenticate", ""),
+  ("proxy-authorization", ""),
+  ("range", ""),
+  ("referer", ""),
+  ("refresh", ""),
+  ("retry-after", ""),
+  ("server", ""),
+  ("set-cookie", ""),
+  ("strict-transport-security", ""),
+  ("transfer-encoding", ""),
+  ("user-agent", ""),
+  ("vary", ""),
+  ("via", ""),
+  ("www-authenticate", ""),
+];
+
+/// Appendix B, as `(code, length in bits)` for each byte value. The 30-bit
+/// all-ones EOS symbol is handled separately, since it may only appear as
+/// padding.
+#[rustfmt::skip]
+const HUFFMAN: [(u32, u8); 256] = [
+  (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28),
+  (0xfffffe4, 28), (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28),
+  (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+  (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28),
+  (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28),
+  (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+  (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28),
+  (0xffffff8, 28), (0xffffff9, 28), (0xffffffa, 28), (0xffffffb, 28),
+  (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12), (0x1ff9, 13), (0x15, 6),
+  (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10), (0xf9, 8), (0x7fb, 11),
+  (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6), (0x0, 5), (0x1, 5), (0x2, 5),
+  (0x19, 6), (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6),
+  (0x5c, 7), (0xfb, 8), (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10),
+  (0x1ffa, 13), (0x21, 6), (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7),
+  (0x61, 7), (0x62, 7), (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7),
+  (0x68, 7), (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+  (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7), (0xfd, 8),
+  (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+  (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5), (0x25, 6),
+  (0x26, 6), (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7), (0x28, 6), (0x29, 6),
+  (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7), (0x2c, 6), (0x8, 5), (0x9, 5),
+  (0x2d, 6), (0x77, 7), (0x78, 7), (0x79, 7), (0x7a, 7), (0x7b, 7),
+  (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28),
+  (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20), (0x3fffd3, 22),
+  (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22),
+  (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23),
+  (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23), (0xffffec, 24),
+  (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24),
+  (0x7fffe1, 23), (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23),
+  (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23), (0x3fffd9, 22),
+  (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22),
+  (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22),
+  (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21), (0x7fffea, 23),
+  (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21),
+  (0x3fffdf, 22), (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21),
+  (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21), (0x7fffed, 23),
+  (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20),
+  (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23),
+  (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23), (0x3ffffe0, 26),
+  (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22),
+  (0x7ffff2, 23), (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26),
+  (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27), (0x7ffffdf, 27),
+  (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19),
+  (0x1fffe3, 21), (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27),
+  (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24), (0x1fffe4, 21),
+  (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28),
+  (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20),
+  (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21), (0x3fffe9, 22),
+  (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22),
+  (0x3fffeb, 22), (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24),
+  (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23), (0x3ffffeb, 26),
+  (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27),
+  (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27),
+  (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27), (0x7ffffee, 27),
+  (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26),
+];
+
+/// A binary tree for decoding Huffman codes, one bit at a time. Each node
+/// holds its two children; values below `LEAF` are other nodes, and values
+/// at or above it are the byte `value - LEAF`.
+const LEAF: u16 = 0x8000;
+
+fn huffman_tree() -> &'static [[u16; 2]] {
+  static TREE: OnceLock<Vec<[u16; 2]>> = OnceLock::new();
+  TREE.get_or_init(|| {
+    let mut tree = vec![[0u16; 2]];
+    for (byte, &(code, len)) in HUFFMAN.iter().enumerate() {
+      let mut node = 0;
+      for bit in (0..len).rev() {
+        let dir = ((code >> bit) & 1) as usize;
+        if bit == 0 {
+          tree[node][dir] = LEAF + byte as u16;
+        } else {
+          if tree[node][dir] == 0 {
+            tree.push([0; 2]);
+            tree[node][dir] = (tree.len() - 1) as u16;
+          }
+          node = tree[node][dir] as usize;
+        }
+      }
+    }
+    tree
+  })
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, HpackFail> {
+  let tree = huffman_tree();
+  let mut out = Vec::with_capacity(data.len() * 8 / 5);
+  let (mut node, mut depth, mut all_ones) = (0, 0, true);
+  for byte in data {
+    for bit in (0..8).rev() {
+      let dir = ((byte >> bit) & 1) as usize;
+      let next = tree[node][dir];
+      depth += 1;
+      all_ones &= dir == 1;
+      if next >= LEAF {
+        out.push((next - LEAF) as u8);
+        node = 0;
+        depth = 0;
+        all_ones = true;
+      } else if next == 0 {
+        // only the EOS symbol runs off the end of the tree
+        return Err(HpackFail::BadHuffman);
+      } else {
+        node = next as usize;
+      }
+    }
+  }
+  // leftovers must be a prefix of EOS, and shorter than a byte
+  if depth > 7 || !all_ones {
+    return Err(HpackFail::BadHuffman);
+  }
+  Ok(out)
+}
+
+/// Section 5.1: an integer in the low `prefix` bits of the first byte,
+/// continuing into later bytes if those are all ones.
+fn decode_int(data: &mut &[u8], prefix: u8) -> Result<usize, HpackFail> {
+  let (&first, rest) = data.split_first().ok_or(HpackFail::Truncated)?;
+  *data = rest;
+  let max = (1usize << prefix) - 1;
+  let mut value = first as usize & max;
+  if value < max {
+    return Ok(value);
+  }
+  let mut shift = 0;
+  loop {
+    let (&b, rest) = data.split_first().ok_or(HpackFail::Truncated)?;
+    *data = rest;
+    if shift > 28 {
+      return Err(HpackFail::IntegerOverflow);
+    }
+    value += ((b & 0x7f) as usize) << shift;
+    shift += 7;
+    if b & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+}
+
+fn encode_int(out: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+  let max = (1usize << prefix) - 1;
+  if value < max {
+    out.push(flags | value as u8);
+    return;
+  }
+  out.push(flags | max as u8);
+  value -= max;
+  while value >= 0x80 {
+    out.push((value & 0x7f) as u8 | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
+
+/// Section 5.2
+fn decode_string(data: &mut &[u8]) -> Result<Vec<u8>, HpackFail> {
+  let huffman = data.first().ok_or(HpackFail::Truncated)? & 0x80 != 0;
+  let len = decode_int(data, 7)?;
+  if len > data.len() {
+    return Err(HpackFail::Truncated);
+  }
+  let (raw, rest) = data.split_at(len);
+  *data = rest;
+  match huffman {
+    true => huffman_decode(raw),
+    false => Ok(raw.to_vec()),
+  }
+}
+
+fn encode_string(out: &mut Vec<u8>, s: &[u8]) {
+  encode_int(out, 0, 7, s.len());
+  out.extend_from_slice(s);
+}
+
+/// Decodes header blocks from one connection. The dynamic table persists
+/// between blocks, so the same decoder has to see every block, in order.
+pub struct Decoder {
+  table: VecDeque<Header>,
+  size: usize,
+  max_size: usize,
+  /// The limit we advertised, which the peer can shrink `max_size` under
+  limit: usize,
+}
+
+impl Decoder {
+  pub fn new(limit: usize) -> Decoder {
+    Decoder {
+      table: VecDeque::new(),
+      size: 0,
+      max_size: limit,
+      limit,
+    }
+  }
+
+  fn get(&self, index: usize) -> Result<Header, HpackFail> {
+    match index {
+      0 => Err(HpackFail::BadIndex(0)),
+      1..=61 => {
+        let (n, v) = STATIC_TABLE[index - 1];
+        Ok((n.into(), v.into()))
+      }
+      _ => self
+        .table
+        .get(index - 62)
+        .cloned()
+        .ok_or(HpackFail::BadIndex(index)),
+    }
+  }
+
+  fn evict_to(&mut self, max: usize) {
+    while self.size > max {
+      match self.table.pop_back() {
+        Some((n, v)) => self.size -= n.len() + v.len() + 32,
+        None => break,
+      }
+    }
+  }
+
+  fn insert(&mut self, header: Header) {
+    let size = header.0.len() + header.1.len() + 32;
+    self.evict_to(self.max_size.saturating_sub(size));
+    // entries bigger than the whole table just empty it
+    if size <= self.max_size {
+      self.size += size;
+      self.table.push_front(header);
+    }
+  }
+
+  pub fn decode(&mut self, mut data: &[u8]) -> Result<Vec<Header>, HpackFail> {
+    let mut headers = vec![];
+    while let Some(&first) = data.first() {
+      if first & 0x80 != 0 {
+        // 6.1: indexed
+        let index = decode_int(&mut data, 7)?;
+        headers.push(self.get(index)?);
+      } else if first & 0xe0 == 0x20 {
+        // 6.3: dynamic table size update
+        let size = decode_int(&mut data, 5)?;
+        if size > self.limit {
+          return Err(HpackFail::TableSizeTooBig(size));
+        }
+        self.max_size = size;
+        self.evict_to(size);
+      } else {
+        // 6.2: literals, either indexed (6 bit prefix) or not (4 bit prefix)
+        let indexing = first & 0xc0 == 0x40;
+        let index = decode_int(&mut data, if indexing { 6 } else { 4 })?;
+        let name = match index {
+          0 => decode_string(&mut data)?,
+          i => self.get(i)?.0,
+        };
+        let value = decode_string(&mut data)?;
+        if indexing {
+          self.insert((name.clone(), value.clone()));
+        }
+        headers.push((name, value));
+      }
+    }
+    Ok(headers)
+  }
+}
+
+/// Encode a header block, using the static table where it matches exactly and
+/// literals without indexing otherwise. `name`s must already be lowercase.
+pub fn encode<'a>(
+  headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Vec<u8> {
+  let mut out = vec![];
+  for (name, value) in headers {
+    let exact = STATIC_TABLE.iter().position(|&e| e == (name, value));
+    let named = STATIC_TABLE.iter().position(|&(n, _)| n == name);
+    match (exact, named) {
+      (Some(i), _) => encode_int(&mut out, 0x80, 7, i + 1),
+      (None, Some(i)) => {
+        encode_int(&mut out, 0, 4, i + 1);
+        encode_string(&mut out, value.as_bytes());
+      }
+      (None, None) => {
+        out.push(0);
+        encode_string(&mut out, name.as_bytes());
+        encode_string(&mut out, value.as_bytes());
+      }
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn unhex(s: &str) -> Vec<u8> {
+    let s: String = s.split_whitespace().collect();
+    (0..s.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+      .collect()
+  }
+
+  fn strs(headers: Vec<Header>) -> Vec<(String, String)> {
+    headers
+      .into_iter()
+      .map(|(n, v)| {
+        (String::from_utf8(n).unwrap(), String::from_utf8(v).unwrap())
+      })
+      .collect()
+  }
+
+  #[test]
+  fn integers() {
+    // C.1.1 to C.1.3
+    for &(bytes, prefix, value) in &[
+      (&[0x0a][..], 5, 10),
+      (&[0x1f, 0x9a, 0x0a], 5, 1337),
+      (&[0x2a], 8, 42),
+    ] {
+      let mut data = bytes;
+      assert_eq!(decode_int(&mut data, prefix), Ok(value));
+      assert!(data.is_empty(), "not all of {} consumed", value);
+      let mut out = vec![];
+      encode_int(&mut out, 0, prefix, value);
+      assert_eq!(out, bytes, "{} encoded wrong", value);
+    }
+    assert_eq!(
+      decode_int(&mut &[0x1f, 0x9a][..], 5),
+      Err(HpackFail::Truncated)
+    );
+  }
+
+  // C.4: requests with Huffman coding, sharing one dynamic table
+  #[test]
+  fn huffman_requests() {
+    let mut dec = Decoder::new(4096);
+    let first = dec
+      .decode(&unhex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"))
+      .unwrap();
+    assert_eq!(
+      strs(first),
+      vec![
+        (":method".into(), "GET".into()),
+        (":scheme".into(), "http".into()),
+        (":path".into(), "/".into()),
+        (":authority".into(), "www.example.com".into()),
+      ]
+    );
+    let second = dec.decode(&unhex("8286 84be 5886 a8eb 1064 9cbf")).unwrap();
+    assert_eq!(
+      strs(second)[3..],
+      [
+        (":authority".into(), "www.example.com".into()),
+        ("cache-control".into(), "no-cache".into()),
+      ]
+    );
+    let third = dec
+      .decode(&unhex(
+        "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+      ))
+      .unwrap();
+    assert_eq!(
+      strs(third)[1..],
+      [
+        (":scheme".into(), "https".into()),
+        (":path".into(), "/index.html".into()),
+        (":authority".into(), "www.example.com".into()),
+        ("custom-key".into(), "custom-value".into()),
+      ]
+    );
+    assert_eq!(dec.size, 164, "dynamic table size wrong");
+  }
+
+  #[test]
+  fn eviction() {
+    let mut dec = Decoder::new(4096);
+    // size update to 60, then two literals with indexing of size 32+1+1
+    dec
+      .decode(&unhex("3f1d 4001 6101 62 4001 6301 64"))
+      .unwrap();
+    assert_eq!(dec.table.len(), 1, "oldest entry not evicted");
+    assert_eq!(dec.get(62), Ok((b"c".to_vec(), b"d".to_vec())));
+    assert!(
+      dec.decode(&unhex("3fe2 1f")).is_err(),
+      "oversize table allowed"
+    );
+  }
+
+  #[test]
+  fn bad_huffman() {
+    // a whole byte of EOS padding
+    assert_eq!(huffman_decode(&[0xff]), Err(HpackFail::BadHuffman));
+    // padding that isn't all ones
+    assert_eq!(huffman_decode(&[0x00]), Err(HpackFail::BadHuffman));
+    assert_eq!(huffman_decode(&[0x1f]), Ok(b"a".to_vec()));
+  }
+
+  #[test]
+  fn round_trip() {
+    let headers = [
+      (":status", "200"),
+      (":status", "401"),
+      ("content-type", "text/html"),
+      ("x-custom", "yes"),
+    ];
+    let block = encode(headers.iter().cloned());
+    assert_eq!(block[0], 0x88, ":status 200 not indexed");
+    let decoded = Decoder::new(4096).decode(&block).unwrap();
+    let decoded = strs(decoded);
+    let expected: Vec<(String, String)> =
+      headers.iter().map(|&(n, v)| (n.into(), v.into())).collect();
+    assert_eq!(decoded, expected);
+  }
+}
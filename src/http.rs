@@ -1,12 +1,19 @@
 use std::{
+  ffi::OsStr,
   fmt,
   fs::File,
-  io::{self, BufRead, BufReader, BufWriter, Write as _},
-  net,
-  path::{Component, Path},
+  io::{self, BufRead, BufWriter, Read, Write},
+  net::IpAddr,
+  path::{Component, Path, PathBuf},
 };
 
-use crate::cfg;
+use crate::{
+  archive::Archives,
+  cfg,
+  negotiate::{self, Accept, Languages, Variant},
+  sniff,
+  vfs::{DirEntry, Vfs},
+};
 
 #[derive(Debug)]
 pub enum ReqFail {
@@ -55,52 +62,224 @@ fn percent_decode(mut inp: &str) -> Option<String> {
   String::from_utf8(out).ok()
 }
 
+/// Turn a request target like `/url/here?query` into the path it names, like
+/// `url/here`, and the query, rejecting anything that tries to escape the
+/// root.
+pub fn parse_target(target: &str) -> Result<(String, String), ReqFail> {
+  let url = target.strip_prefix('/').unwrap_or(target);
+  let url = url.split('#').next().unwrap_or(url);
+  let (url, query) = url.split_once('?').unwrap_or((url, ""));
+  let url_path = Path::new(url);
+  if url_path.components().any(|c| c == Component::ParentDir) {
+    return Err(ReqFail::Malicious(".. component in path"));
+  }
+  let path = percent_decode(url).ok_or(ReqFail::InvalidPercentEncode)?;
+  Ok((path, query.into()))
+}
+
+/// Join `path` onto `dir`, making sure the result stays inside it. Only plain
+/// names are let through, since e.g. an absolute path would replace `dir`
+/// entirely.
+fn confine(dir: &Path, path: &str) -> Option<PathBuf> {
+  let mut out = dir.to_path_buf();
+  for part in Path::new(path).components() {
+    match part {
+      Component::Normal(name) => out.push(name),
+      Component::CurDir | Component::RootDir => (),
+      Component::ParentDir | Component::Prefix(_) => return None,
+    }
+  }
+  Some(out)
+}
+
+/// Escape text to be put in HTML
+fn html_escape(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      '\'' => out.push_str("&#39;"),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// Encode a path segment to be put in a URL
+pub(crate) fn percent_encode(segment: &str) -> String {
+  let mut out = String::with_capacity(segment.len());
+  for &b in segment.as_bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        out.push(b as char)
+      }
+      _ => out.push_str(&format!("%{:02X}", b)),
+    }
+  }
+  out
+}
+
+/// A page linking to everything in a directory
+fn listing(path: &str, entries: Vec<DirEntry>) -> Response {
+  let title = html_escape(&format!("/{}", path));
+  let mut page = format!(
+    "<!DOCTYPE html>\n<title>{0}</title>\n<h1>{0}</h1>\n<ul>\n",
+    title
+  );
+  for entry in entries {
+    let slash = if entry.is_dir { "/" } else { "" };
+    page.push_str(&format!(
+      "<li><a href=\"{}{2}\">{}{2}</a></li>\n",
+      percent_encode(&entry.name),
+      html_escape(&entry.name),
+      slash,
+    ));
+  }
+  page.push_str("</ul>\n");
+  Response::bytes("text/html; charset=utf-8", page)
+}
+
+/// The type configured or built in for files ending in `.ext`, and where it
+/// came from
+fn mime_for(
+  target: &cfg::Target,
+  cfg: &cfg::Config,
+  ext: &OsStr,
+) -> Option<(String, &'static str)> {
+  if let Some(t) = target.mappings.and_then(|m| m.get(ext)) {
+    return Some((t.clone(), "from the mount"));
+  }
+  if let Some(t) = cfg.mappings.get(ext) {
+    return Some((t.clone(), "from the mappings"));
+  }
+  let builtin = ext.to_str().and_then(crate::mime_type);
+  builtin.map(|t| (t.into(), "built in"))
+}
+
+/// Which charset a text file with extension `ext`, starting with `head`, is
+/// in
+fn charset(
+  head: &[u8],
+  html: bool,
+  ext: &OsStr,
+  cfg: &cfg::Config,
+  why: &mut dyn FnMut(fmt::Arguments),
+) -> String {
+  if let Some(charset) = sniff::bom(head) {
+    why(format_args!("its byte order mark says it's {}", charset));
+    return charset.into();
+  }
+  if let Some(charset) = sniff::meta_charset(head).filter(|_| html) {
+    why(format_args!("its <meta> says it's {}", charset));
+    return charset;
+  }
+  let shown = ext.to_string_lossy();
+  if let Some(charset) = cfg.charsets.get(ext) {
+    why(format_args!("{} is the default for .{}", charset, shown));
+    return charset.clone();
+  }
+  if let Some(charset) = &cfg.charset {
+    why(format_args!("{} is the default", charset));
+    return charset.clone();
+  }
+  let charset = sniff::guess_charset(head);
+  match charset {
+    "utf-8" => why(format_args!("it's valid UTF-8")),
+    _ => why(format_args!(
+      "it isn't valid UTF-8, so guessing {}",
+      charset
+    )),
+  }
+  charset.into()
+}
+
 pub struct Request {
+  pub method: String,
   pub path: String,
+  /// Everything after the `?`, still percent-encoded
+  pub query: String,
+  pub headers: Vec<(String, String)>,
+  /// Where the request came from, if it came over the network
+  pub peer: Option<IpAddr>,
 }
 
 impl Request {
-  pub fn load(conn: &mut net::TcpStream) -> Result<Request, ReqFail> {
-    let mut input = BufReader::new(conn).lines();
-    let path = {
-      // parse "GET /url/here HTTP/1.1" to "url/here"
-      let first_line = input
-        .next()
-        .ok_or(ReqFail::EarlyInputEnd)?
-        .or(Err(ReqFail::EarlyInputEnd))?;
-      // +1 because we actually care about the next character, not this one
-      let mut url_start = first_line
-        .find(" ")
-        .ok_or_else(|| ReqFail::InvalidFormat(first_line.clone()))?
-        + 1;
-      if first_line.chars().nth(url_start) == Some('/') {
-        url_start += 1;
-      }
-      let url_length = first_line[url_start..]
-        .find(" ")
-        .ok_or_else(|| ReqFail::InvalidFormat(first_line.clone()))?;
-      let url = &first_line[url_start..url_start + url_length];
-      let end = url
-        .find('?')
-        .or_else(|| url.find('#'))
-        .unwrap_or_else(|| url.len());
-      let url = &url[..end];
-      let url_path = Path::new(url);
-      if url_path.components().any(|c| c == Component::ParentDir) {
-        return Err(ReqFail::Malicious(".. component in path"));
-      }
-      percent_decode(url).ok_or(ReqFail::InvalidPercentEncode)?
+  #[allow(clippy::comparison_to_empty)]
+  pub fn load(conn: impl BufRead) -> Result<Request, ReqFail> {
+    let mut input = conn.lines();
+    // parse "GET /url/here HTTP/1.1" to "GET" and "url/here"
+    let first_line = input
+      .next()
+      .ok_or(ReqFail::EarlyInputEnd)?
+      .or(Err(ReqFail::EarlyInputEnd))?;
+    let mut parts = first_line.split(' ');
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+      (Some(m), Some(t), Some(_)) => (m.to_string(), t),
+      _ => return Err(ReqFail::InvalidFormat(first_line.clone())),
     };
-    // TODO: Read through request to get url + headers
-    // let content_types;
+    let (path, query) = parse_target(target)?;
+    let mut headers = vec![];
     for line in input {
       let line = line.map_err(ReqFail::IOOpFailed)?;
       if line == "" {
         break;
       }
+      // lines that aren't headers are skipped, like they always were
+      let colon = match line.find(':') {
+        Some(colon) => colon,
+        None => continue,
+      };
+      let (name, val) = line.split_at(colon);
+      headers.push((name.trim().into(), val[1..].trim().into()));
+    }
+
+    Ok(Request {
+      method,
+      path,
+      query,
+      headers,
+      peer: None,
+    })
+  }
+
+  /// Get the value of the first header with the given (case-insensitive) name
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, v)| &v[..])
+  }
+}
+
+/// What gets sent after the headers of a successful response.
+pub enum Body {
+  File(File),
+  Bytes(io::Cursor<Vec<u8>>),
+  /// Read as it's sent, e.g. for something generated on the fly
+  Stream(Box<dyn Read + Send>),
+}
+
+impl fmt::Debug for Body {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Body::File(file) => f.debug_tuple("File").field(file).finish(),
+      Body::Bytes(b) => write!(f, "Bytes({} bytes)", b.get_ref().len()),
+      Body::Stream(_) => write!(f, "Stream"),
     }
+  }
+}
 
-    Ok(Request { path })
+impl Read for Body {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Body::File(file) => file.read(buf),
+      Body::Bytes(b) => b.read(buf),
+      Body::Stream(s) => s.read(buf),
+    }
   }
 }
 
@@ -109,99 +288,327 @@ pub enum Response {
   Ok {
     headers: Vec<(String, String)>,
     body_type: String,
-    body_len: usize,
-    body: File,
+    /// `None` if it's not known up front, in which case the end of the body
+    /// is marked by closing the connection
+    body_len: Option<usize>,
+    body: Body,
   },
   NotFound,
   Moved(String),
+  Unauthorized(String),
+  Forbidden,
+  /// There are versions of what was asked for, but the client doesn't want
+  /// any of them. Has the request headers which were checked, for `Vary`.
+  NotAcceptable(String),
 }
 
 impl Response {
+  /// A successful response with a body that's already in memory
+  pub fn bytes(body_type: &str, body: impl Into<Vec<u8>>) -> Response {
+    let body = body.into();
+    Response::Ok {
+      headers: vec![],
+      body_type: body_type.into(),
+      body_len: Some(body.len()),
+      body: Body::Bytes(io::Cursor::new(body)),
+    }
+  }
+
+  /// A successful response whose body is read from `body` as it's sent,
+  /// until it runs out
+  pub fn stream(body_type: &str, body: impl Read + Send + 'static) -> Response {
+    Response::Ok {
+      headers: vec![],
+      body_type: body_type.into(),
+      body_len: None,
+      body: Body::Stream(Box::new(body)),
+    }
+  }
+
+  /// Add a header to a successful response. Other kinds of response have
+  /// fixed headers, so they're returned unchanged.
+  pub fn with_header(mut self, name: &str, value: &str) -> Response {
+    if let Response::Ok { headers, .. } = &mut self {
+      headers.push((name.into(), value.into()));
+    }
+    self
+  }
+
   pub fn code(&self) -> u16 {
     match self {
       Response::Ok { .. } => 200,
       Response::NotFound => 404,
       Response::Moved(_) => 301,
+      Response::Unauthorized(_) => 401,
+      Response::Forbidden => 403,
+      Response::NotAcceptable(_) => 406,
     }
   }
 
   pub fn to(req: Request, cfg: &cfg::Config) -> io::Result<Response> {
-    let filepath = cfg.root.join(&req.path);
-    let filepath = if filepath.is_dir() {
+    Response::explained(req, cfg, &mut |_| ())
+  }
+
+  /// Like `to`, but calling `why` to explain each decision along the way
+  #[allow(clippy::len_zero)]
+  pub(crate) fn explained(
+    req: Request,
+    cfg: &cfg::Config,
+    why: &mut dyn FnMut(fmt::Arguments),
+  ) -> io::Result<Response> {
+    let target = cfg.mount_for(&req.path);
+    let filepath = match confine(target.dir, target.rest) {
+      Some(p) => p,
+      None => {
+        why(format_args!(
+          "{} escapes {}",
+          target.rest,
+          target.dir.display()
+        ));
+        return Ok(Response::NotFound);
+      }
+    };
+    why(format_args!("/{} maps to {}", req.path, filepath.display()));
+    let vfs = Archives::new(target.vfs);
+    let in_archive = vfs.in_archive(&filepath);
+    if in_archive {
+      why(format_args!("{} is inside an archive", filepath.display()));
+    }
+    let is_dir = vfs.metadata(&filepath).is_ok_and(|m| m.is_dir)
+      // archives are files, so they can still be downloaded, unless they're
+      // explicitly asked for as a directory
+      || (in_archive && req.path.ends_with('/'));
+    let filepath = if is_dir {
       // enforce trailing / (except if request is for root)
       if req.path.len() > 0 && !req.path.ends_with("/") {
+        why(format_args!("it's a directory, so redirecting to add a /"));
         return Ok(Response::Moved(format!("/{}/", req.path)));
       }
-      filepath.join("index.html")
+      let index = filepath.join("index.html");
+      // there's no adding an index.html to an archive, so list it instead
+      if in_archive && vfs.metadata(&index).is_err() {
+        why(format_args!(
+          "it's a directory with no index.html, so listing it"
+        ));
+        return Ok(listing(&req.path, vfs.read_dir(&filepath)?));
+      }
+      why(format_args!("it's a directory, so serving its index.html"));
+      index
     } else {
       filepath
     };
-    // TODO: More robust extension checking + checking for match with Accept header
+    // a file asked for by its full name is served as is, unless negotiating
+    // is turned on for everything, or it doesn't exist but there are versions
+    // of it in other languages
+    let exists = vfs.metadata(&filepath).is_ok();
+    let by_name = filepath.extension().is_some() && !is_dir;
+    let mut headers = vec![];
+    let filepath = match cfg.negotiate || !exists {
+      true => {
+        let accept = Accept::parse(req.header("Accept"));
+        // ?lang=de overrides Accept-Language, for trying each out
+        let lang_param = req
+          .query
+          .split('&')
+          .find_map(|p| p.strip_prefix("lang="))
+          .and_then(percent_decode);
+        let languages = match lang_param {
+          Some(lang) => {
+            why(format_args!("?lang={} overrides Accept-Language", lang));
+            Languages::parse(Some(&lang))
+          }
+          None => Languages::parse(req.header("Accept-Language")),
+        };
+        let default = cfg.default_language.as_deref();
+        // something missing that was asked for by name can only be answered
+        // with a version of it in another language, not e.g. app.min.js for
+        // app.js
+        let only_languages = by_name && !cfg.negotiate;
+        let found = negotiate::siblings(&vfs, &filepath);
+        let variants: Vec<_> = found
+          .into_iter()
+          .filter(|path| {
+            !only_languages
+              || (path.extension() == filepath.extension()
+                && negotiate::language(path, &filepath).is_some())
+          })
+          .filter_map(|path| {
+            let (mime, _) = mime_for(&target, cfg, path.extension()?)?;
+            let lang = negotiate::language(&path, &filepath);
+            let quality = accept.quality(&mime)
+              * languages.quality(lang.as_deref(), default);
+            match &lang {
+              Some(lang) => why(format_args!(
+                "{} is {} in {}, q={}",
+                path.display(),
+                mime,
+                lang,
+                quality
+              )),
+              None => why(format_args!(
+                "{} is {}, q={}",
+                path.display(),
+                mime,
+                quality
+              )),
+            }
+            Some(Variant {
+              path,
+              mime,
+              lang,
+              quality,
+            })
+          })
+          .collect();
+        let vary = match variants.iter().any(|v| v.lang.is_some()) {
+          true => "Accept, Accept-Language",
+          false => "Accept",
+        };
+        match variants.is_empty() {
+          true => filepath,
+          false => match negotiate::choose(variants, &filepath) {
+            Some(chosen) => {
+              why(format_args!("chose {}", chosen.path.display()));
+              headers.push(("Vary".into(), vary.into()));
+              if let Some(lang) = chosen.lang {
+                headers.push(("Content-Language".into(), lang));
+              }
+              chosen.path
+            }
+            None => {
+              why(format_args!("none of those are acceptable"));
+              return Ok(Response::NotAcceptable(vary.into()));
+            }
+          },
+        }
+      }
+      false => filepath,
+    };
     let ext = match filepath.extension() {
       Some(e) => e.to_owned(),
       None => "".into(),
     };
-    let mapped_type = match cfg.mappings.get(&ext) {
-      Some(t) => t.clone(),
-      None => "text/plain".into(),
+    let shown = ext.to_string_lossy();
+    let mapped_type = match mime_for(&target, cfg, &ext) {
+      Some((t, from)) => {
+        why(format_args!("{} for .{}, {}", t, shown, from));
+        Some(t)
+      }
+      None => None,
     };
-    let doc = match File::open(filepath) {
+    let doc = match vfs.open(&filepath) {
       Ok(d) => d,
-      Err(e) => match e.kind() {
-        io::ErrorKind::NotFound => return Ok(Response::NotFound),
-        _ => return Err(e),
-      },
+      Err(e) => {
+        why(format_args!("couldn't open {}: {}", filepath.display(), e));
+        match e.kind() {
+          io::ErrorKind::NotFound => return Ok(Response::NotFound),
+          _ => return Err(e),
+        }
+      }
+    };
+    let (head, doc) = match &mapped_type {
+      Some(t) if !sniff::wants_charset(t) => (vec![], doc),
+      _ => sniff::peek(doc)?,
     };
-    let metadata = doc.metadata()?;
+    let mapped_type = match mapped_type {
+      Some(t) => t,
+      None => {
+        let t = sniff::sniff(&head);
+        match ext.is_empty() {
+          true => why(format_args!("no extension, but it looks like {}", t)),
+          false => why(format_args!(
+            "no mapping for .{}, but it looks like {}",
+            shown, t
+          )),
+        }
+        t.into()
+      }
+    };
+    let mapped_type = match sniff::wants_charset(&mapped_type) {
+      true => {
+        let html = mapped_type.starts_with("text/html");
+        let charset = charset(&head, html, &ext, cfg, why);
+        format!("{};charset={}", mapped_type, charset)
+      }
+      false => mapped_type,
+    };
+    let metadata = vfs.metadata(&filepath)?;
     Ok(Response::Ok {
-      headers: vec![],
+      headers,
       body_type: mapped_type,
-      body_len: metadata.len() as usize,
+      body_len: Some(metadata.len as usize),
       body: doc,
     })
   }
 
-  pub fn write(self, conn: net::TcpStream) -> io::Result<()> {
-    let mut bufout = BufWriter::new(conn);
-    let mut head = |code, ctype, len| {
-      write!(
-          bufout,
-          concat!(
-              "HTTP/1.1 {code}\n",
-              "Cache-Control: no-cache\n",
-              "Connection: close\n",
-              "Content-Type: {type}\n",
-              "Content-Length: {len}\n",
-          ),
-          code = code,
-          type = ctype,
-          len = len,
-      )
-    };
+  /// The status line, like `200 OK`
+  pub fn status(&self) -> &'static str {
     match self {
+      Response::Ok { .. } => "200 OK",
+      Response::NotFound => "404 Not Found",
+      Response::Moved(_) => "301 Moved Permanently",
+      Response::Unauthorized(_) => "401 Unauthorized",
+      Response::Forbidden => "403 Forbidden",
+      Response::NotAcceptable(_) => "406 Not Acceptable",
+    }
+  }
+
+  /// Every header to send, except `Connection`, which only makes sense for
+  /// HTTP/1.1
+  pub fn headers(&self) -> Vec<(String, String)> {
+    let (ctype, len) = match self {
       Response::Ok {
-        headers,
         body_type,
         body_len,
-        mut body,
-      } => {
-        head("200 OK", &body_type[..], body_len)?;
-        for (name, val) in headers {
-          write!(bufout, "{}: {}\n", name, val)?;
-        }
-        write!(bufout, "\n")?;
-        io::copy(&mut body, &mut bufout)?;
-      }
-      Response::NotFound => {
-        head("404 Not Found", "text/plain", 0)?;
-        write!(bufout, "\n")?;
-      }
-      Response::Moved(to) => {
-        head("301 Moved Permanently", "text/plain", 0)?;
-        write!(bufout, "Location: {to}\n\n", to = to)?;
-      }
+        ..
+      } => (&body_type[..], *body_len),
+      _ => ("text/plain", Some(0)),
     };
-    Ok(())
+    let mut out = vec![
+      ("Cache-Control".into(), "no-cache".into()),
+      ("Content-Type".into(), ctype.into()),
+      // the type's always deliberate, even when it's been sniffed
+      ("X-Content-Type-Options".into(), "nosniff".into()),
+    ];
+    if let Some(len) = len {
+      out.push(("Content-Length".into(), len.to_string()));
+    }
+    match self {
+      Response::Ok { headers, .. } => out.extend(headers.iter().cloned()),
+      Response::Moved(to) => out.push(("Location".into(), to.clone())),
+      Response::Unauthorized(realm) => out.push((
+        "WWW-Authenticate".into(),
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm),
+      )),
+      Response::NotAcceptable(vary) => out.push(("Vary".into(), vary.clone())),
+      Response::NotFound | Response::Forbidden => (),
+    }
+    out
+  }
+
+  pub fn into_body(self) -> Option<Body> {
+    match self {
+      Response::Ok { body, .. } => Some(body),
+      _ => None,
+    }
+  }
+
+  /// Send the response over HTTP/1.1, returning how many bytes of body were
+  /// sent.
+  pub fn write(self, conn: impl Write) -> io::Result<u64> {
+    let mut bufout = BufWriter::new(conn);
+    writeln!(bufout, "HTTP/1.1 {}", self.status())?;
+    writeln!(bufout, "Connection: close")?;
+    for (name, val) in self.headers() {
+      writeln!(bufout, "{}: {}", name, val)?;
+    }
+    writeln!(bufout)?;
+    let sent = match self.into_body() {
+      Some(mut body) => io::copy(&mut body, &mut bufout)?,
+      None => 0,
+    };
+    bufout.flush()?;
+    Ok(sent)
   }
 }
 
@@ -212,31 +619,450 @@ mod test {
   mod request {
     use super::*;
 
-    // TODO: How to mock TcpStream?
-    // to test:
-    //  - correct URL extracted from first line
-    //  - malicious URLs rejected
+    fn load(raw: &str) -> Result<Request, ReqFail> {
+      Request::load(raw.as_bytes())
+    }
+
+    #[test]
+    fn first_line() {
+      let req = load("GET /a/b%20c?query#frag HTTP/1.1\nHost: x\n\n").unwrap();
+      assert_eq!(req.method, "GET");
+      assert_eq!(req.path, "a/b c", "wrong path extracted");
+      assert_eq!(req.query, "query", "wrong query extracted");
+      assert_eq!(req.headers, vec![("Host".into(), "x".into())]);
+      assert_eq!(load("GET / HTTP/1.1\n\n").unwrap().path, "");
+      assert!(matches!(load(""), Err(ReqFail::EarlyInputEnd)));
+      assert!(
+        matches!(load("GET /\n\n"), Err(ReqFail::InvalidFormat(_))),
+        "accepted a line with no version"
+      );
+      let req = load("GET / HTTP/1.1\nno colon\nHost: x\n\n").unwrap();
+      assert_eq!(req.headers, vec![("Host".into(), "x".into())]);
+    }
+
+    #[test]
+    fn malicious() {
+      for target in ["/../etc/passwd", "/a/../../b", "/a/..", "/.."] {
+        let line = format!("GET {} HTTP/1.1\n\n", target);
+        assert!(
+          matches!(load(&line), Err(ReqFail::Malicious(_))),
+          "{} accepted",
+          target
+        );
+      }
+      let encoded = load("GET /a%2F..%2Fb HTTP/1.1\n\n");
+      assert!(
+        matches!(encoded, Err(ReqFail::InvalidPercentEncode)),
+        "encoded slash accepted"
+      );
+      // only whole `..` components are a problem
+      assert_eq!(load("GET /a..b/..c HTTP/1.1\n\n").unwrap().path, "a..b/..c");
+    }
+
+    #[test]
+    fn percent_decoding() {
+      assert_eq!(percent_decode("f%69le"), Some("file".into()));
+      assert_eq!(percent_decode("a%20b"), Some("a b".into()));
+      assert_eq!(percent_decode("sub%2Ffile"), None, "%2F decoded");
+      assert_eq!(percent_decode("trunc%6"), None, "truncated escape");
+    }
+
+    #[test]
+    fn header_lookup() {
+      let req = Request {
+        method: "GET".into(),
+        path: "".into(),
+        query: String::new(),
+        headers: vec![
+          ("Authorization".into(), "Basic abc".into()),
+          ("X-Dup".into(), "first".into()),
+          ("x-dup".into(), "second".into()),
+        ],
+        peer: None,
+      };
+      assert_eq!(req.header("authorization"), Some("Basic abc"));
+      assert_eq!(req.header("X-DUP"), Some("first"), "not first match");
+      assert_eq!(req.header("Accept"), None);
+    }
   }
 
   mod result {
     use super::*;
 
-    // TODO: How to mock filesystem?
-    // to test:
-    //  - if directory but no trailing /, Moved("{}/")
-    //  - if directory otherwise, loads index.html
-    //  - correct file loaded based on path
-    //  - correct content-type based on extension
-    //  - NotFound for nonexistent files
+    fn memory_cfg() -> cfg::Config {
+      let mut fs = crate::MemoryFs::new();
+      fs.insert_static("index.html", b"root index");
+      fs.insert_static("dir/index.html", b"dir index");
+      fs.insert_static("dir/style.css", b"a {}");
+      fs.insert_static("app.mjs", b"export {}");
+      fs.insert_static("fixture", b"{\"a\": [1, 2]}");
+      fs.insert_static("logo.unknown", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+      let mut mappings = std::collections::HashMap::new();
+      mappings.insert("html".into(), "text/html".into());
+      mappings.insert("css".into(), "text/css".into());
+      cfg::Config {
+        root: "".into(),
+        vfs: Some(std::sync::Arc::new(fs)),
+        mappings,
+        ..Default::default()
+      }
+    }
+
+    fn get(cfg: &cfg::Config, path: &str) -> Response {
+      let req = Request {
+        method: "GET".into(),
+        path: path.into(),
+        query: String::new(),
+        headers: vec![],
+        peer: None,
+      };
+      Response::to(req, cfg).expect("failed to resolve")
+    }
+
+    /// The content type and body of a successful response
+    fn contents(resp: Response) -> (String, String) {
+      match resp {
+        Response::Ok {
+          body_type,
+          body_len,
+          mut body,
+          ..
+        } => {
+          let mut text = String::new();
+          body.read_to_string(&mut text).unwrap();
+          assert_eq!(body_len, Some(text.len()), "wrong length");
+          (body_type, text)
+        }
+        other => panic!("wrong response {:?}", other),
+      }
+    }
+
+    #[test]
+    fn resolution() {
+      let cfg = memory_cfg();
+      match get(&cfg, "dir") {
+        Response::Moved(to) => assert_eq!(to, "/dir/", "wrong redirect"),
+        other => panic!("wrong response {:?}", other),
+      }
+      let html = ("text/html;charset=utf-8".into(), "dir index".into());
+      assert_eq!(contents(get(&cfg, "dir/")), html, "no index.html");
+      let html = ("text/html;charset=utf-8".into(), "root index".into());
+      assert_eq!(contents(get(&cfg, "")), html, "no root index.html");
+      let css = ("text/css;charset=utf-8".into(), "a {}".into());
+      assert_eq!(contents(get(&cfg, "dir/style.css")), css, "wrong file");
+      let js = ("text/javascript;charset=utf-8".into(), "export {}".into());
+      assert_eq!(contents(get(&cfg, "app.mjs")), js, "no built-in type");
+      let json = ("application/json".into(), "{\"a\": [1, 2]}".into());
+      assert_eq!(contents(get(&cfg, "fixture")), json, "not sniffed");
+      match get(&cfg, "logo.unknown") {
+        Response::Ok { body_type, .. } => assert_eq!(body_type, "image/png"),
+        other => panic!("wrong response {:?}", other),
+      }
+      assert_eq!(get(&cfg, "dir/nope.css").code(), 404);
+      assert_eq!(get(&cfg, "index.html/x").code(), 404, "file used as dir");
+    }
+
+    #[test]
+    fn charsets() {
+      let mut fs = crate::MemoryFs::new();
+      fs.insert_static("latin1.txt", b"caf\xe9");
+      fs.insert_static("utf16.csv", b"\xff\xfea\0,\0b\0");
+      fs.insert_static("meta.html", b"<meta charset=\"Shift_JIS\">");
+      fs.insert_static("notes.md", b"# hi");
+      fs.insert_static("given.ascii", b"hi");
+      let mut cfg = cfg::Config {
+        root: "".into(),
+        vfs: Some(std::sync::Arc::new(fs)),
+        ..Default::default()
+      };
+      let ascii = "text/plain; charset=us-ascii";
+      cfg.mappings.insert("ascii".into(), ascii.into());
+      cfg.charsets.insert("md".into(), "iso-8859-1".into());
+      let body_type = |cfg: &cfg::Config, path: &str| match get(cfg, path) {
+        Response::Ok { body_type, .. } => body_type,
+        other => panic!("wrong response {:?}", other),
+      };
+      let cases = [
+        ("latin1.txt", "text/plain;charset=windows-1252"),
+        ("utf16.csv", "text/csv;charset=utf-16le"),
+        ("meta.html", "text/html;charset=shift_jis"),
+        ("notes.md", "text/markdown;charset=iso-8859-1"),
+        ("given.ascii", ascii),
+      ];
+      for (path, mime) in cases {
+        assert_eq!(body_type(&cfg, path), mime, "wrong type for {}", path);
+      }
+      // a default overrides guessing, but not what the file says
+      cfg.charset = Some("koi8-r".into());
+      let cases = [
+        ("latin1.txt", "text/plain;charset=koi8-r"),
+        ("meta.html", "text/html;charset=shift_jis"),
+        ("notes.md", "text/markdown;charset=iso-8859-1"),
+      ];
+      for (path, mime) in cases {
+        assert_eq!(body_type(&cfg, path), mime, "wrong type for {}", path);
+      }
+    }
+
+    #[test]
+    fn negotiation() {
+      let mut fs = crate::MemoryFs::new();
+      fs.insert_static("img/hero.png", b"png");
+      fs.insert_static("img/hero.webp", b"webp");
+      fs.insert_static("img/hero.avif", b"avif");
+      fs.insert_static("docs/index.txt", b"text");
+      let mut cfg = cfg::Config {
+        root: "".into(),
+        vfs: Some(std::sync::Arc::new(fs)),
+        ..Default::default()
+      };
+      let get = |cfg: &cfg::Config, path: &str, accept: &str| {
+        let req = Request {
+          method: "GET".into(),
+          path: path.into(),
+          query: String::new(),
+          headers: vec![("Accept".into(), accept.into())],
+          peer: None,
+        };
+        let resp = Response::to(req, cfg).expect("failed to resolve");
+        let vary = resp.headers().into_iter().find(|(n, _)| n == "Vary");
+        let vary = vary.map(|(_, v)| v);
+        match resp {
+          Response::Ok { mut body, .. } => {
+            let mut text = String::new();
+            body.read_to_string(&mut text).unwrap();
+            (text, vary)
+          }
+          other => (other.code().to_string(), vary),
+        }
+      };
+      let chrome = "image/avif,image/webp,image/*,*/*;q=0.8";
+      let accept = Some("Accept".to_string());
+      assert_eq!(
+        get(&cfg, "img/hero", chrome),
+        ("avif".into(), accept.clone())
+      );
+      let webp = "image/webp, image/png;q=0.5";
+      assert_eq!(get(&cfg, "img/hero", webp).0, "webp", "q-values ignored");
+      assert_eq!(get(&cfg, "img/hero", "*/*").0, "avif", "preference ignored");
+      assert_eq!(get(&cfg, "img/hero", "text/html"), ("406".into(), accept));
+      assert_eq!(get(&cfg, "img/nope", chrome).0, "404");
+      assert_eq!(get(&cfg, "docs/", "text/*").0, "text", "index not found");
+      // unless asked to, files asked for by name are left alone
+      assert_eq!(get(&cfg, "img/hero.png", chrome), ("png".into(), None));
+      cfg.negotiate = true;
+      assert_eq!(get(&cfg, "img/hero.png", chrome).0, "avif");
+      assert_eq!(get(&cfg, "img/hero.png", "image/png").0, "png");
+      assert_eq!(get(&cfg, "img/hero.png", "*/*").0, "png", "lost a tie");
+    }
+
+    #[test]
+    fn languages() {
+      let mut fs = crate::MemoryFs::new();
+      fs.insert_static("index.en.html", b"en");
+      fs.insert_static("index.de.html", b"de");
+      fs.insert_static("index.fr.html", b"fr");
+      fs.insert_static("plain/index.html", b"plain");
+      fs.insert_static("plain/index.de.html", b"de");
+      fs.insert_static("app.min.js", b"min");
+      fs.insert_static("data.backup.json", b"{}");
+      let mut cfg = cfg::Config {
+        root: "".into(),
+        vfs: Some(std::sync::Arc::new(fs)),
+        ..Default::default()
+      };
+      let get = |cfg: &cfg::Config, path: &str, languages: &str| {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let req = Request {
+          method: "GET".into(),
+          path: path.into(),
+          query: query.into(),
+          headers: vec![("Accept-Language".into(), languages.into())],
+          peer: None,
+        };
+        let resp = Response::to(req, cfg).expect("failed to resolve");
+        let headers = resp.headers();
+        let header = |name: &str| {
+          let found = headers.iter().find(|(n, _)| n == name);
+          found.map(|(_, v)| v.clone()).unwrap_or_default()
+        };
+        let (vary, lang) = (header("Vary"), header("Content-Language"));
+        match resp {
+          Response::Ok { mut body, .. } => {
+            let mut text = String::new();
+            body.read_to_string(&mut text).unwrap();
+            (text, vary, lang)
+          }
+          other => (other.code().to_string(), vary, lang),
+        }
+      };
+      let vary = "Accept, Accept-Language".to_string();
+      let austrian = "de-AT, de;q=0.9, en;q=0.5";
+      assert_eq!(
+        get(&cfg, "", austrian),
+        ("de".into(), vary.clone(), "de".into())
+      );
+      assert_eq!(get(&cfg, "index.html", "fr, en").0, "fr", "not by name");
+      assert_eq!(get(&cfg, "", "en-GB;q=0.5, fr;q=0.7").0, "fr");
+      assert_eq!(get(&cfg, "?lang=en", austrian).0, "en", "no override");
+      assert_eq!(get(&cfg, "?a=b&lang=fr", "").0, "fr", "no override");
+      assert_eq!(get(&cfg, "?lang=%66r", "de").0, "fr", "not decoded");
+      assert_eq!(get(&cfg, "?lang=%zz", "de").0, "de", "bad encoding used");
+      // with nothing acceptable, the default if there is one
+      assert_eq!(get(&cfg, "", "es").0, "de", "not sorted by name");
+      assert_eq!(get(&cfg, "plain/", "de").0, "plain", "index.html ignored");
+      cfg.default_language = Some("fr".into());
+      assert_eq!(get(&cfg, "", "es").0, "fr", "default ignored");
+      assert_eq!(get(&cfg, "", "").0, "fr", "default ignored");
+      assert_eq!(get(&cfg, "", "en;q=0").0, "fr", "q=0 ignored");
+      assert_eq!(get(&cfg, "", "de"), ("de".into(), vary, "de".into()));
+      assert_eq!(get(&cfg, "index.en.html", "de").0, "en", "not by name");
+      assert_eq!(get(&cfg, "index.es.html", "es").0, "404");
+      // other files named like it aren't versions of it
+      assert_eq!(get(&cfg, "app.js", "").0, "404", "served app.min.js");
+      assert_eq!(get(&cfg, "data.json", "").0, "404", "served a backup");
+      assert_eq!(get(&cfg, "index.html", "de").0, "de", "language lost");
+      cfg.negotiate = true;
+      assert_eq!(get(&cfg, "plain/", "es").0, "plain", "untagged not first");
+      assert_eq!(get(&cfg, "plain/", "de").0, "de");
+    }
+
+    #[test]
+    fn confinement() {
+      let dir = Path::new("root");
+      assert_eq!(confine(dir, "a/./b"), Some("root/a/b".into()));
+      assert_eq!(confine(dir, "/etc/passwd"), Some("root/etc/passwd".into()));
+      assert_eq!(confine(dir, "a/../../x"), None, "escaped with ..");
+    }
+
+    #[test]
+    fn mounts() {
+      let mut mount: cfg::Mount = "/sub=tests/webroot/subdir".parse().unwrap();
+      mount.mappings.insert("".into(), "text/x-sub".into());
+      let cfg = cfg::Config {
+        root: "tests/webroot".into(),
+        mounts: vec![mount],
+        ..Default::default()
+      };
+      let get = |path: &str| {
+        let req = Request {
+          method: "GET".into(),
+          path: path.into(),
+          query: String::new(),
+          headers: vec![],
+          peer: None,
+        };
+        Response::to(req, &cfg).expect("failed to resolve")
+      };
+      match get("sub/file") {
+        Response::Ok { body_type, .. } => {
+          assert_eq!(body_type, "text/x-sub;charset=utf-8", "override ignored")
+        }
+        other => panic!("wrong response {:?}", other),
+      }
+      match get("file") {
+        Response::Ok { body_type, .. } => {
+          assert_eq!(body_type, "text/plain;charset=utf-8", "override leaked")
+        }
+        other => panic!("wrong response {:?}", other),
+      }
+      match get("sub") {
+        Response::Moved(to) => assert_eq!(to, "/sub/", "wrong redirect"),
+        other => panic!("wrong response {:?}", other),
+      }
+      assert_eq!(get("sub/subdir/file").code(), 404, "mount fell through");
+      assert_eq!(get("/etc/passwd").code(), 404, "escaped the root");
+    }
+
+    #[test]
+    fn not_found() {
+      let cfg = cfg::Config {
+        root: "tests/webroot".into(),
+        ..Default::default()
+      };
+      let req = Request {
+        method: "GET".into(),
+        path: "nonexistent".into(),
+        query: String::new(),
+        headers: vec![],
+        peer: None,
+      };
+      let resp = Response::to(req, &cfg).expect("failed to resolve");
+      assert_eq!(resp.code(), 404);
+    }
+
+    #[test]
+    fn explanation() {
+      let req = Request {
+        method: "GET".into(),
+        path: "dir/".into(),
+        query: String::new(),
+        headers: vec![],
+        peer: None,
+      };
+      let mut notes = vec![];
+      let cfg = memory_cfg();
+      Response::explained(req, &cfg, &mut |why| notes.push(why.to_string()))
+        .expect("failed to resolve");
+      assert_eq!(
+        notes,
+        [
+          "/dir/ maps to dir",
+          "it's a directory, so serving its index.html",
+          "text/html for .html, from the mappings",
+          "it's valid UTF-8",
+        ]
+      );
+    }
   }
 
   mod write {
     use super::*;
 
-    // TODO: How to mock TcpStream?
-    // to test:
-    //  - response format is correct
-    //  - .code() matches output code
-    //  - all required headers are outputted
+    fn written(resp: Response) -> String {
+      let mut out = vec![];
+      resp.write(&mut out).expect("failed to write");
+      String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn codes() {
+      let all = [
+        Response::bytes("text/plain", ""),
+        Response::NotFound,
+        Response::Moved("/a/".into()),
+        Response::Unauthorized("realm".into()),
+        Response::Forbidden,
+      ];
+      let codes: Vec<_> = all.iter().map(Response::code).collect();
+      assert_eq!(codes, [200, 404, 301, 401, 403]);
+      for resp in all {
+        let line = format!("HTTP/1.1 {} ", resp.code());
+        assert!(written(resp).starts_with(&line), "code() mismatch");
+      }
+    }
+
+    #[test]
+    fn format() {
+      let resp =
+        Response::bytes("text/html", "<p>hi</p>").with_header("X-A", "b");
+      assert_eq!(
+        written(resp),
+        "HTTP/1.1 200 OK\nConnection: close\nCache-Control: no-cache\n\
+         Content-Type: text/html\nX-Content-Type-Options: nosniff\n\
+         Content-Length: 9\nX-A: b\n\n<p>hi</p>"
+      );
+      let moved = written(Response::Moved("/dir/".into()));
+      assert!(moved.contains("\nLocation: /dir/\n"), "no Location");
+      assert!(moved.ends_with("\nContent-Length: 0\nLocation: /dir/\n\n"));
+      let auth = written(Response::Unauthorized("files".into()));
+      assert!(
+        auth.contains("\nWWW-Authenticate: Basic realm=\"files\""),
+        "no WWW-Authenticate"
+      );
+      let stream = written(Response::stream("text/plain", &b"abc"[..]));
+      assert!(!stream.contains("Content-Length"), "made up a length");
+      assert!(stream.ends_with("\n\nabc"), "stream body missing");
+    }
   }
 }
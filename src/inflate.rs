@@ -0,0 +1,276 @@
+//! Decompressing DEFLATE streams (RFC 1951), as used in zip files. Written for
+//! simplicity over speed, after zlib's `puff`.
+
+use std::io;
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67,
+  83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5,
+  5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513,
+  769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11,
+  11, 12, 12, 13, 13,
+];
+/// The order code length code lengths are sent in
+const CLEN_ORDER: [usize; 19] = [
+  16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn corrupt(why: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("bad deflate: {}", why))
+}
+
+fn too_long() -> io::Error {
+  corrupt("longer than expected")
+}
+
+/// A canonical Huffman code: how many codes there are of each length, and the
+/// symbols in code order.
+struct Huffman {
+  counts: [u16; 16],
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Huffman {
+    let mut counts = [0; 16];
+    for &len in lengths {
+      counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut symbols = vec![];
+    for len in 1..16 {
+      for (sym, _) in lengths.iter().enumerate().filter(|(_, &l)| l == len) {
+        symbols.push(sym as u16);
+      }
+    }
+    Huffman { counts, symbols }
+  }
+}
+
+struct Bits<'a> {
+  data: &'a [u8],
+  pos: usize,
+  buf: u32,
+  have: u32,
+}
+
+impl Bits<'_> {
+  fn bits(&mut self, need: u32) -> io::Result<u32> {
+    while self.have < need {
+      let byte = *self
+        .data
+        .get(self.pos)
+        .ok_or_else(|| corrupt("truncated"))?;
+      self.pos += 1;
+      self.buf |= (byte as u32) << self.have;
+      self.have += 8;
+    }
+    let out = self.buf & ((1 << need) - 1);
+    self.buf >>= need;
+    self.have -= need;
+    Ok(out)
+  }
+
+  fn decode(&mut self, code: &Huffman) -> io::Result<u16> {
+    // codes are packed starting from the most significant bit, so walk
+    // through each length's range of codes one bit at a time
+    let (mut bits, mut first, mut index) = (0, 0, 0);
+    for &count in &code.counts[1..] {
+      bits |= self.bits(1)? as i32;
+      let count = count as i32;
+      if bits - count < first {
+        return Ok(code.symbols[(index + bits - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      bits <<= 1;
+    }
+    Err(corrupt("invalid code"))
+  }
+
+  /// A block stored as-is, after skipping to the next byte boundary
+  fn stored(&mut self, out: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+    self.buf = 0;
+    self.have = 0;
+    let header = self
+      .data
+      .get(self.pos..self.pos + 4)
+      .ok_or_else(|| corrupt("truncated"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let check = u16::from_le_bytes([header[2], header[3]]);
+    if len != !check {
+      return Err(corrupt("stored length mismatch"));
+    }
+    self.pos += 4;
+    if out.len() + len as usize > limit {
+      return Err(too_long());
+    }
+    let data = self
+      .data
+      .get(self.pos..self.pos + len as usize)
+      .ok_or_else(|| corrupt("truncated"))?;
+    out.extend_from_slice(data);
+    self.pos += len as usize;
+    Ok(())
+  }
+
+  fn compressed(
+    &mut self,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lit: &Huffman,
+    dist: &Huffman,
+  ) -> io::Result<()> {
+    loop {
+      let sym = self.decode(lit)? as usize;
+      if sym < 256 {
+        if out.len() == limit {
+          return Err(too_long());
+        }
+        out.push(sym as u8);
+        continue;
+      }
+      if sym == 256 {
+        return Ok(());
+      }
+      let sym = sym - 257;
+      if sym >= LENGTH_BASE.len() {
+        return Err(corrupt("invalid length"));
+      }
+      let len = LENGTH_BASE[sym] as usize
+        + self.bits(LENGTH_EXTRA[sym] as u32)? as usize;
+      let sym = self.decode(dist)? as usize;
+      if sym >= DIST_BASE.len() {
+        return Err(corrupt("invalid distance"));
+      }
+      let back =
+        DIST_BASE[sym] as usize + self.bits(DIST_EXTRA[sym] as u32)? as usize;
+      if back > out.len() {
+        return Err(corrupt("distance too far back"));
+      }
+      if out.len() + len > limit {
+        return Err(too_long());
+      }
+      // byte by byte, since the copy can overlap what it's producing
+      let start = out.len() - back;
+      for i in 0..len {
+        out.push(out[start + i]);
+      }
+    }
+  }
+
+  /// Read the code lengths for a block's own Huffman codes
+  fn dynamic(&mut self) -> io::Result<(Huffman, Huffman)> {
+    let nlit = self.bits(5)? as usize + 257;
+    let ndist = self.bits(5)? as usize + 1;
+    let nclen = self.bits(4)? as usize + 4;
+    let mut clens = [0; 19];
+    for &i in &CLEN_ORDER[..nclen] {
+      clens[i] = self.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clens);
+    let mut lengths = Vec::with_capacity(nlit + ndist);
+    while lengths.len() < nlit + ndist {
+      let (len, repeat) = match self.decode(&clen)? {
+        sym @ 0..=15 => (sym as u8, 1),
+        16 => {
+          let prev = *lengths.last().ok_or_else(|| corrupt("no length"))?;
+          (prev, 3 + self.bits(2)?)
+        }
+        17 => (0, 3 + self.bits(3)?),
+        _ => (0, 11 + self.bits(7)?),
+      };
+      for _ in 0..repeat {
+        lengths.push(len);
+      }
+    }
+    if lengths.len() > nlit + ndist {
+      return Err(corrupt("too many lengths"));
+    }
+    let (lit, dist) = lengths.split_at(nlit);
+    Ok((Huffman::new(lit), Huffman::new(dist)))
+  }
+}
+
+/// Decompress a whole raw DEFLATE stream, which mustn't come to more than
+/// `limit` bytes. The output grows as it's produced, so a `limit` from an
+/// untrusted header can't make this allocate much more than it's given.
+pub(crate) fn inflate(data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+  let mut out = vec![];
+  let mut bits = Bits {
+    data,
+    pos: 0,
+    buf: 0,
+    have: 0,
+  };
+  loop {
+    let last = bits.bits(1)? == 1;
+    match bits.bits(2)? {
+      0 => bits.stored(&mut out, limit)?,
+      1 => {
+        let mut lengths = [8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        let lit = Huffman::new(&lengths);
+        let dist = Huffman::new(&[5; 30]);
+        bits.compressed(&mut out, limit, &lit, &dist)?;
+      }
+      2 => {
+        let (lit, dist) = bits.dynamic()?;
+        bits.compressed(&mut out, limit, &lit, &dist)?;
+      }
+      _ => return Err(corrupt("invalid block type")),
+    }
+    if last {
+      return Ok(out);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn block_types() {
+    // zlib.compressobj(wbits=-15) output, for each kind of block
+    let stored = [1, 5, 0, 250, 255, b'h', b'e', b'l', b'l', b'o'];
+    assert_eq!(inflate(&stored, 5).unwrap(), b"hello");
+    let fixed = [203, 72, 205, 201, 201, 87, 200, 0, 145, 0];
+    assert_eq!(inflate(&fixed, 11).unwrap(), b"hello hello");
+    let dynamic = [
+      37, 138, 129, 13, 0, 0, 12, 193, 94, 217, 107, 229, 255, 31, 198, 22, 34,
+      82, 64, 128, 26, 134, 207, 41, 177, 67, 195, 111, 250, 83, 203, 212, 202,
+      22, 45,
+    ];
+    let text = b"aabaaabbaaacaaaaacaa aaabccabbbbabbaaaabaaabaaaa aa abcababa";
+    assert_eq!(inflate(&dynamic, text.len()).unwrap(), &text[..]);
+    assert!(inflate(&fixed[..5], 11).is_err(), "truncation unnoticed");
+  }
+
+  #[test]
+  fn limits() {
+    let stored = [1, 5, 0, 250, 255, b'h', b'e', b'l', b'l', b'o'];
+    assert!(inflate(&stored, 4).is_err(), "stored block over the limit");
+    let fixed = [203, 72, 205, 201, 201, 87, 200, 0, 145, 0];
+    assert!(inflate(&fixed, 10).is_err(), "copy over the limit");
+    assert!(inflate(&fixed, 5).is_err(), "literal over the limit");
+    // 100,000 zeros in 114 bytes
+    let mut bomb = vec![
+      237, 193, 49, 1, 0, 0, 0, 194, 160, 245, 79, 109, 13, 15, 160,
+    ];
+    bomb.resize(111, 0);
+    bomb.extend([128, 87, 3]);
+    assert_eq!(inflate(&bomb, 100_000).unwrap().len(), 100_000);
+    assert!(inflate(&bomb, 1000).is_err(), "bomb went off");
+  }
+}
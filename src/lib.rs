@@ -1,14 +1,68 @@
-use std::{io, net, time::Instant};
+use std::{
+  fmt,
+  io::{self, BufReader, Read, Write},
+  net::{self, SocketAddr, ToSocketAddrs},
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    mpsc, Arc,
+  },
+  thread,
+  time::Instant,
+};
 
+mod access;
+pub use access::{LogFormat, LogFormatFail, Verbosity, VerbosityFail};
+mod acl;
+use access::{AccessLog, Record};
+use acl::Acl;
+pub use acl::{Cidr, CidrFail};
+mod archive;
+mod auth;
+pub use auth::Auth;
 mod cfg;
-pub use cfg::Config;
+pub use cfg::{with_port, Config, Mount, MountFail};
+mod conn;
+use conn::Conn;
+pub use conn::{Listener, Transport};
+mod h2;
+use h2::H2Fail;
+mod handler;
+use handler::{Chain, Files};
+pub use handler::{Handler, Layer, Router};
+mod hpack;
 mod http;
-use http::*;
+pub use http::{Body, ReqFail, Request, Response};
+mod inflate;
+mod metrics;
+use metrics::Metrics;
+mod mime;
+pub use mime::{load_mime_types, mime_type};
+mod negotiate;
+mod running;
+pub use running::RunningServer;
+mod shutdown;
+use shutdown::Tracker;
+pub use shutdown::{ShutdownHandle, Summary};
+mod sniff;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::{Tls, TlsFail};
+mod trace;
+use trace::Trace;
+#[cfg(unix)]
+mod unix;
+mod vfs;
+pub use vfs::{DirEntry, Disk, MemoryFs, Metadata, Vfs};
 
 #[derive(Debug)]
 pub enum HttpservFail {
   Io(io::Error),
   Request(ReqFail),
+  Http2(H2Fail),
+  #[cfg(feature = "tls")]
+  Tls(TlsFail),
 }
 impl From<io::Error> for HttpservFail {
   fn from(ioe: io::Error) -> Self {
@@ -20,55 +74,646 @@ impl From<ReqFail> for HttpservFail {
     Self::Request(rf)
   }
 }
+impl From<H2Fail> for HttpservFail {
+  fn from(hf: H2Fail) -> Self {
+    Self::Http2(hf)
+  }
+}
+impl fmt::Display for HttpservFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      HttpservFail::Io(e) => write!(f, "{}", e),
+      HttpservFail::Request(e) => write!(f, "{}", e),
+      HttpservFail::Http2(e) => write!(f, "{}", e),
+      #[cfg(feature = "tls")]
+      HttpservFail::Tls(e) => write!(f, "{}", e),
+    }
+  }
+}
+#[cfg(feature = "tls")]
+impl From<TlsFail> for HttpservFail {
+  fn from(tf: TlsFail) -> Self {
+    Self::Tls(tf)
+  }
+}
 type Result<T> = std::result::Result<T, HttpservFail>;
 
-pub struct Httpserv {
+/// Everything connections need to be handled, shared between their threads.
+struct Shared {
   cfg: Config,
-  listener: net::TcpListener,
+  /// Outermost first, starting with the access list and authentication
+  layers: Vec<Box<dyn Layer>>,
+  router: Router,
+  /// Replaces serving files, if set
+  handler: Option<Box<dyn Handler>>,
+  #[cfg(feature = "tls")]
+  tls: Option<Arc<rustls::ServerConfig>>,
+  stop: ShutdownHandle,
+  tracker: Tracker,
+  served: AtomicU64,
+  access: Option<AccessLog>,
+  metrics: Option<Metrics>,
+}
+
+pub struct Httpserv {
+  shared: Arc<Shared>,
+  listeners: Vec<Arc<dyn Listener>>,
+  locals: Vec<SocketAddr>,
+  /// Unix sockets listened on, which aren't in `locals`
+  sockets: Vec<PathBuf>,
+}
+
+/// How many ports to try before giving up, with port 0 or `port_fallback`
+const BIND_ATTEMPTS: u16 = 100;
+
+/// Bind every address `hostname` resolves to, all on the same port. With
+/// `fallback`, walk upwards from the given port until one is free everywhere.
+fn bind_all(
+  hostname: &str,
+  fallback: bool,
+) -> io::Result<Vec<net::TcpListener>> {
+  let addrs: Vec<_> = hostname.to_socket_addrs()?.collect();
+  let first = match addrs.first() {
+    Some(a) => a.port(),
+    None => {
+      return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{}' didn't resolve to any addresses", hostname),
+      ))
+    }
+  };
+  let (mut port, mut attempts) = (first, 1);
+  loop {
+    match bind_port(&addrs, port) {
+      // with port 0, the OS may have picked one that's only free on some of
+      // the addresses, so just let it pick again
+      Err(e)
+        if e.kind() == io::ErrorKind::AddrInUse
+          && (first == 0 || fallback)
+          && attempts < BIND_ATTEMPTS
+          && port < u16::MAX =>
+      {
+        attempts += 1;
+        if first != 0 {
+          port += 1;
+        }
+      }
+      res => return res,
+    }
+  }
+}
+
+/// Bind each of `addrs` on `port`. If it's 0, the first one picks the port for
+/// the rest. An address that's in use is an error, but others only are if none
+/// of them work, since e.g. `localhost` may resolve to `::1` on a machine
+/// without IPv6.
+fn bind_port(
+  addrs: &[SocketAddr],
+  mut port: u16,
+) -> io::Result<Vec<net::TcpListener>> {
+  let mut listeners = vec![];
+  let mut fail = None;
+  for addr in addrs {
+    match net::TcpListener::bind(SocketAddr::new(addr.ip(), port)) {
+      Ok(l) => {
+        port = l.local_addr()?.port();
+        listeners.push(l);
+      }
+      Err(e) if e.kind() == io::ErrorKind::AddrInUse => return Err(e),
+      Err(e) => fail = Some(e),
+    }
+  }
+  match fail {
+    Some(e) if listeners.is_empty() => Err(e),
+    _ => Ok(listeners),
+  }
+}
+
+/// Listen on a Unix domain socket, given after `unix:` in a hostname.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<Arc<dyn Listener>> {
+  Ok(Arc::new(unix::UnixSocket::bind(path)?))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &Path) -> io::Result<Arc<dyn Listener>> {
+  Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    format!("can't listen on {}: Unix sockets need Unix", path.display()),
+  ))
 }
 
 impl Httpserv {
   pub fn new(cfg: Config) -> Result<Httpserv> {
-    let listener = net::TcpListener::bind(&cfg.hostname)?;
-    Ok(Httpserv { cfg, listener })
+    Httpserv::with_listeners(cfg, vec![])
+  }
+
+  /// Like `new`, but also accepting connections from each of `extra`, so
+  /// `cfg.hostnames` can be empty
+  pub fn with_listeners(
+    cfg: Config,
+    extra: Vec<Box<dyn Listener>>,
+  ) -> Result<Httpserv> {
+    let (mut tcp, mut listeners, mut sockets) = (vec![], vec![], vec![]);
+    for hostname in &cfg.hostnames {
+      match hostname.strip_prefix("unix:") {
+        Some(path) => {
+          listeners.push(bind_unix(Path::new(path))?);
+          sockets.push(path.into());
+        }
+        None => tcp.extend(bind_all(hostname, cfg.port_fallback)?),
+      }
+    }
+    let locals = tcp
+      .iter()
+      .map(net::TcpListener::local_addr)
+      .collect::<io::Result<Vec<_>>>()?;
+    for listener in tcp {
+      listeners.push(Arc::new(listener) as Arc<dyn Listener>);
+    }
+    listeners.extend(extra.into_iter().map(Arc::from));
+    if listeners.is_empty() {
+      let msg = "no addresses to listen on";
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+    }
+    let mut acl = Acl {
+      allow: cfg.allow.clone(),
+      deny: cfg.deny.clone(),
+    };
+    if acl.allow.is_empty() && locals.iter().any(|l| l.ip().is_unspecified()) {
+      acl.allow = Cidr::local_ranges();
+    }
+    let exposed: Vec<_> = locals
+      .iter()
+      .filter(|l| !l.ip().is_loopback())
+      .map(ToString::to_string)
+      .collect();
+    // this is loud on purpose, so even --quiet doesn't silence it
+    if !exposed.is_empty() {
+      let allowed = match acl.allow.len() {
+        0 => "everyone".into(),
+        _ => acl
+          .allow
+          .iter()
+          .map(ToString::to_string)
+          .collect::<Vec<_>>()
+          .join(", "),
+      };
+      eprintln!(
+        "\n  !!! WARNING: listening on {}, which other machines can reach !!!\n  \
+         Serving {} to: {}\n",
+        exposed.join(", "),
+        cfg.root.display(),
+        allowed
+      );
+    }
+    let access = match cfg.verbosity {
+      Verbosity::Quiet => None,
+      _ => Some(AccessLog::open(cfg.log_format, cfg.log_file.as_deref())?),
+    };
+    let shared = Shared {
+      #[cfg(feature = "tls")]
+      tls: match &cfg.tls {
+        Some(tls) => Some(tls.server_config(&locals)?),
+        None => None,
+      },
+      stop: ShutdownHandle::new(cfg.shutdown_grace, &listeners),
+      tracker: Tracker::default(),
+      served: AtomicU64::new(0),
+      access,
+      metrics: match cfg.metrics {
+        true => Some(Metrics::new()),
+        false => None,
+      },
+      layers: {
+        let mut layers: Vec<Box<dyn Layer>> = vec![Box::new(acl)];
+        if let Some(auth) = &cfg.auth {
+          layers.push(Box::new(auth.clone()));
+        }
+        layers
+      },
+      router: Router::default(),
+      handler: None,
+      cfg,
+    };
+    Ok(Httpserv {
+      shared: Arc::new(shared),
+      listeners,
+      locals,
+      sockets,
+    })
+  }
+
+  /// Start a server on a background thread, on ports picked by the OS. Each
+  /// of `cfg.hostnames` is still used for the address, but its port is
+  /// ignored, so that many servers can run at once, e.g. one per test. Unix
+  /// sockets are used as given.
+  pub fn spawn(mut cfg: Config) -> Result<RunningServer> {
+    for hostname in &mut cfg.hostnames {
+      if hostname.starts_with("unix:") {
+        continue;
+      }
+      *hostname = with_port(hostname, Some(0));
+    }
+    Ok(Httpserv::new(cfg)?.start())
+  }
+
+  /// Run on a background thread, until the returned server is dropped.
+  pub fn start(mut self) -> RunningServer {
+    RunningServer {
+      urls: self.urls(),
+      locals: self.locals.clone(),
+      handle: self.shutdown_handle(),
+      thread: Some(thread::spawn(move || self.run())),
+    }
+  }
+
+  fn shared_mut(&mut self) -> &mut Shared {
+    Arc::get_mut(&mut self.shared).expect("can't change a running server")
+  }
+
+  /// Wrap every request in `layer`, inside any layers added before it.
+  pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+    self.shared_mut().layers.push(Box::new(layer));
+    self
+  }
+
+  /// Answer requests for `method` and `path` with `handler`, instead of a
+  /// file. See `Router::route` for how they're matched.
+  pub fn route(
+    mut self,
+    method: &str,
+    path: &str,
+    handler: impl Handler + 'static,
+  ) -> Self {
+    self.shared_mut().router.route(method, path, handler);
+    self
+  }
+
+  /// Answer anything not matched by a route with `handler`, instead of
+  /// serving files.
+  pub fn handler(mut self, handler: impl Handler + 'static) -> Self {
+    self.shared_mut().handler = Some(Box::new(handler));
+    self
   }
 
   pub fn config(&self) -> &Config {
-    &self.cfg
+    &self.shared.cfg
   }
 
-  fn respond_one(&self, conn: io::Result<net::TcpStream>) -> Result<()> {
-    // just ignore failed connections
-    let begin = Instant::now();
-    let mut conn = match conn {
-      Ok(s) => s,
-      Err(e) => return Err(e.into()),
+  /// Get a handle which makes `run` return, once in-flight requests finish.
+  pub fn shutdown_handle(&self) -> ShutdownHandle {
+    self.shared.stop.clone()
+  }
+
+  /// The address actually listened on, which is where to look for the port if
+  /// it was 0 or `port_fallback` kicked in. If there are several, this is the
+  /// first; see `local_addrs` for all of them. Panics if only listening on
+  /// Unix sockets.
+  pub fn local_addr(&self) -> SocketAddr {
+    self.locals[0]
+  }
+
+  /// Every IP address actually listened on
+  pub fn local_addrs(&self) -> &[SocketAddr] {
+    &self.locals
+  }
+
+  /// URLs a browser can use to reach the server, one per address
+  pub fn urls(&self) -> Vec<String> {
+    #[cfg(feature = "tls")]
+    let scheme = match self.shared.tls {
+      Some(_) => "https",
+      None => "http",
     };
-    let request = http::Request::load(&mut conn)?;
-    if self.cfg.log {
-      print!("Serving /{}", request.path)
+    #[cfg(not(feature = "tls"))]
+    let scheme = "http";
+    let mut urls = vec![];
+    for local in &self.locals {
+      let host = match local.ip() {
+        ip if ip.is_unspecified() => "localhost".into(),
+        net::IpAddr::V6(ip) => format!("[{}]", ip),
+        ip => ip.to_string(),
+      };
+      let url = format!("{}://{}:{}/", scheme, host, local.port());
+      if !urls.contains(&url) {
+        urls.push(url);
+      }
+    }
+    // the usual way to put a socket in a URL, e.g. `http+unix://%2Fa.sock/`
+    for socket in &self.sockets {
+      let path = socket.to_string_lossy().replace('/', "%2F");
+      urls.push(format!("{}+unix://{}/", scheme, path));
+    }
+    urls
+  }
+
+  /// Serve until shut down by a `ShutdownHandle`, handling each connection on
+  /// its own thread.
+  pub fn run(&mut self) -> Summary {
+    let shared = &self.shared;
+    thread::scope(|s| {
+      for listener in &self.listeners {
+        s.spawn(move || {
+          loop {
+            let conn = listener.accept();
+            if shared.stop.is_shutdown() {
+              break;
+            }
+            // just ignore failed connections
+            let conn = match conn {
+              Ok(c) => c,
+              Err(_) => continue,
+            };
+            let (id, busy) = match shared.tracker.open(&*conn) {
+              Ok(t) => t,
+              Err(_) => continue,
+            };
+            let shared = shared.clone();
+            thread::spawn(move || {
+              let _ = shared.respond_one(conn, &busy);
+              shared.tracker.close(id);
+            });
+          }
+        });
+      }
+    });
+    let unfinished = match self.shared.stop.deadline() {
+      Some(deadline) => self.shared.tracker.drain(deadline),
+      None => 0,
+    };
+    Summary {
+      requests: self.shared.served.load(Ordering::SeqCst),
+      unfinished,
     }
-    let response = http::Response::to(request, &self.cfg)?;
-    if self.cfg.log {
-      print!(" with {}", response.code());
+  }
+
+  /// Serve one connection at a time, until handling one fails or it's shut
+  /// down.
+  pub fn run_to_fail(&mut self) -> Result<()> {
+    // every listener accepts on its own thread, but they're handled here
+    let (send, recv) = mpsc::channel();
+    for listener in &self.listeners {
+      let (listener, send) = (listener.clone(), send.clone());
+      let stop = self.shared.stop.clone();
+      thread::spawn(move || loop {
+        let conn = listener.accept();
+        if stop.is_shutdown() || send.send(conn).is_err() {
+          break;
+        }
+      });
     }
-    response.write(conn)?;
-    if self.cfg.log {
-      println!(" in {}us", (Instant::now() - begin).as_micros());
+    drop(send);
+    for conn in recv {
+      if self.shared.stop.is_shutdown() {
+        break;
+      }
+      self.shared.respond_one(conn?, &AtomicUsize::new(0))?;
     }
     Ok(())
   }
+}
 
-  pub fn run(&mut self) {
-    for conn in self.listener.incoming() {
-      let _ = self.respond_one(conn);
+impl Shared {
+  /// Wrap a freshly accepted connection in TLS, if that's enabled.
+  fn wrap(&self, conn: Box<dyn Transport>) -> Result<Conn> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &self.tls {
+      let session =
+        rustls::ServerConnection::new(tls.clone()).map_err(TlsFail::Rustls)?;
+      let stream = rustls::StreamOwned::new(session, conn);
+      return Ok(Conn::Tls(Box::new(stream)));
     }
+    Ok(Conn::Plain(conn))
   }
 
-  pub fn run_to_fail(&mut self) -> Result<()> {
-    for conn in self.listener.incoming() {
-      self.respond_one(conn)?;
+  /// Serve a connection, keeping `busy` set to how many requests are being
+  /// responded to on it.
+  fn respond_one(
+    &self,
+    conn: Box<dyn Transport>,
+    busy: &AtomicUsize,
+  ) -> Result<()> {
+    let begin = Instant::now();
+    let _open = self.metrics.as_ref().map(Metrics::opened);
+    let mut conn = BufReader::new(self.wrap(conn)?);
+    let peer = conn.get_ref().peer();
+    let request = http::Request::load(&mut conn)?;
+
+    // HTTP/2 with prior knowledge, or negotiated with ALPN
+    if request.method == "PRI" && request.path == "*" {
+      let mut rest = [0; 6];
+      conn.read_exact(&mut rest)?;
+      if rest != h2::PREFACE[18..] {
+        return Err(ReqFail::InvalidFormat("HTTP/2 preface".into()).into());
+      }
+      return self.serve_h2(conn, peer, None, busy);
+    }
+    if let Some(settings) = h2c_settings(&request, conn.get_ref()) {
+      let out = conn.get_mut();
+      write!(
+        out,
+        "HTTP/1.1 101 Switching Protocols\nConnection: Upgrade\nUpgrade: h2c\n\n"
+      )?;
+      out.flush()?;
+      let mut preface = [0; 24];
+      conn.read_exact(&mut preface)?;
+      if preface != h2::PREFACE {
+        return Err(ReqFail::InvalidFormat("HTTP/2 preface".into()).into());
+      }
+      return self.serve_h2(conn, peer, Some((request, settings)), busy);
     }
+
+    busy.store(1, Ordering::SeqCst);
+    let mut record = Record::new(&request, "HTTP/1.1", begin);
+    record.peer = peer;
+    let response = self.resolve(request, peer, "HTTP/1.1")?;
+    record.status = response.code();
+    record.bytes = response.write(conn.get_mut())?;
+    conn.get_mut().close()?;
+    self.log(record);
+    Ok(())
+  }
+
+  fn serve_h2(
+    &self,
+    mut conn: BufReader<Conn>,
+    peer: Option<net::IpAddr>,
+    upgrade: Option<(Request, Vec<u8>)>,
+    busy: &AtomicUsize,
+  ) -> Result<()> {
+    h2::Session::new(
+      &mut conn,
+      |request| self.resolve(request, peer, "HTTP/2.0"),
+      |mut record: Record| {
+        record.peer = peer;
+        self.log(record)
+      },
+      busy,
+    )
+    .run(upgrade)?;
+    conn.get_mut().close()?;
     Ok(())
   }
+
+  /// Figure out the response to a request, whatever protocol it came over.
+  fn resolve(
+    &self,
+    mut request: Request,
+    peer: Option<net::IpAddr>,
+    protocol: &'static str,
+  ) -> io::Result<Response> {
+    request.peer = peer;
+    let trace = match self.cfg.verbosity {
+      Verbosity::Trace => Some(Trace::new(&request, protocol, self.is_tls())),
+      _ => None,
+    };
+    let files = Files {
+      cfg: &self.cfg,
+      trace: trace.as_ref(),
+    };
+    let inner: &dyn Handler = match &self.handler {
+      Some(handler) => &**handler,
+      None => &files,
+    };
+    let mut layers: Vec<&dyn Layer> =
+      self.layers.iter().map(|l| &**l as &dyn Layer).collect();
+    layers.push(&self.router);
+    let page = self.metrics.as_ref().map(metrics::Page);
+    if let Some(page) = &page {
+      layers.push(page);
+    }
+    let response = Chain {
+      layers: &layers,
+      inner,
+    }
+    .handle(request);
+    if let Some(trace) = trace {
+      trace.print(&response);
+    }
+    response
+  }
+
+  fn is_tls(&self) -> bool {
+    #[cfg(feature = "tls")]
+    return self.tls.is_some();
+    #[cfg(not(feature = "tls"))]
+    false
+  }
+
+  /// Note that a response has been completely sent.
+  fn log(&self, record: Record) {
+    self.served.fetch_add(1, Ordering::SeqCst);
+    if let Some(metrics) = &self.metrics {
+      metrics.record(&record);
+    }
+    if let Some(access) = &self.access {
+      access.write(&record);
+    }
+  }
+}
+
+/// If this is a cleartext request to upgrade to HTTP/2, get the settings it
+/// came with.
+fn h2c_settings(request: &Request, conn: &Conn) -> Option<Vec<u8>> {
+  if !matches!(conn, Conn::Plain(_)) {
+    return None;
+  }
+  let upgrade = request.header("Upgrade")?;
+  if !upgrade
+    .split(',')
+    .any(|p| p.trim().eq_ignore_ascii_case("h2c"))
+  {
+    return None;
+  }
+  // it's base64url, but we only have a decoder for the normal alphabet
+  let settings = request
+    .header("HTTP2-Settings")?
+    .replace('-', "+")
+    .replace('_', "/");
+  auth::base64_decode(&settings)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::Mutex;
+
+  /// A connection whose request is already written, and whose response can
+  /// be checked afterwards.
+  struct Memory {
+    input: io::Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+  }
+
+  impl Read for Memory {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      self.input.read(buf)
+    }
+  }
+
+  impl Write for Memory {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.output.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  impl Transport for Memory {
+    fn peer(&self) -> Option<net::IpAddr> {
+      None
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+      Ok(Box::new(Memory {
+        input: io::Cursor::new(vec![]),
+        output: self.output.clone(),
+      }))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn any_transport() {
+    let server = Httpserv::new(Config {
+      root: "tests/webroot".into(),
+      hostnames: vec!["127.0.0.1:0".into()],
+      ..Default::default()
+    })
+    .unwrap();
+    let output = Arc::new(Mutex::new(vec![]));
+    let conn = Memory {
+      input: io::Cursor::new(b"GET /subdir/file HTTP/1.1\n\n".to_vec()),
+      output: output.clone(),
+    };
+    let busy = AtomicUsize::new(0);
+    server.shared.respond_one(Box::new(conn), &busy).unwrap();
+    let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    assert!(output.starts_with("HTTP/1.1 200 OK\n"), "wrong status");
+    assert!(output.ends_with("\n\n4\n"), "wrong body");
+  }
+
+  #[test]
+  fn spawn_ipv6() {
+    if net::TcpListener::bind("[::1]:0").is_err() {
+      return; // no IPv6 here
+    }
+    for hostname in ["[::1]:8080", "[::1]", "::1"] {
+      let server = Httpserv::spawn(Config {
+        root: "tests/webroot".into(),
+        hostnames: vec![hostname.into()],
+        ..Default::default()
+      })
+      .unwrap_or_else(|e| panic!("{} refused: {}", hostname, e));
+      let addr = server.local_addr();
+      assert_eq!(addr.ip(), net::Ipv6Addr::LOCALHOST, "wrong address");
+      assert_ne!(addr.port(), 8080, "{}: port kept", hostname);
+    }
+  }
 }
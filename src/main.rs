@@ -1,10 +1,101 @@
-use std::{collections::HashMap, fmt, path::Path, time::Instant};
+use std::{
+  collections::HashMap,
+  ffi::OsString,
+  fmt, fs, io,
+  path::{Component, Path, PathBuf},
+  process,
+  time::{Duration, Instant},
+};
 
 use httpserv::*;
 
+mod settings;
+mod signal;
+use settings::{Source, Sources};
+
+const USAGE: &str = "\
+Usage: httpserv [options] [directory] [listen] [ext=type...]
+
+Serves the files in a directory over HTTP.
+
+Arguments:
+  directory                 Where the files to serve are (default: .)
+  listen                    Where to listen, like localhost:8080; several can
+                            be separated by commas (default: localhost:8080)
+  ext=type                  Serve files ending in .ext as MIME type `type`
+
+Options:
+  -r, --root <dir>          Same as `directory`
+  -b, --bind <host[:port]>  Listen here; can be repeated, or separated by
+                            commas. unix:/path/to.sock listens on a Unix socket
+  -p, --port <port>         Use this port for every address listened on
+      --port-fallback       If the port is taken, try the next one up
+      --mime <ext=type>     Serve files ending in .ext as MIME type `type`
+      --mime-types <path>   Load MIME types from a file like /etc/mime.types
+      --charset <[ext=]cs>  Charset of text files (ending in .ext) which don't
+                            say, instead of guessing UTF-8 or Windows-1252
+      --negotiate           Pick between e.g. hero.avif and hero.png by what
+                            the client accepts, even when asked for hero.png
+      --default-language <tag>
+                            Which of e.g. index.en.html and index.de.html to
+                            serve if none are in a language the client accepts
+      --mount <prefix=dir>  Also serve `dir` under the URL `prefix`
+      --auth <user:pass>    Require HTTP Basic authentication as this user
+      --auth-file <path>    Load users from an htpasswd-style file
+      --allow <cidr>        Only answer clients in this range
+      --deny <cidr>         Never answer clients in this range
+      --shutdown-grace <s>  How long requests get to finish on Ctrl-C
+                            (default: 10)
+      --log-format <fmt>    common, combined, or json (default: common)
+      --log-file <path>     Append the access log here instead of printing it
+      --metrics             Report counters at /__httpserv/metrics
+      --tls                 Serve HTTPS with a self-signed certificate
+      --tls-cert <path>     Serve HTTPS with this PEM certificate chain...
+      --tls-key <path>      ...and this PEM private key
+  -q, --quiet               Print nothing while running
+  -v, --verbose             Dump every request and response, with a curl
+                            command to repeat it
+      --verbosity <level>   quiet, normal, or trace (default: normal)
+      --config <path>       Read options from this file, instead of looking
+                            for httpserv.toml
+      --no-config           Don't read any httpserv.toml
+      --print-config        Print the options that would be used, and where
+                            each came from, then exit
+  -h, --help                Print this and exit
+  -V, --version             Print the version and exit
+
+Options can also be written --option=value, and anything after -- is taken as
+an argument, even if it starts with -.
+
+Options are also read from httpserv.toml in the served directory, or else the
+current one, as `option = value` lines, e.g. `port = 3000` or
+`bind = [\"localhost\", \"[::1]\"]`, with MIME types under a [mime] section.
+
+Each option can also be set in the environment as HTTPSERV_<OPTION>, with
+dashes as underscores, e.g. HTTPSERV_PORT=3000 or HTTPSERV_LOG_FORMAT=json.
+Options that can be repeated take a comma-separated list (except auth), and
+MIME types are HTTPSERV_MIME_<EXT>=type. The environment wins over the file,
+and the command line wins over both.";
+
 #[derive(Debug)]
 pub enum ArgFail {
   InvalidFormat(String),
+  MissingValue(String),
+  UnknownFlag(String),
+  /// A value given to a flag which doesn't take one, like `--tls=yes`
+  UnexpectedValue(String),
+  AuthFile(String, io::Error),
+  MimeTypes(String, io::Error),
+  /// A config file couldn't be read, or has something wrong in it
+  ConfigFile(PathBuf, String),
+  /// An environment variable has something wrong with it
+  EnvVar(String, String),
+  #[cfg(not(feature = "tls"))]
+  NoTls,
+  /// Not actually a failure: `--help` was asked for
+  Help,
+  /// Not actually a failure: `--version` was asked for
+  Version,
 }
 
 impl fmt::Display for ArgFail {
@@ -13,78 +104,754 @@ impl fmt::Display for ArgFail {
       ArgFail::InvalidFormat(s) => {
         write!(f, "'{}' is incorrectly formatted", s)
       }
+      ArgFail::MissingValue(s) => write!(f, "'{}' needs a value", s),
+      ArgFail::UnknownFlag(s) => write!(f, "unknown option '{}'", s),
+      ArgFail::UnexpectedValue(s) => write!(f, "'{}' doesn't take a value", s),
+      ArgFail::AuthFile(p, e) | ArgFail::MimeTypes(p, e) => {
+        write!(f, "failed to read '{}': {}", p, e)
+      }
+      ArgFail::ConfigFile(p, e) => write!(f, "in {}: {}", p.display(), e),
+      ArgFail::EnvVar(v, e) => write!(f, "${}: {}", v, e),
+      #[cfg(not(feature = "tls"))]
+      ArgFail::NoTls => write!(
+        f,
+        "TLS support not compiled in; rebuild with `--features tls`"
+      ),
+      ArgFail::Help => write!(f, "{}", USAGE),
+      ArgFail::Version => {
+        write!(f, "httpserv {}", env!("CARGO_PKG_VERSION"))
+      }
     }
   }
 }
 
-fn get_cfg(args: impl Iterator<Item = String>) -> Result<Config, ArgFail> {
+fn parse_cidr(value: String) -> Result<Cidr, ArgFail> {
+  value.parse().map_err(|_| ArgFail::InvalidFormat(value))
+}
+
+/// Check a charset's name looks like one, e.g. `utf-8` or `ISO_8859-1:1987`
+fn parse_charset(name: String) -> Result<String, ArgFail> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || "-_.:".contains(c);
+  match !name.is_empty() && name.chars().all(valid) {
+    true => Ok(name.to_ascii_lowercase()),
+    false => Err(ArgFail::InvalidFormat(name)),
+  }
+}
+
+/// Check a language tag looks like one, e.g. `en` or `pt-BR`
+fn parse_language(tag: String) -> Result<String, ArgFail> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || c == '-';
+  let starts = tag.starts_with(|c: char| c.is_ascii_alphabetic());
+  match starts && tag.chars().all(valid) {
+    true => Ok(tag),
+    false => Err(ArgFail::InvalidFormat(tag)),
+  }
+}
+
+/// Parse `ext=type` or `ext=charset`, allowing a leading `.` on the extension
+fn parse_for_ext(pair: &str) -> Result<(String, String), ArgFail> {
+  match pair.split_once('=') {
+    Some((ext, value)) if !value.is_empty() => {
+      Ok((ext.strip_prefix('.').unwrap_or(ext).into(), value.into()))
+    }
+    _ => Err(ArgFail::InvalidFormat(pair.into())),
+  }
+}
+
+/// Every option, and whether it takes a value
+const OPTIONS: &[(&str, bool)] = &[
+  ("root", true),
+  ("bind", true),
+  ("port", true),
+  ("port-fallback", false),
+  ("mime", true),
+  ("mime-types", true),
+  ("charset", true),
+  ("negotiate", false),
+  ("default-language", true),
+  ("mount", true),
+  ("auth", true),
+  ("auth-file", true),
+  ("allow", true),
+  ("deny", true),
+  ("shutdown-grace", true),
+  ("log-format", true),
+  ("log-file", true),
+  ("verbosity", true),
+  ("quiet", false),
+  ("verbose", false),
+  ("metrics", false),
+  ("tls", false),
+  ("tls-cert", true),
+  ("tls-key", true),
+  // only on the command line
+  ("config", true),
+  ("no-config", false),
+  ("print-config", false),
+  ("help", false),
+  ("version", false),
+];
+
+/// Options which only make sense on the command line
+const CLI_ONLY: &[&str] = &["print-config", "help", "version"];
+
+/// Options which add to each other when given more than once
+const LISTS: &[&str] = &[
+  "bind",
+  "mime-types",
+  "charset",
+  "mount",
+  "auth",
+  "auth-file",
+  "allow",
+  "deny",
+];
+
+/// What environment variables start with to be read as options
+const ENV_PREFIX: &str = "HTTPSERV_";
+
+/// Pick out the options set with environment variables, like `HTTPSERV_PORT`
+/// for `--port`, or `HTTPSERV_MIME_MD` for `--mime md=...`. Returns each
+/// variable's name, the option, and its value.
+#[allow(clippy::type_complexity)]
+fn env_options(
+  vars: impl Iterator<Item = (OsString, OsString)>,
+) -> Result<Vec<(String, String, String)>, ArgFail> {
+  let mut out = vec![];
+  for (name, value) in vars {
+    let name = name.to_string_lossy().into_owned();
+    let option = match name.strip_prefix(ENV_PREFIX) {
+      Some(option) => option.to_ascii_lowercase().replace('_', "-"),
+      None => continue,
+    };
+    let fail = |msg: &str| ArgFail::EnvVar(name.clone(), msg.into());
+    let value = value.into_string().map_err(|_| fail("isn't valid UTF-8"))?;
+    let known = OPTIONS.iter().any(|(o, _)| *o == option);
+    if let Some(ext) = option.strip_prefix("mime-").filter(|_| !known) {
+      out.push((name.clone(), "mime".into(), format!("{}={}", ext, value)));
+      continue;
+    }
+    let takes_value = match OPTIONS.iter().find(|(o, _)| *o == option) {
+      Some(_) if CLI_ONLY.contains(&&option[..]) => None,
+      Some(&(_, takes_value)) => Some(takes_value),
+      None => None,
+    };
+    let values: Vec<String> = match takes_value {
+      None => return Err(fail("isn't an option httpserv has")),
+      // switches can be set like any other boolean variable
+      Some(false) => match &value.to_ascii_lowercase()[..] {
+        "1" | "true" | "yes" | "on" => vec!["true".into()],
+        "" | "0" | "false" | "no" | "off" => vec!["false".into()],
+        _ => return Err(fail("should be true or false")),
+      },
+      // passwords can have commas in them
+      Some(true) if LISTS.contains(&&option[..]) && option != "auth" => {
+        value.split(',').map(|v| v.trim().to_string()).collect()
+      }
+      Some(true) => vec![value],
+    };
+    for value in values {
+      out.push((name.clone(), option.clone(), value));
+    }
+  }
+  out.sort_by(|a, b| a.0.cmp(&b.0));
+  Ok(out)
+}
+
+/// Split the command line into options, with their values if they take one,
+/// and positional arguments.
+#[allow(clippy::type_complexity)]
+fn tokenize(
+  args: impl Iterator<Item = String>,
+) -> Result<(Vec<(String, Option<String>)>, Vec<String>), ArgFail> {
+  let (mut flags, mut positional) = (vec![], vec![]);
   let mut args = args.skip(1);
-  let root = Path::new(&args.next().unwrap_or(".".into())).to_path_buf();
-  let hostname = args.next().unwrap_or("localhost:8080".into());
-  let mut mappings = HashMap::new();
-  mappings.insert("html".into(), "text/html;charset=utf-8".into());
-  mappings.insert("css".into(), "text/css;charset=utf-8".into());
-  mappings.insert("js".into(), "text/javascript;charset=utf-8".into());
-  mappings.insert("png".into(), "image/png".into());
-  mappings.insert("jpg".into(), "image/jpeg".into());
-  mappings.insert("jpeg".into(), "image/jpeg".into());
-  mappings.insert("ico".into(), "image/vnd.microsoft.icon".into());
-  mappings.insert("svg".into(), "image/svg+xml".into());
-  mappings.insert("wasm".into(), "application/wasm".into());
-  mappings.insert("pdf".into(), "application/pdf".into());
-  mappings.insert("pdf".into(), "application/pdf".into());
-  mappings.insert("zip".into(), "application/zip".into());
+  while let Some(arg) = args.next() {
+    let flag = match &arg[..] {
+      "--" => {
+        positional.extend(&mut args);
+        break;
+      }
+      "-r" => "--root",
+      "-b" => "--bind",
+      "-p" => "--port",
+      "-q" => "--quiet",
+      "-v" => "--verbose",
+      "-h" => "--help",
+      "-V" => "--version",
+      a if a.starts_with("--") => a,
+      a if a.starts_with('-') && a != "-" => {
+        return Err(ArgFail::UnknownFlag(arg))
+      }
+      _ => {
+        positional.push(arg);
+        continue;
+      }
+    };
+    // --flag=value works as well as --flag value
+    let (flag, inline) = match flag.split_once('=') {
+      Some((flag, value)) => (flag, Some(value.to_string())),
+      None => (flag, None),
+    };
+    let name = &flag[2..];
+    let takes_value = match OPTIONS.iter().find(|(o, _)| *o == name) {
+      Some(&(_, takes_value)) => takes_value,
+      None => return Err(ArgFail::UnknownFlag(flag.into())),
+    };
+    let value = match (takes_value, inline) {
+      (true, Some(value)) => Some(value),
+      (true, None) => Some(
+        args
+          .next()
+          .ok_or_else(|| ArgFail::MissingValue(flag.into()))?,
+      ),
+      (false, Some(_)) => return Err(ArgFail::UnexpectedValue(flag.into())),
+      (false, None) => None,
+    };
+    match name {
+      "help" => return Err(ArgFail::Help),
+      "version" => return Err(ArgFail::Version),
+      _ => flags.push((name.to_string(), value)),
+    }
+  }
+  Ok((flags, positional))
+}
+
+/// A `Config` being put together, from each source in turn
+struct Builder {
+  root: PathBuf,
+  binds: Vec<String>,
+  port: Option<u16>,
+  mappings: HashMap<OsString, String>,
+  /// Loaded from `--mime-types` files, for `mappings` to override
+  mime_types: HashMap<OsString, String>,
+  charset: Option<String>,
+  charsets: HashMap<OsString, String>,
+  negotiate: bool,
+  default_language: Option<String>,
+  auth: Option<Auth>,
+  allow: Vec<Cidr>,
+  deny: Vec<Cidr>,
+  port_fallback: bool,
+  mounts: Vec<Mount>,
+  shutdown_grace: Duration,
+  log_format: LogFormat,
+  log_file: Option<PathBuf>,
+  verbosity: Verbosity,
+  metrics: bool,
+  #[cfg(feature = "tls")]
+  tls: bool,
+  #[cfg(feature = "tls")]
+  tls_cert: Option<String>,
+  #[cfg(feature = "tls")]
+  tls_key: Option<String>,
+  sources: Sources,
+}
+
+impl Builder {
+  fn new() -> Builder {
+    let mut builder = Builder {
+      root: ".".into(),
+      binds: vec![],
+      port: None,
+      mappings: HashMap::new(),
+      mime_types: HashMap::new(),
+      charset: None,
+      charsets: HashMap::new(),
+      negotiate: false,
+      default_language: None,
+      auth: None,
+      allow: vec![],
+      deny: vec![],
+      port_fallback: false,
+      mounts: vec![],
+      shutdown_grace: Config::default().shutdown_grace,
+      log_format: LogFormat::default(),
+      log_file: None,
+      verbosity: Verbosity::Normal,
+      metrics: false,
+      #[cfg(feature = "tls")]
+      tls: false,
+      #[cfg(feature = "tls")]
+      tls_cert: None,
+      #[cfg(feature = "tls")]
+      tls_key: None,
+      sources: Sources::default(),
+    };
+    let defaults = [
+      ("root", "."),
+      ("bind", "localhost:8080"),
+      ("port-fallback", "false"),
+      ("shutdown-grace", "10"),
+      ("log-format", "common"),
+      ("verbosity", "normal"),
+      ("negotiate", "false"),
+      ("metrics", "false"),
+    ];
+    for (key, value) in defaults {
+      builder
+        .apply(key, Some(value.into()), &Source::Default)
+        .expect("bad default");
+    }
+    builder
+  }
+
+  /// Note where a setting came from, forgetting anything set for the same
+  /// thing by a different source, so that later sources win.
+  fn note(&mut self, group: &str, source: &Source, shown: String) {
+    // both fill in the same users, so a new source for either replaces both
+    let other = match group {
+      "auth" => Some("auth-file"),
+      "auth-file" => Some("auth"),
+      _ => None,
+    };
+    if other.is_some_and(|other| self.sources.forget_unless(other, source)) {
+      self.auth = None;
+    }
+    if !self.sources.set(group, source, shown) {
+      return;
+    }
+    match group {
+      "bind" => self.binds.clear(),
+      "mount" => self.mounts.clear(),
+      "mime-types" => self.mime_types.clear(),
+      "charset" => {
+        self.charset = None;
+        self.charsets.clear();
+      }
+      "auth" | "auth-file" => self.auth = None,
+      "allow" => self.allow.clear(),
+      "deny" => self.deny.clear(),
+      _ => (),
+    }
+  }
+
+  /// Set option `key` from `source`. Switches are on if there's no value.
+  fn apply(
+    &mut self,
+    key: &str,
+    value: Option<String>,
+    source: &Source,
+  ) -> Result<(), ArgFail> {
+    let value = value.unwrap_or_else(|| "true".into());
+    let switch = || match &value[..] {
+      "true" => Ok(true),
+      "false" => Ok(false),
+      _ => Err(ArgFail::InvalidFormat(value.clone())),
+    };
+    let invalid = || ArgFail::InvalidFormat(value.clone());
+    match key {
+      "root" => {
+        self.note(key, source, value.clone());
+        self.root = value.into();
+      }
+      "bind" => {
+        for bind in value.split(',') {
+          self.note(key, source, bind.into());
+          self.binds.push(bind.into());
+        }
+      }
+      "port" => {
+        let port = value.parse().map_err(|_| invalid())?;
+        self.note(key, source, value);
+        self.port = Some(port);
+      }
+      "mime" => {
+        let (ext, mime) = parse_for_ext(&value)?;
+        self.note(&format!("mime.{}", ext), source, mime.clone());
+        self.mappings.insert(ext.into(), mime);
+      }
+      "charset" => {
+        self.note(key, source, value.clone());
+        match value.split_once('=') {
+          Some(_) => {
+            let (ext, charset) = parse_for_ext(&value)?;
+            let charset = parse_charset(charset)?;
+            self.charsets.insert(ext.into(), charset);
+          }
+          None => self.charset = Some(parse_charset(value)?),
+        }
+      }
+      "mime-types" => {
+        self.note(key, source, value.clone());
+        let loaded = load_mime_types(Path::new(&value))
+          .map_err(|e| ArgFail::MimeTypes(value, e))?;
+        self.mime_types.extend(loaded);
+      }
+      "mount" => {
+        let mount = value.parse().map_err(|_| invalid())?;
+        self.note(key, source, value);
+        self.mounts.push(mount);
+      }
+      "auth" => {
+        // don't print passwords
+        let user = value.split(':').next().unwrap_or("");
+        self.note(key, source, format!("{}:<hidden>", user));
+        self
+          .auth
+          .get_or_insert_with(Auth::default)
+          .add_pair(&value)
+          .ok_or_else(invalid)?
+      }
+      "auth-file" => {
+        self.note(key, source, value.clone());
+        self
+          .auth
+          .get_or_insert_with(Auth::default)
+          .load_file(Path::new(&value))
+          .map_err(|e| ArgFail::AuthFile(value, e))?
+      }
+      "allow" | "deny" => {
+        let cidr = parse_cidr(value.clone())?;
+        self.note(key, source, value);
+        match key {
+          "allow" => self.allow.push(cidr),
+          _ => self.deny.push(cidr),
+        }
+      }
+      "port-fallback" => {
+        self.port_fallback = switch()?;
+        self.note(key, source, value);
+      }
+      "shutdown-grace" => {
+        let secs = value.parse().map_err(|_| invalid())?;
+        self.shutdown_grace = Duration::from_secs(secs);
+        self.note(key, source, value);
+      }
+      "log-format" => {
+        self.log_format = value.parse().map_err(|_| invalid())?;
+        self.note(key, source, value);
+      }
+      "log-file" => {
+        self.log_file = Some(value.clone().into());
+        self.note(key, source, value);
+      }
+      "verbosity" => {
+        self.verbosity = value.parse().map_err(|_| invalid())?;
+        self.note(key, source, value);
+      }
+      "quiet" | "verbose" => {
+        if switch()? {
+          self.verbosity = match key {
+            "quiet" => Verbosity::Quiet,
+            _ => Verbosity::Trace,
+          };
+          let shown = self.verbosity.to_string();
+          self.note("verbosity", source, shown);
+        }
+      }
+      "negotiate" => {
+        self.negotiate = switch()?;
+        self.note(key, source, value);
+      }
+      "default-language" => {
+        self.default_language = Some(parse_language(value.clone())?);
+        self.note(key, source, value);
+      }
+      "metrics" => {
+        self.metrics = switch()?;
+        self.note(key, source, value);
+      }
+      #[cfg(feature = "tls")]
+      "tls" => {
+        self.tls = switch()?;
+        self.note(key, source, value);
+      }
+      #[cfg(feature = "tls")]
+      "tls-cert" => {
+        self.tls_cert = Some(value.clone());
+        self.note(key, source, value);
+      }
+      #[cfg(feature = "tls")]
+      "tls-key" => {
+        self.tls_key = Some(value.clone());
+        self.note(key, source, value);
+      }
+      #[cfg(not(feature = "tls"))]
+      "tls" | "tls-cert" | "tls-key" => return Err(ArgFail::NoTls),
+      _ => return Err(ArgFail::UnknownFlag(key.into())),
+    }
+    Ok(())
+  }
+
+  /// Apply everything in the config file at `path`. If it's `confined`,
+  /// because it came with what's being served and so can't be trusted, it
+  /// can't point the root, mounts, or log file outside its own directory.
+  fn load_file(
+    &mut self,
+    path: &Path,
+    text: &str,
+    confined: bool,
+  ) -> Result<(), ArgFail> {
+    let fail = |msg: String| ArgFail::ConfigFile(path.into(), msg);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let lines = settings::parse(text, dir).map_err(|e| fail(e.to_string()))?;
+    let source = Source::File(path.into());
+    for settings::Line { line, key, value } in lines {
+      if let Some(outside) = outside(&key, &value, dir).filter(|_| confined) {
+        return Err(fail(format!(
+          "line {}: {} '{}' is outside {}, so it can only be set from \
+           the command line or a config file outside the served directory",
+          line,
+          key,
+          outside,
+          dir.display()
+        )));
+      }
+      self
+        .apply(&key, Some(value), &source)
+        .map_err(|e| fail(format!("line {}: {}", line, e)))?;
+    }
+    Ok(())
+  }
+
+  fn build(self) -> Result<Config, ArgFail> {
+    #[cfg(feature = "tls")]
+    let tls = match (self.tls_cert, self.tls_key) {
+      (Some(cert), Some(key)) => Some(Tls::Files {
+        cert: cert.into(),
+        key: key.into(),
+      }),
+      (None, None) if self.tls => Some(Tls::SelfSigned),
+      (None, None) => None,
+      (Some(_), None) => return Err(ArgFail::MissingValue("--tls-key".into())),
+      (None, Some(_)) => {
+        return Err(ArgFail::MissingValue("--tls-cert".into()))
+      }
+    };
+    let mut mappings = self.mime_types;
+    mappings.extend(self.mappings);
+    let port = self.port;
+    let hostnames = self
+      .binds
+      .iter()
+      .map(|b| match b.starts_with("unix:") {
+        true => b.clone(),
+        false => with_port(b, port),
+      })
+      .collect();
+    Ok(Config {
+      root: self.root,
+      vfs: None,
+      mounts: self.mounts,
+      hostnames,
+      port_fallback: self.port_fallback,
+      mappings,
+      charset: self.charset,
+      charsets: self.charsets,
+      negotiate: self.negotiate,
+      default_language: self.default_language,
+      verbosity: self.verbosity,
+      log_format: self.log_format,
+      log_file: self.log_file,
+      metrics: self.metrics,
+      auth: self.auth,
+      allow: self.allow,
+      deny: self.deny,
+      shutdown_grace: self.shutdown_grace,
+      #[cfg(feature = "tls")]
+      tls,
+    })
+  }
+}
+
+/// The path `value` gives for `key`, if it's one which is outside `dir`
+fn outside<'a>(key: &str, value: &'a str, dir: &Path) -> Option<&'a str> {
+  let path = match key {
+    "root" | "log-file" => value,
+    "mount" => value.split_once('=')?.1,
+    _ => return None,
+  };
+  let climbs = Path::new(path)
+    .components()
+    .any(|c| c == Component::ParentDir);
+  match Path::new(path).starts_with(dir) && !climbs {
+    true => None,
+    false => Some(path),
+  }
+}
+
+/// The config to run with, where each part of it came from, and whether that
+/// should be printed instead of running
+struct Loaded {
+  cfg: Config,
+  sources: Sources,
+  print: bool,
+  /// The config file read, if any
+  file: Option<PathBuf>,
+}
+
+/// Put together the config from, in increasing order of precedence: a
+/// `httpserv.toml`, if there is one; `HTTPSERV_*` environment variables; and
+/// the command line.
+fn load(
+  args: impl Iterator<Item = String>,
+  vars: impl Iterator<Item = (OsString, OsString)>,
+) -> Result<Loaded, ArgFail> {
+  let (flags, positional) = tokenize(args)?;
+  let env = env_options(vars)?;
+  let last = |name: &str| {
+    let flag = flags.iter().rev().find(|(n, _)| n == name);
+    let var = env.iter().rev().find(|(_, n, _)| n == name);
+    match (flag, var) {
+      (Some((_, value)), _) => value.clone(),
+      (None, Some((_, _, value))) => Some(value.clone()),
+      (None, None) => None,
+    }
+  };
+  let given = |name: &str| flags.iter().any(|(n, _)| n == name);
+  let mut builder = Builder::new();
+  let mut file = None;
+
+  if !given("no-config") && last("no-config").as_deref() != Some("true") {
+    let files = match last("config") {
+      Some(path) => vec![(PathBuf::from(path), true, false)],
+      None => {
+        let root = match positional.first() {
+          Some(root) if !given("root") => Some(root.clone()),
+          _ => last("root"),
+        };
+        let root = root.unwrap_or_else(|| ".".into());
+        let in_root = Path::new(&root).join(settings::FILE_NAME);
+        // whoever made what's being served wrote its file, unless it's the
+        // current directory being served
+        let cwd = fs::canonicalize(".").ok();
+        let confined = fs::canonicalize(&root).ok() != cwd;
+        vec![
+          (in_root, false, confined),
+          (settings::FILE_NAME.into(), false, false),
+        ]
+      }
+    };
+    for (path, required, confined) in files {
+      match fs::read_to_string(&path) {
+        Ok(text) => {
+          builder.load_file(&path, &text, confined)?;
+          file = Some(path);
+          break;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => (),
+        Err(e) => return Err(ArgFail::ConfigFile(path, e.to_string())),
+      }
+    }
+  }
+
+  for (var, name, value) in env {
+    let source = Source::Env(var.clone());
+    match &name[..] {
+      "config" | "no-config" => (),
+      _ => builder
+        .apply(&name, Some(value), &source)
+        .map_err(|e| ArgFail::EnvVar(var, e.to_string()))?,
+    }
+  }
+
+  let mut args = positional.into_iter();
+  if let Some(root) = args.next() {
+    builder.apply("root", Some(root), &Source::Args)?;
+  }
+  if let Some(listen) = args.next() {
+    builder.apply("bind", Some(listen), &Source::Args)?;
+  }
   for pair in args {
-    let eq_idx = pair
-      .find("=")
-      .ok_or_else(|| ArgFail::InvalidFormat(pair.clone()))?;
-    let (ext, mime) = pair.split_at(eq_idx);
-    let (_, mime) = mime.split_at(1);
-    mappings.insert(ext.into(), mime.into());
-  }
-
-  Ok(Config {
-    root,
-    hostname,
-    mappings,
-    log: true,
+    builder.apply("mime", Some(pair), &Source::Args)?;
+  }
+  let print = given("print-config");
+  for (name, value) in flags {
+    match &name[..] {
+      "config" | "no-config" | "print-config" => (),
+      _ => builder.apply(&name, value, &Source::Flags)?,
+    }
+  }
+
+  let sources = std::mem::take(&mut builder.sources);
+  Ok(Loaded {
+    cfg: builder.build()?,
+    sources,
+    print,
+    file,
   })
 }
 
 fn main() {
   let load_start = Instant::now();
 
-  let cfg = match get_cfg(std::env::args()) {
-    Ok(c) => c,
-    Err(e) => {
-      println!("Failed to get config: {:?}", e);
+  let cfg = match load(std::env::args(), std::env::vars_os()) {
+    Ok(loaded) if loaded.print => {
+      print!("{}", loaded.sources.print(LISTS));
+      return;
+    }
+    Ok(loaded) => {
+      // always said, since it can change what's served and where
+      if let Some(file) = &loaded.file {
+        eprintln!("httpserv: using settings from {}", file.display());
+      }
+      loaded.cfg
+    }
+    Err(e @ (ArgFail::Help | ArgFail::Version)) => {
+      println!("{}", e);
       return;
     }
+    Err(e) => {
+      eprintln!("httpserv: {}\nRun `httpserv --help` for usage.", e);
+      process::exit(2);
+    }
   };
+  let quiet = cfg.verbosity == Verbosity::Quiet;
 
   let mut server = match Httpserv::new(cfg) {
     Ok(s) => s,
     Err(e) => {
-      println!("Failed to launch server: {:?}", e);
-      return;
+      eprintln!("httpserv: failed to launch server: {}", e);
+      process::exit(1);
     }
   };
 
-  println!(
-    "Launched in {}us; listening on {}; serving from {}",
-    (Instant::now() - load_start).as_micros(),
-    server.config().hostname,
-    server.config().root.display()
-  );
+  if !quiet {
+    println!(
+      "Launched in {}us; serving {} at:",
+      (Instant::now() - load_start).as_micros(),
+      server.config().root.display()
+    );
+    for url in server.urls() {
+      println!("  {}", url);
+    }
+  }
+
+  let handle = server.shutdown_handle();
+  let grace = server.config().shutdown_grace;
+  signal::on_stop(move || {
+    if !quiet {
+      println!(
+        "Shutting down; waiting up to {}s for requests in progress \
+         (interrupt again to stop now)",
+        grace.as_secs()
+      );
+    }
+    handle.shutdown();
+  });
 
-  server.run();
+  let summary = server.run();
+  if quiet {
+    return;
+  }
+  print!("Served {} requests", summary.requests);
+  match summary.unfinished {
+    0 => println!(),
+    n => println!("; cut off {} still in progress", n),
+  }
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
   use super::*;
   use std::ffi::OsStr;
 
+  fn get_cfg(args: impl Iterator<Item = String>) -> Result<Config, ArgFail> {
+    load(args, std::iter::empty()).map(|loaded| loaded.cfg)
+  }
+
   #[test]
   fn with_no_args() {
     let cfg = get_cfg(vec!["".into()].into_iter());
@@ -95,50 +862,41 @@ mod tests {
         "default root not cwd"
       );
       assert_eq!(
-        cfg.hostname, "localhost:8080",
+        cfg.hostnames,
+        vec!["localhost:8080"],
         "default hostname not localhost:8080"
       );
+      assert!(cfg.mappings.is_empty(), "mappings set by default");
+      for (ext, mime) in [
+        ("html", "text/html"),
+        ("css", "text/css"),
+        ("js", "text/javascript"),
+        ("png", "image/png"),
+        ("jpg", "image/jpeg"),
+        ("jpeg", "image/jpeg"),
+        ("ico", "image/vnd.microsoft.icon"),
+        ("svg", "image/svg+xml"),
+      ] {
+        assert_eq!(mime_type(ext), Some(mime), "built-in {} is wrong", ext);
+      }
       assert_eq!(
-        cfg.mappings.get(OsStr::new("html")),
-        Some(&"text/html;charset=utf-8".into()),
-        "mappings[html] is wrong"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("css")),
-        Some(&"text/css;charset=utf-8".into()),
-        "mappings[css] is wrong"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("js")),
-        Some(&"text/javascript;charset=utf-8".into()),
-        "mappings[js] is wrong"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("png")),
-        Some(&"image/png".into()),
-        "mappings[png] is wrong"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("jpg")),
-        Some(&"image/jpeg".into()),
-        "mappings[jpg] is wrong"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("jpeg")),
-        Some(&"image/jpeg".into()),
-        "mappings[jpeg] is wrong"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("ico")),
-        Some(&"image/vnd.microsoft.icon".into()),
-        "mappings[ico] is wrong"
+        cfg.verbosity,
+        Verbosity::Normal,
+        "not logging requests by default"
       );
+      assert!(cfg.auth.is_none(), "auth required by default");
+      assert!(cfg.allow.is_empty(), "allowlist set by default");
+      assert!(cfg.deny.is_empty(), "denylist set by default");
+      assert!(!cfg.port_fallback, "port fallback on by default");
+      assert!(cfg.mounts.is_empty(), "mounts set by default");
+      assert!(!cfg.metrics, "metrics on by default");
+      assert!(!cfg.negotiate, "negotiating by default");
+      assert_eq!(cfg.default_language, None, "default language set");
       assert_eq!(
-        cfg.mappings.get(OsStr::new("svg")),
-        Some(&"image/svg+xml".into()),
-        "mappings[svg] is wrong"
+        cfg.shutdown_grace,
+        Duration::from_secs(10),
+        "wrong default shutdown grace"
       );
-      assert!(cfg.log, "not logging timings by default");
     } else {
       assert!(false, "Getting config returned error");
     }
@@ -163,7 +921,8 @@ mod tests {
     let cfg = get_cfg(vec!["", "", "laksdla:12313"].into_iter().map(Into::into));
     if let Ok(cfg) = cfg {
       assert_eq!(
-        cfg.hostname, "laksdla:12313",
+        cfg.hostnames,
+        vec!["laksdla:12313"],
         "given hostname doesn't match"
       );
     } else {
@@ -172,49 +931,41 @@ mod tests {
   }
 
   #[test]
-  fn given_mappings() {
-    let cfg = get_cfg(vec!["", "", "", "a=b", "c=d"].into_iter().map(Into::into));
+  fn given_hostnames() {
+    let cfg = get_cfg(
+      vec![
+        "",
+        "",
+        "127.0.0.1:80,[::1]:80",
+        "--port-fallback",
+        "--shutdown-grace",
+        "3",
+      ]
+      .into_iter()
+      .map(Into::into),
+    );
     if let Ok(cfg) = cfg {
+      assert!(cfg.port_fallback, "--port-fallback ignored");
       assert_eq!(
-        cfg.mappings.get(OsStr::new("html")),
-        Some(&"text/html;charset=utf-8".into()),
-        "Old mapping was changed"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("css")),
-        Some(&"text/css;charset=utf-8".into()),
-        "Old mapping was changed"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("js")),
-        Some(&"text/javascript;charset=utf-8".into()),
-        "Old mapping was changed"
+        cfg.shutdown_grace,
+        Duration::from_secs(3),
+        "--shutdown-grace ignored"
       );
       assert_eq!(
-        cfg.mappings.get(OsStr::new("png")),
-        Some(&"image/png".into()),
-        "Old mapping was changed"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("jpg")),
-        Some(&"image/jpeg".into()),
-        "Old mapping was changed"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("jpeg")),
-        Some(&"image/jpeg".into()),
-        "Old mapping was changed"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("ico")),
-        Some(&"image/vnd.microsoft.icon".into()),
-        "Old mapping was changed"
-      );
-      assert_eq!(
-        cfg.mappings.get(OsStr::new("svg")),
-        Some(&"image/svg+xml".into()),
-        "Old mapping was changed"
+        cfg.hostnames,
+        vec!["127.0.0.1:80", "[::1]:80"],
+        "given hostnames don't match"
       );
+    } else {
+      panic!("Getting config returned error");
+    }
+  }
+
+  #[test]
+  fn given_mappings() {
+    let cfg = get_cfg(vec!["", "", "", "a=b", "c=d"].into_iter().map(Into::into));
+    if let Ok(cfg) = cfg {
+      assert_eq!(cfg.mappings.len(), 2, "defaults in mappings");
       assert_eq!(
         cfg.mappings.get(OsStr::new("a")),
         Some(&"b".into()),
@@ -252,4 +1003,513 @@ mod tests {
       assert!(false, "Getting config returned error");
     }
   }
+
+  #[test]
+  fn given_auth() {
+    let cfg = get_cfg(
+      vec!["", "--auth", "user:pass", "some/dir"]
+        .into_iter()
+        .map(Into::into),
+    );
+    if let Ok(cfg) = cfg {
+      assert_eq!(cfg.root, Path::new("some/dir"), "flag broke positionals");
+      let auth = cfg.auth.expect("auth not set");
+      assert!(auth.check(Some("Basic dXNlcjpwYXNz")), "user:pass rejected");
+    } else {
+      panic!("Getting config returned error");
+    }
+  }
+
+  #[test]
+  fn given_mounts() {
+    let cfg = get_cfg(
+      vec!["", "--mount", "/static/=assets", "--mount", "docs=doc"]
+        .into_iter()
+        .map(Into::into),
+    );
+    if let Ok(cfg) = cfg {
+      assert_eq!(
+        cfg.mounts,
+        vec![Mount::new("static", "assets"), Mount::new("docs", "doc")],
+        "wrong mounts"
+      );
+    } else {
+      panic!("Getting config returned error");
+    }
+    let cfg = get_cfg(vec!["", "--mount", "nope"].into_iter().map(Into::into));
+    assert!(
+      matches!(cfg, Err(ArgFail::InvalidFormat(_))),
+      "mount without a directory accepted"
+    );
+  }
+
+  #[test]
+  fn given_log() {
+    let cfg = get_cfg(
+      vec!["", "--log-format", "json", "--log-file", "access.log"]
+        .into_iter()
+        .map(Into::into),
+    );
+    if let Ok(cfg) = cfg {
+      assert_eq!(cfg.log_format, LogFormat::Json, "wrong log format");
+      assert_eq!(cfg.log_file, Some("access.log".into()), "wrong log file");
+    } else {
+      panic!("Getting config returned error");
+    }
+    let cfg = get_cfg(vec!["", "--verbose"].into_iter().map(Into::into));
+    if let Ok(cfg) = cfg {
+      assert_eq!(cfg.verbosity, Verbosity::Trace, "--verbose not tracing");
+    } else {
+      panic!("Getting config returned error");
+    }
+    let args = vec!["", "--log-format", "apache"];
+    let cfg = get_cfg(args.into_iter().map(Into::into));
+    assert!(
+      matches!(cfg, Err(ArgFail::InvalidFormat(_))),
+      "unknown log format accepted"
+    );
+  }
+
+  #[test]
+  fn given_acl() {
+    let cfg = get_cfg(
+      vec![
+        "",
+        "--allow",
+        "10.0.0.0/8",
+        "--deny",
+        "10.0.0.1",
+        "--allow",
+        "::1",
+      ]
+      .into_iter()
+      .map(Into::into),
+    );
+    if let Ok(cfg) = cfg {
+      let allow: Vec<_> = cfg.allow.iter().map(ToString::to_string).collect();
+      assert_eq!(allow, vec!["10.0.0.0/8", "::1/128"], "wrong allowlist");
+      let deny: Vec<_> = cfg.deny.iter().map(ToString::to_string).collect();
+      assert_eq!(deny, vec!["10.0.0.1/32"], "wrong denylist");
+    } else {
+      panic!("Getting config returned error");
+    }
+  }
+
+  #[cfg(feature = "tls")]
+  #[test]
+  fn given_tls() {
+    let get = |v: Vec<&str>| get_cfg(v.into_iter().map(Into::into));
+    let cfg = get(vec!["", "--tls"]).expect("--tls rejected");
+    assert_eq!(cfg.tls, Some(Tls::SelfSigned), "--tls not self-signed");
+    let cfg = get(vec!["", "--tls-key", "k.pem", "--tls-cert", "c.pem"])
+      .expect("--tls-cert/--tls-key rejected");
+    assert_eq!(
+      cfg.tls,
+      Some(Tls::Files {
+        cert: "c.pem".into(),
+        key: "k.pem".into()
+      }),
+      "wrong cert/key"
+    );
+    assert!(
+      get(vec!["", "--tls-cert", "c.pem"]).is_err(),
+      "cert without key accepted"
+    );
+  }
+
+  #[cfg(not(feature = "tls"))]
+  #[test]
+  fn no_tls() {
+    let cfg = get_cfg(vec!["", "--tls"].into_iter().map(Into::into));
+    assert!(
+      matches!(cfg, Err(ArgFail::NoTls)),
+      "--tls accepted without TLS"
+    );
+  }
+
+  #[test]
+  fn bad_flags() {
+    let get = |v: Vec<&str>| get_cfg(v.into_iter().map(Into::into));
+    assert!(
+      matches!(get(vec!["", "--auth"]), Err(ArgFail::MissingValue(_))),
+      "missing flag value accepted"
+    );
+    assert!(
+      matches!(
+        get(vec!["", "--auth", "nope"]),
+        Err(ArgFail::InvalidFormat(_))
+      ),
+      "malformed user:pass accepted"
+    );
+    assert!(
+      matches!(get(vec!["", "--wat", "x"]), Err(ArgFail::UnknownFlag(_))),
+      "unknown flag accepted"
+    );
+    assert!(
+      matches!(
+        get(vec!["", "--allow", "10.0/8"]),
+        Err(ArgFail::InvalidFormat(_))
+      ),
+      "malformed CIDR accepted"
+    );
+    assert!(
+      matches!(get(vec!["", "-x"]), Err(ArgFail::UnknownFlag(_))),
+      "unknown short flag accepted"
+    );
+    assert!(
+      matches!(
+        get(vec!["", "--metrics=yes"]),
+        Err(ArgFail::UnexpectedValue(_))
+      ),
+      "value for a switch accepted"
+    );
+    assert!(
+      matches!(
+        get(vec!["", "--charset", "md=utf 8"]),
+        Err(ArgFail::InvalidFormat(_))
+      ),
+      "malformed charset accepted"
+    );
+    assert!(
+      matches!(
+        get(vec!["", "--default-language", "en_US"]),
+        Err(ArgFail::InvalidFormat(_))
+      ),
+      "malformed language accepted"
+    );
+    assert!(
+      matches!(
+        get(vec!["", "--port", "http"]),
+        Err(ArgFail::InvalidFormat(_))
+      ),
+      "non-numeric port accepted"
+    );
+    assert!(matches!(get(vec!["", "x", "-h"]), Err(ArgFail::Help)));
+    assert!(matches!(get(vec!["", "--version"]), Err(ArgFail::Version)));
+  }
+
+  #[test]
+  fn named_flags() {
+    let cfg = get_cfg(
+      vec![
+        "",
+        "--root",
+        "site",
+        "--bind=0.0.0.0,[::]",
+        "-b",
+        "unix:/tmp/x.sock",
+        "-p",
+        "3000",
+        "--mime",
+        ".foo=text/foo",
+        "--mime=ico=image/x-icon",
+        "--charset",
+        "Latin1",
+        "--charset=.md=utf-8",
+        "--negotiate",
+        "--default-language=pt-BR",
+        "-q",
+      ]
+      .into_iter()
+      .map(Into::into),
+    );
+    if let Ok(cfg) = cfg {
+      assert_eq!(cfg.root, Path::new("site"), "--root ignored");
+      assert_eq!(
+        cfg.hostnames,
+        vec!["0.0.0.0:3000", "[::]:3000", "unix:/tmp/x.sock"],
+        "wrong hostnames"
+      );
+      assert_eq!(
+        cfg.mappings.get(OsStr::new("foo")),
+        Some(&"text/foo".into()),
+        "--mime ignored"
+      );
+      assert_eq!(
+        cfg.mappings.get(OsStr::new("ico")),
+        Some(&"image/x-icon".into()),
+        "--mime didn't override a default"
+      );
+      assert_eq!(cfg.charset, Some("latin1".into()), "--charset ignored");
+      assert_eq!(
+        cfg.charsets.get(OsStr::new("md")),
+        Some(&"utf-8".into()),
+        "--charset for an extension ignored"
+      );
+      assert!(cfg.negotiate, "--negotiate ignored");
+      assert_eq!(cfg.default_language, Some("pt-BR".into()));
+      assert_eq!(cfg.verbosity, Verbosity::Quiet, "--quiet ignored");
+    } else {
+      panic!("Getting config returned error");
+    }
+    // the flags win over positionals, and -- ends the flags
+    let cfg = get_cfg(
+      vec!["", "-r", "a", "--port", "80", "--", "-b", "localhost:1"]
+        .into_iter()
+        .map(Into::into),
+    );
+    if let Ok(cfg) = cfg {
+      assert_eq!(cfg.root, Path::new("a"), "positional beat --root");
+      assert_eq!(cfg.hostnames, vec!["localhost:80"], "--port ignored");
+    } else {
+      panic!("Getting config returned error");
+    }
+  }
+
+  #[test]
+  fn config_file() {
+    let dir = std::env::temp_dir()
+      .join(format!("httpserv-cfg-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("httpserv.toml");
+    fs::write(
+      &file,
+      "bind = [\"127.0.0.1:1\", \"[::1]:1\"]\n\
+       metrics = true\n\
+       mount = \"/a=assets\" # relative to the file\n\
+       [mime]\n\
+       foo = \"text/foo\"\n",
+    )
+    .unwrap();
+    let root = dir.to_string_lossy().into_owned();
+    let args = |extra: &[&str]| {
+      let mut args = vec!["".to_string(), root.clone()];
+      args.extend(extra.iter().map(|a| a.to_string()));
+      args.into_iter()
+    };
+
+    let loaded = load(
+      args(&["--bind", "localhost:2", "--print-config"]),
+      std::iter::empty(),
+    );
+    let loaded = loaded.expect("failed to load");
+    assert!(loaded.print, "--print-config ignored");
+    assert_eq!(loaded.file, Some(file.clone()), "wrong file reported");
+    let cfg = loaded.cfg;
+    assert!(cfg.metrics, "file ignored");
+    assert_eq!(cfg.hostnames, vec!["localhost:2"], "file beat command line");
+    assert_eq!(cfg.mounts, vec![Mount::new("a", dir.join("assets"))]);
+    assert_eq!(
+      cfg.mappings.get(OsStr::new("foo")),
+      Some(&"text/foo".into()),
+      "[mime] ignored"
+    );
+    let printed = loaded.sources.print(LISTS);
+    let from_file = format!("metrics = true # {}\n", file.display());
+    assert!(printed.contains(&from_file), "wrong source:\n{}", printed);
+    assert!(printed.contains("bind = [\"localhost:2\"] # command line\n"));
+    assert!(printed.contains("port-fallback = false # default\n"));
+
+    let cfg = get_cfg(args(&["--no-config"])).expect("failed to load");
+    assert!(!cfg.metrics, "--no-config ignored");
+
+    // a file in the served directory can't reach outside it
+    for line in [
+      "mount = \"/etc=/etc\"",
+      "mount = \"/up=../\"",
+      "log-file = \"/tmp/x.log\"",
+      "root = \"..\"",
+    ] {
+      fs::write(&file, line).unwrap();
+      match get_cfg(args(&[])) {
+        Err(ArgFail::ConfigFile(..)) => (),
+        other => panic!("{} accepted: {:?}", line, other.map(|c| c.mounts)),
+      }
+    }
+    // unless it's given on purpose
+    fs::write(&file, "mount = \"/etc=/etc\"").unwrap();
+    let path = file.to_string_lossy().into_owned();
+    let cfg = get_cfg(args(&["--config", &path])).expect("--config refused");
+    assert_eq!(cfg.mounts, vec![Mount::new("etc", "/etc")], "confined");
+
+    fs::write(&file, "metrics = true\nmetrics = maybe\n").unwrap();
+    let err = get_cfg(args(&[])).expect_err("bad file accepted");
+    assert!(
+      err
+        .to_string()
+        .ends_with("line 2: 'maybe' is incorrectly formatted"),
+      "wrong error: {}",
+      err
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    let cfg = get_cfg(args(&["--config", "nope.toml"]));
+    assert!(
+      matches!(cfg, Err(ArgFail::ConfigFile(..))),
+      "missing --config file ignored"
+    );
+  }
+
+  #[test]
+  fn mime_types() {
+    let dir = std::env::temp_dir().join("httpserv-mime-types-test");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mime.types");
+    fs::write(&path, "text/x-a a b\nimage/x-png png\n").unwrap();
+    let path = path.to_string_lossy().into_owned();
+    let args = ["", "--no-config", "--mime-types", &path, "--mime", "b=c/d"];
+    let cfg =
+      get_cfg(args.iter().map(|a| a.to_string())).expect("failed to load");
+    let get = |ext: &str| cfg.mappings.get(OsStr::new(ext)).cloned();
+    assert_eq!(get("a"), Some("text/x-a".into()), "file not loaded");
+    assert_eq!(get("png"), Some("image/x-png".into()), "built-in won");
+    assert_eq!(get("b"), Some("c/d".into()), "file beat --mime");
+    // even when the file's given later
+    let args = ["", "--no-config", "--mime", "b=c/d", "--mime-types", &path];
+    let cfg =
+      get_cfg(args.iter().map(|a| a.to_string())).expect("failed to load");
+    assert_eq!(cfg.mappings.get(OsStr::new("b")), Some(&"c/d".into()));
+    fs::remove_dir_all(&dir).unwrap();
+
+    let args = ["", "--no-config", "--mime-types", "/nonexistent/mime.types"];
+    match get_cfg(args.iter().map(|a| a.to_string())) {
+      Err(ArgFail::MimeTypes(..)) => (),
+      other => panic!("missing file gave {:?}", other),
+    }
+  }
+
+  #[test]
+  fn charset_sources() {
+    let dir = std::env::temp_dir()
+      .join(format!("httpserv-charset-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+      dir.join("httpserv.toml"),
+      "charset = [\"latin1\", \"md=utf-16le\"]\n",
+    )
+    .unwrap();
+    let root = dir.to_string_lossy().into_owned();
+    let load_with = |flags: &[&str], vars: &[(&str, &str)]| {
+      let mut args = vec!["".to_string(), root.clone()];
+      args.extend(flags.iter().map(|a| a.to_string()));
+      let vars = vars.iter().map(|(n, v)| (n.into(), v.into()));
+      load(args.into_iter(), vars).expect("failed to load").cfg
+    };
+    let charsets = |cfg: &Config| {
+      let md = cfg.charsets.get(OsStr::new("md")).cloned();
+      let txt = cfg.charsets.get(OsStr::new("txt")).cloned();
+      (cfg.charset.clone(), md, txt)
+    };
+    let some = |cs: &str| Some(cs.to_string());
+
+    let cfg = load_with(&[], &[]);
+    assert_eq!(charsets(&cfg), (some("latin1"), some("utf-16le"), None));
+    // whichever form is given, it replaces everything from the file
+    for (flags, vars) in [
+      (&["--charset", "koi8-r"][..], &[][..]),
+      (&[], &[("HTTPSERV_CHARSET", "koi8-r")]),
+    ] {
+      let cfg = load_with(flags, vars);
+      assert_eq!(charsets(&cfg), (some("koi8-r"), None, None), "file won");
+    }
+    for (flags, vars) in [
+      (&["--charset", "txt=koi8-r"][..], &[][..]),
+      (&[], &[("HTTPSERV_CHARSET", "txt=koi8-r")]),
+    ] {
+      let cfg = load_with(flags, vars);
+      assert_eq!(charsets(&cfg), (None, None, some("koi8-r")), "file won");
+    }
+    // and the command line beats the environment
+    let cfg = load_with(
+      &["--charset", "utf-8"],
+      &[("HTTPSERV_CHARSET", "koi8-r, md=koi8-r")],
+    );
+    assert_eq!(charsets(&cfg), (some("utf-8"), None, None), "env won");
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn env_vars() {
+    let load_env = |args: &[&str], vars: &[(&str, &str)]| {
+      let args = args.iter().map(|a| a.to_string());
+      let vars = vars.iter().map(|(n, v)| (n.into(), v.into()));
+      load(args, vars)
+    };
+    let loaded = load_env(
+      &["", "--no-config", "--port", "3"],
+      &[
+        ("HTTPSERV_ROOT", "site"),
+        ("HTTPSERV_BIND", "127.0.0.1, [::1]"),
+        ("HTTPSERV_PORT", "2"),
+        ("HTTPSERV_MIME_MD", "text/markdown"),
+        ("HTTPSERV_PORT_FALLBACK", "1"),
+        ("HTTPSERV_METRICS", "off"),
+        ("PATH", "/bin"),
+      ],
+    );
+    let loaded = loaded.expect("failed to load");
+    let cfg = loaded.cfg;
+    assert_eq!(cfg.root, Path::new("site"), "$HTTPSERV_ROOT ignored");
+    assert_eq!(cfg.hostnames, vec!["127.0.0.1:3", "[::1]:3"]);
+    assert_eq!(
+      cfg.mappings.get(OsStr::new("md")),
+      Some(&"text/markdown".into()),
+      "$HTTPSERV_MIME_MD ignored"
+    );
+    assert!(cfg.port_fallback, "$HTTPSERV_PORT_FALLBACK ignored");
+    assert!(!cfg.metrics, "$HTTPSERV_METRICS ignored");
+    let printed = loaded.sources.print(LISTS);
+    assert!(printed.contains("port = 3 # command line\n"), "env won");
+    assert!(printed.contains("root = \"site\" # $HTTPSERV_ROOT\n"));
+
+    // positional arguments still beat the environment
+    let cfg = load_env(&["", "--no-config", "dir"], &[("HTTPSERV_ROOT", "x")]);
+    assert_eq!(cfg.expect("failed to load").cfg.root, Path::new("dir"));
+
+    for (var, value, msg) in [
+      ("HTTPSERV_PORT", "http", "$HTTPSERV_PORT: 'http' is"),
+      ("HTTPSERV_METRICS", "maybe", "$HTTPSERV_METRICS: should be"),
+      ("HTTPSERV_MIME_TYPES", "/x", "$HTTPSERV_MIME_TYPES: failed"),
+      ("HTTPSERV_PROT", "80", "$HTTPSERV_PROT: isn't an option"),
+      ("HTTPSERV_HELP", "1", "$HTTPSERV_HELP: isn't an option"),
+    ] {
+      let err = match load_env(&["", "--no-config"], &[(var, value)]) {
+        Ok(_) => panic!("accepted {}={}", var, value),
+        Err(e) => e.to_string(),
+      };
+      assert!(err.starts_with(msg), "wrong error for {}: {}", var, err);
+    }
+  }
+
+  #[test]
+  fn auth_sources() {
+    let dir = std::env::temp_dir()
+      .join(format!("httpserv-auth-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let users = dir.join("users");
+    let hash =
+      "3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d";
+    fs::write(&users, format!("f:{{SHA256}}{}\n", hash)).unwrap();
+    let users = users.to_string_lossy().into_owned();
+    let root = dir.to_string_lossy().into_owned();
+    let load_with = |flags: &[&str], vars: &[(&str, &str)]| {
+      let mut args = vec!["".to_string(), root.clone()];
+      args.extend(flags.iter().map(|a| a.to_string()));
+      let vars = vars.iter().map(|(n, v)| (n.into(), v.into()));
+      load(args.into_iter(), vars).expect("failed to load").cfg
+    };
+    // who of a, e and f (all with password "b") gets in
+    let allowed = |cfg: &Config| {
+      let auth = cfg.auth.as_ref().expect("no auth");
+      let check = |basic| auth.check(Some(&format!("Basic {}", basic)));
+      (check("YTpi"), check("ZTpi"), check("Zjpi"))
+    };
+
+    // the two forms count as one option, so either replaces the other
+    fs::write(dir.join("httpserv.toml"), "auth = \"a:b\"\n").unwrap();
+    for (flags, vars) in [
+      (&["--auth-file", &users[..]][..], &[][..]),
+      (&[], &[("HTTPSERV_AUTH_FILE", &users[..])]),
+    ] {
+      let cfg = load_with(flags, vars);
+      assert_eq!(allowed(&cfg), (false, false, true), "file auth kept");
+    }
+    fs::write(dir.join("httpserv.toml"), "auth-file = \"users\"\n").unwrap();
+    let cfg = load_with(&["--auth", "e:b"], &[]);
+    assert_eq!(allowed(&cfg), (false, true, false), "file auth-file kept");
+    // but given together from the same place, both count
+    let cfg = load_with(&["--auth", "e:b", "--auth-file", &users], &[]);
+    assert_eq!(allowed(&cfg), (false, true, true), "--auth dropped");
+    fs::remove_dir_all(&dir).unwrap();
+  }
 }
@@ -0,0 +1,401 @@
+//! Counters of what's been served, for seeing what a page load actually
+//! costs. They're all atomics, so keeping them up to date doesn't slow
+//! requests down.
+
+use std::{
+  collections::HashMap,
+  fmt::Write as _,
+  io,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    RwLock,
+  },
+  time::Instant,
+};
+
+use crate::{
+  access::{json_escape, Record},
+  http::{Request, Response},
+  Handler, Layer,
+};
+
+/// Where the metrics are served, unless there's a real file there
+pub(crate) const PATH: &str = "__httpserv/metrics";
+
+/// Methods counted separately; anything else is counted as `other`
+const METHODS: [&str; 7] =
+  ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+/// Upper bounds of the latency histogram's buckets, in microseconds
+const BUCKETS: [u64; 10] = [
+  100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+  5_000_000,
+];
+
+/// How many distinct paths get their own count, so a crawler can't use up
+/// all the memory
+const MAX_PATHS: usize = 1024;
+
+/// How many of the most requested paths are reported
+const TOP_PATHS: usize = 10;
+
+pub(crate) struct Metrics {
+  start: Instant,
+  /// Indexed by status code, minus 100
+  statuses: [AtomicU64; 500],
+  /// Indexed like `METHODS`, with one more at the end for anything else
+  methods: [AtomicU64; METHODS.len() + 1],
+  bytes: AtomicU64,
+  /// Not cumulative; the last one is for anything slower than every bucket
+  latency: [AtomicU64; BUCKETS.len() + 1],
+  latency_us: AtomicU64,
+  paths: RwLock<HashMap<String, AtomicU64>>,
+  /// Requests for paths past the first `MAX_PATHS`
+  other_paths: AtomicU64,
+  open: AtomicUsize,
+}
+
+/// Counts a connection as open until it's dropped.
+pub(crate) struct OpenConn<'a>(&'a AtomicUsize);
+
+impl Drop for OpenConn<'_> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics {
+      start: Instant::now(),
+      statuses: std::array::from_fn(|_| AtomicU64::new(0)),
+      methods: std::array::from_fn(|_| AtomicU64::new(0)),
+      bytes: AtomicU64::new(0),
+      latency: std::array::from_fn(|_| AtomicU64::new(0)),
+      latency_us: AtomicU64::new(0),
+      paths: RwLock::new(HashMap::new()),
+      other_paths: AtomicU64::new(0),
+      open: AtomicUsize::new(0),
+    }
+  }
+
+  /// Note a connection opening, until the returned guard is dropped.
+  pub fn opened(&self) -> OpenConn<'_> {
+    self.open.fetch_add(1, Ordering::SeqCst);
+    OpenConn(&self.open)
+  }
+
+  /// Count a response which has been completely sent.
+  pub fn record(&self, record: &Record) {
+    let status = (record.status as usize).checked_sub(100);
+    if let Some(count) = status.and_then(|s| self.statuses.get(s)) {
+      count.fetch_add(1, Ordering::Relaxed);
+    }
+    let method = METHODS
+      .iter()
+      .position(|m| *m == record.method)
+      .unwrap_or(METHODS.len());
+    self.methods[method].fetch_add(1, Ordering::Relaxed);
+    self.bytes.fetch_add(record.bytes, Ordering::Relaxed);
+
+    let took = record.begin.elapsed().as_micros() as u64;
+    let bucket = BUCKETS
+      .iter()
+      .position(|&b| took <= b)
+      .unwrap_or(BUCKETS.len());
+    self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    self.latency_us.fetch_add(took, Ordering::Relaxed);
+
+    let path = format!("/{}", record.path);
+    let paths = self.paths.read().unwrap_or_else(|e| e.into_inner());
+    if let Some(count) = paths.get(&path) {
+      count.fetch_add(1, Ordering::Relaxed);
+      return;
+    }
+    drop(paths);
+    let mut paths = self.paths.write().unwrap_or_else(|e| e.into_inner());
+    if paths.len() < MAX_PATHS || paths.contains_key(&path) {
+      let count = paths.entry(path).or_insert_with(|| AtomicU64::new(0));
+      count.fetch_add(1, Ordering::Relaxed);
+    } else {
+      self.other_paths.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  /// Each status code that's been sent, with how many times
+  fn statuses(&self) -> Vec<(usize, u64)> {
+    let counts = self.statuses.iter().map(|c| c.load(Ordering::Relaxed));
+    (100..).zip(counts).filter(|&(_, n)| n > 0).collect()
+  }
+
+  fn methods(&self) -> Vec<(&'static str, u64)> {
+    let counts = self.methods.iter().map(|c| c.load(Ordering::Relaxed));
+    METHODS
+      .iter()
+      .copied()
+      .chain(Some("other"))
+      .zip(counts)
+      .collect()
+  }
+
+  /// Each bucket's upper bound in seconds, or `None` for infinity, and how
+  /// many requests took at most that long
+  fn histogram(&self) -> Vec<(Option<f64>, u64)> {
+    let mut total = 0;
+    let bounds = BUCKETS
+      .iter()
+      .map(|&b| Some(b as f64 / 1e6))
+      .chain(Some(None));
+    bounds
+      .zip(&self.latency)
+      .map(|(bound, count)| {
+        total += count.load(Ordering::Relaxed);
+        (bound, total)
+      })
+      .collect()
+  }
+
+  /// The most requested paths, most first
+  fn top_paths(&self) -> Vec<(String, u64)> {
+    let paths = self.paths.read().unwrap_or_else(|e| e.into_inner());
+    let mut top: Vec<_> = paths
+      .iter()
+      .map(|(path, count)| (path.clone(), count.load(Ordering::Relaxed)))
+      .collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(TOP_PATHS);
+    top
+  }
+
+  /// In Prometheus's text exposition format
+  pub fn prometheus(&self) -> String {
+    let mut out = String::new();
+    let mut metric =
+      |name: &str, kind: &str, help: &str, values: Vec<String>| {
+        let _ = writeln!(out, "# HELP httpserv_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE httpserv_{} {}", name, kind);
+        for value in values {
+          let _ = writeln!(out, "httpserv_{}", value);
+        }
+      };
+    let uptime = self.start.elapsed().as_secs_f64();
+    metric(
+      "uptime_seconds",
+      "gauge",
+      "Seconds since the server started.",
+      vec![format!("uptime_seconds {}", uptime)],
+    );
+    metric(
+      "open_connections",
+      "gauge",
+      "Connections currently open.",
+      vec![format!(
+        "open_connections {}",
+        self.open.load(Ordering::SeqCst)
+      )],
+    );
+    let bytes = self.bytes.load(Ordering::Relaxed);
+    metric(
+      "sent_bytes_total",
+      "counter",
+      "Bytes of response bodies sent.",
+      vec![format!("sent_bytes_total {}", bytes)],
+    );
+    metric(
+      "responses_total",
+      "counter",
+      "Responses sent, by status code.",
+      self
+        .statuses()
+        .into_iter()
+        .map(|(code, n)| {
+          format!("responses_total{{status=\"{}\"}} {}", code, n)
+        })
+        .collect(),
+    );
+    metric(
+      "requests_total",
+      "counter",
+      "Requests answered, by method.",
+      self
+        .methods()
+        .into_iter()
+        .map(|(method, n)| {
+          format!("requests_total{{method=\"{}\"}} {}", method, n)
+        })
+        .collect(),
+    );
+    let histogram = self.histogram();
+    let count = histogram.last().map_or(0, |&(_, n)| n);
+    let mut durations: Vec<_> = histogram
+      .into_iter()
+      .map(|(bound, n)| {
+        let le = bound.map_or("+Inf".into(), |b| b.to_string());
+        format!("request_duration_seconds_bucket{{le=\"{}\"}} {}", le, n)
+      })
+      .collect();
+    let sum = self.latency_us.load(Ordering::Relaxed) as f64 / 1e6;
+    durations.push(format!("request_duration_seconds_sum {}", sum));
+    durations.push(format!("request_duration_seconds_count {}", count));
+    metric(
+      "request_duration_seconds",
+      "histogram",
+      "Time from a request arriving to its response being sent.",
+      durations,
+    );
+    metric(
+      "path_requests_total",
+      "counter",
+      "Requests for the most requested paths.",
+      self
+        .top_paths()
+        .into_iter()
+        .map(|(path, n)| {
+          format!("path_requests_total{{path=\"{}\"}} {}", label(&path), n)
+        })
+        .collect(),
+    );
+    out
+  }
+
+  /// As a JSON object
+  pub fn json(&self) -> String {
+    let list = |items: Vec<String>| items.join(",");
+    let statuses = self
+      .statuses()
+      .into_iter()
+      .map(|(code, n)| format!("\"{}\":{}", code, n))
+      .collect();
+    let methods = self
+      .methods()
+      .into_iter()
+      .map(|(method, n)| format!("\"{}\":{}", method, n))
+      .collect();
+    let buckets = self
+      .histogram()
+      .into_iter()
+      .map(|(bound, n)| match bound {
+        Some(b) => format!("{{\"le\":{},\"count\":{}}}", b, n),
+        None => format!("{{\"le\":\"+Inf\",\"count\":{}}}", n),
+      })
+      .collect();
+    let paths = self
+      .top_paths()
+      .into_iter()
+      .map(|(path, n)| {
+        format!("{{\"path\":\"{}\",\"requests\":{}}}", json_escape(&path), n)
+      })
+      .collect();
+    format!(
+      "{{\"uptime_seconds\":{},\"open_connections\":{},\"sent_bytes\":{},\
+       \"responses\":{{{}}},\"requests\":{{{}}},\
+       \"duration_seconds\":{{\"buckets\":[{}],\"sum\":{},\"count\":{}}},\
+       \"top_paths\":[{}],\"other_paths\":{}}}\n",
+      self.start.elapsed().as_secs_f64(),
+      self.open.load(Ordering::SeqCst),
+      self.bytes.load(Ordering::Relaxed),
+      list(statuses),
+      list(methods),
+      list(buckets),
+      self.latency_us.load(Ordering::Relaxed) as f64 / 1e6,
+      self.histogram().last().map_or(0, |&(_, n)| n),
+      list(paths),
+      self.other_paths.load(Ordering::Relaxed),
+    )
+  }
+}
+
+/// Escape a Prometheus label value
+fn label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+/// Serves the metrics at `PATH`, if nothing further in would answer there
+/// itself, so they never hide a real file.
+pub(crate) struct Page<'a>(pub &'a Metrics);
+
+impl Layer for Page<'_> {
+  fn handle(
+    &self,
+    request: Request,
+    next: &dyn Handler,
+  ) -> io::Result<Response> {
+    if request.path != PATH {
+      return next.handle(request);
+    }
+    let json = request.query.split('&').any(|p| p == "format=json")
+      || request
+        .header("Accept")
+        .is_some_and(|a| a.contains("application/json"));
+    Ok(match next.handle(request)? {
+      Response::NotFound if json => {
+        Response::bytes("application/json", self.0.json())
+      }
+      Response::NotFound => Response::bytes(
+        "text/plain; version=0.0.4; charset=utf-8",
+        self.0.prometheus(),
+      ),
+      response => response,
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn record(method: &str, path: &str, status: u16, bytes: u64) -> Record {
+    let request = Request {
+      method: method.into(),
+      path: path.into(),
+      query: String::new(),
+      headers: vec![],
+      peer: None,
+    };
+    let mut record = Record::new(&request, "HTTP/1.1", Instant::now());
+    record.status = status;
+    record.bytes = bytes;
+    record
+  }
+
+  #[test]
+  fn counting() {
+    let metrics = Metrics::new();
+    metrics.record(&record("GET", "", 200, 100));
+    metrics.record(&record("GET", "a \"b\"", 404, 0));
+    metrics.record(&record("BREW", "", 200, 20));
+    let open = metrics.opened();
+
+    let text = metrics.prometheus();
+    for line in [
+      "httpserv_open_connections 1",
+      "httpserv_sent_bytes_total 120",
+      "httpserv_responses_total{status=\"200\"} 2",
+      "httpserv_responses_total{status=\"404\"} 1",
+      "httpserv_requests_total{method=\"GET\"} 2",
+      "httpserv_requests_total{method=\"other\"} 1",
+      "httpserv_request_duration_seconds_bucket{le=\"+Inf\"} 3",
+      "httpserv_request_duration_seconds_count 3",
+      "httpserv_path_requests_total{path=\"/\"} 2",
+      "httpserv_path_requests_total{path=\"/a \\\"b\\\"\"} 1",
+    ] {
+      assert!(text.contains(&format!("\n{}\n", line)), "no {}", line);
+    }
+    assert!(!text.contains("status=\"500\""), "unsent status reported");
+
+    drop(open);
+    let json = metrics.json();
+    assert!(
+      json.contains("\"open_connections\":0,"),
+      "connection not closed"
+    );
+    assert!(json.contains("\"responses\":{\"200\":2,\"404\":1}"));
+    assert!(json.contains(
+      "\"top_paths\":[{\"path\":\"/\",\"requests\":2},\
+       {\"path\":\"/a \\\"b\\\"\",\"requests\":1}]"
+    ));
+  }
+}
@@ -0,0 +1,167 @@
+//! Which MIME type to serve each file extension as, when nothing's been
+//! configured for it.
+
+use std::{collections::HashMap, ffi::OsString, fs, io, path::Path};
+
+/// The types for extensions common on the web, sorted by extension so they
+/// can be binary searched. Text types get a charset once it's known what
+/// each file's in.
+const BUILTIN: &[(&str, &str)] = &[
+  ("7z", "application/x-7z-compressed"),
+  ("aac", "audio/aac"),
+  ("apng", "image/apng"),
+  ("atom", "application/atom+xml"),
+  ("avi", "video/x-msvideo"),
+  ("avif", "image/avif"),
+  ("bin", "application/octet-stream"),
+  ("bmp", "image/bmp"),
+  ("bz2", "application/x-bzip2"),
+  ("cjs", "text/javascript"),
+  ("css", "text/css"),
+  ("csv", "text/csv"),
+  ("doc", "application/msword"),
+  (
+    "docx",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+  ),
+  ("eot", "application/vnd.ms-fontobject"),
+  ("epub", "application/epub+zip"),
+  ("flac", "audio/flac"),
+  ("gif", "image/gif"),
+  ("gz", "application/gzip"),
+  ("heic", "image/heic"),
+  ("htm", "text/html"),
+  ("html", "text/html"),
+  ("ico", "image/vnd.microsoft.icon"),
+  ("ics", "text/calendar"),
+  ("jar", "application/java-archive"),
+  ("jpeg", "image/jpeg"),
+  ("jpg", "image/jpeg"),
+  ("js", "text/javascript"),
+  ("json", "application/json"),
+  ("jsonld", "application/ld+json"),
+  ("jxl", "image/jxl"),
+  ("m4a", "audio/mp4"),
+  ("m4v", "video/mp4"),
+  ("map", "application/json"),
+  ("md", "text/markdown"),
+  ("mid", "audio/midi"),
+  ("midi", "audio/midi"),
+  ("mjs", "text/javascript"),
+  ("mkv", "video/x-matroska"),
+  ("mov", "video/quicktime"),
+  ("mp3", "audio/mpeg"),
+  ("mp4", "video/mp4"),
+  ("mpeg", "video/mpeg"),
+  ("odp", "application/vnd.oasis.opendocument.presentation"),
+  ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+  ("odt", "application/vnd.oasis.opendocument.text"),
+  ("oga", "audio/ogg"),
+  ("ogg", "audio/ogg"),
+  ("ogv", "video/ogg"),
+  ("opus", "audio/opus"),
+  ("otf", "font/otf"),
+  ("pdf", "application/pdf"),
+  ("png", "image/png"),
+  ("ppt", "application/vnd.ms-powerpoint"),
+  (
+    "pptx",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+  ),
+  ("rar", "application/vnd.rar"),
+  ("rss", "application/rss+xml"),
+  ("rtf", "application/rtf"),
+  ("sh", "application/x-sh"),
+  ("svg", "image/svg+xml"),
+  ("tar", "application/x-tar"),
+  ("tif", "image/tiff"),
+  ("tiff", "image/tiff"),
+  ("toml", "application/toml"),
+  ("ttf", "font/ttf"),
+  ("txt", "text/plain"),
+  ("vtt", "text/vtt"),
+  ("wasm", "application/wasm"),
+  ("wav", "audio/wav"),
+  ("weba", "audio/webm"),
+  ("webm", "video/webm"),
+  ("webmanifest", "application/manifest+json"),
+  ("webp", "image/webp"),
+  ("woff", "font/woff"),
+  ("woff2", "font/woff2"),
+  ("xhtml", "application/xhtml+xml"),
+  ("xls", "application/vnd.ms-excel"),
+  (
+    "xlsx",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+  ),
+  ("xml", "application/xml"),
+  ("xz", "application/x-xz"),
+  ("yaml", "application/yaml"),
+  ("yml", "application/yaml"),
+  ("zip", "application/zip"),
+  ("zst", "application/zstd"),
+];
+
+/// The built-in MIME type for files ending in `.ext`, ignoring case. These
+/// are used for anything `Config::mappings` doesn't cover.
+pub fn mime_type(ext: &str) -> Option<&'static str> {
+  let ext = ext.to_ascii_lowercase();
+  let found = BUILTIN.binary_search_by_key(&&ext[..], |&(e, _)| e);
+  found.ok().map(|idx| BUILTIN[idx].1)
+}
+
+/// Read a file in the format of `/etc/mime.types`, as mappings to put in
+/// `Config::mappings`.
+pub fn load_mime_types(path: &Path) -> io::Result<HashMap<OsString, String>> {
+  Ok(parse_mime_types(&fs::read_to_string(path)?))
+}
+
+/// Each line is a type followed by its extensions, like
+/// `text/html html htm`, and `#` starts a comment. Later lines win.
+fn parse_mime_types(text: &str) -> HashMap<OsString, String> {
+  let mut out = HashMap::new();
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap_or("");
+    let mut words = line.split_whitespace();
+    let mime = match words.next() {
+      Some(mime) => mime,
+      None => continue,
+    };
+    for ext in words {
+      out.insert(ext.into(), mime.into());
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn builtin() {
+    for pair in BUILTIN.windows(2) {
+      assert!(pair[0].0 < pair[1].0, "{} out of order", pair[1].0);
+    }
+    assert_eq!(mime_type("woff2"), Some("font/woff2"));
+    assert_eq!(mime_type("PNG"), Some("image/png"), "case mattered");
+    assert_eq!(mime_type(""), None);
+    assert_eq!(mime_type("nope"), None);
+  }
+
+  #[test]
+  fn mime_types_file() {
+    let text = "# comment\n\
+                text/html\t\thtml htm\n\
+                application/x-empty\n\
+                \n\
+                text/x-old  foo # trailing\n\
+                text/x-new  foo\n";
+    let parsed = parse_mime_types(text);
+    let get = |ext: &str| parsed.get(&OsString::from(ext)).map(|s| &s[..]);
+    assert_eq!(get("html"), Some("text/html"));
+    assert_eq!(get("htm"), Some("text/html"));
+    assert_eq!(get("foo"), Some("text/x-new"), "earlier line won");
+    assert_eq!(parsed.len(), 3);
+  }
+}
@@ -0,0 +1,318 @@
+//! Choosing between variants of a file, like `hero.avif`, `hero.webp`, and
+//! `hero.png`, or `index.en.html` and `index.de.html`, by what the client
+//! says it accepts.
+
+use std::{
+  ffi::OsStr,
+  path::{Path, PathBuf},
+};
+
+use crate::vfs::Vfs;
+
+/// Types to pick first when the client likes several as much, best first
+const PREFERENCE: &[&str] = &[
+  "image/avif",
+  "image/webp",
+  "image/png",
+  "image/jpeg",
+  "image/gif",
+];
+
+/// The media ranges in an `Accept` header, and how much each is wanted.
+pub(crate) struct Accept(Vec<(String, f32)>);
+
+impl Accept {
+  /// Parse an `Accept` header. No header means anything is fine.
+  ///
+  /// Like Apache, a wildcard without an explicit `q` counts for a little
+  /// less than a type given by name, since browsers tend to tack `*/*` on the
+  /// end of a list of what they really support.
+  pub fn parse(header: Option<&str>) -> Accept {
+    let header = header.unwrap_or("*/*");
+    let mut ranges = vec![];
+    for part in header.split(',') {
+      let mut params = part.split(';');
+      let range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+      if !range.contains('/') {
+        continue;
+      }
+      let q = params
+        .filter_map(|p| p.trim().strip_prefix("q="))
+        .find_map(|q| q.trim().parse::<f32>().ok());
+      let q = match (q, &range[..]) {
+        (Some(q), _) => q.clamp(0.0, 1.0),
+        (None, "*/*") => 0.01,
+        (None, r) if r.ends_with("/*") => 0.02,
+        (None, _) => 1.0,
+      };
+      ranges.push((range, q));
+    }
+    Accept(ranges)
+  }
+
+  /// How much `mime` is wanted, from 0 (not at all) to 1, going by the most
+  /// specific range it's in
+  pub fn quality(&self, mime: &str) -> f32 {
+    let mime = mime.split(';').next().unwrap_or("").trim();
+    let mime = mime.to_ascii_lowercase();
+    let kind = mime.split('/').next().unwrap_or("");
+    let specificity = |range: &str| match range.split_once('/') {
+      Some(("*", "*")) => Some(1),
+      Some((k, "*")) if k == kind => Some(2),
+      _ if range == mime => Some(3),
+      _ => None,
+    };
+    let best = self
+      .0
+      .iter()
+      .filter_map(|(range, q)| specificity(range).map(|s| (s, *q)))
+      .max_by_key(|&(s, _)| s);
+    best.map_or(0.0, |(_, q)| q)
+  }
+}
+
+/// How much a version in a language that wasn't asked for is wanted, so that
+/// one is still served if there's nothing better. Versions with no language
+/// are the most generic, so they come first, then the default language.
+const UNTAGGED: f32 = 0.003;
+const DEFAULT: f32 = 0.002;
+const OTHER: f32 = 0.001;
+
+/// The language ranges in an `Accept-Language` header, and how much each is
+/// wanted.
+pub(crate) struct Languages(Vec<(String, f32)>);
+
+impl Languages {
+  /// Parse an `Accept-Language` header, or the same format given some other
+  /// way. Each range is wanted a tiny bit less than the one before, so ties
+  /// go to whichever was listed first.
+  pub fn parse(header: Option<&str>) -> Languages {
+    let mut ranges = vec![];
+    for part in header.unwrap_or("").split(',') {
+      let mut params = part.split(';');
+      let range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+      if range.is_empty() {
+        continue;
+      }
+      let q = params
+        .filter_map(|p| p.trim().strip_prefix("q="))
+        .find_map(|q| q.trim().parse::<f32>().ok());
+      let q = q.map_or(1.0, |q| q.clamp(0.0, 1.0));
+      let order = 1.0 - ranges.len().min(100) as f32 / 1000.0;
+      ranges.push((range, q * order));
+    }
+    Languages(ranges)
+  }
+
+  /// How much a version in `lang` is wanted. A range matches languages it's
+  /// a prefix of, or which are a prefix of it, so `de-AT` matches `de`, and
+  /// `*` only counts if nothing more specific does.
+  pub fn quality(&self, lang: Option<&str>, default: Option<&str>) -> f32 {
+    let lang = match lang {
+      Some(lang) => lang.to_ascii_lowercase(),
+      None => return UNTAGGED,
+    };
+    let named = self
+      .0
+      .iter()
+      .filter(|(range, _)| same_language(range, &lang))
+      .map(|&(_, q)| q)
+      .reduce(f32::max);
+    let any = self.0.iter().find(|(range, _)| range == "*");
+    match (named, any) {
+      (Some(q), _) | (None, Some(&(_, q))) => q,
+      _ if default.is_some_and(|d| same_language(&d.to_lowercase(), &lang)) => {
+        DEFAULT
+      }
+      _ => OTHER,
+    }
+  }
+}
+
+/// Whether `a` and `b`, both lowercase, are the same language, if one's more
+/// specific, like `de` and `de-at`
+fn same_language(a: &str, b: &str) -> bool {
+  let prefix = |a: &str, b: &str| {
+    b.strip_prefix(a).is_some_and(|rest| rest.starts_with('-'))
+  };
+  a == b || prefix(a, b) || prefix(b, a)
+}
+
+/// A version of a file that could be served for it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Variant {
+  pub path: PathBuf,
+  pub mime: String,
+  /// The language it's in, if its name says, like `de` for `index.de.html`
+  pub lang: Option<String>,
+  /// How much the client wants this one
+  pub quality: f32,
+}
+
+/// What versions of `path` are named after: `hero` for `hero` or `hero.png`
+fn stem(path: &Path) -> Option<&OsStr> {
+  match path.extension() {
+    Some(_) => path.file_stem(),
+    None => path.file_name(),
+  }
+}
+
+/// The files next to `path` which are versions of it: for `hero` or
+/// `hero.png`, those named `hero.` and then anything, including `hero.png`
+/// itself. Directories don't count.
+pub(crate) fn siblings(vfs: &dyn Vfs, path: &Path) -> Vec<PathBuf> {
+  let stem = match stem(path) {
+    Some(stem) => stem,
+    None => return vec![],
+  };
+  let prefix = format!("{}.", stem.to_string_lossy());
+  let dir = path.parent().unwrap_or(Path::new(""));
+  let mut out: Vec<_> = match vfs.read_dir(dir) {
+    Ok(entries) => entries
+      .into_iter()
+      .filter(|e| !e.is_dir && e.name.starts_with(&prefix))
+      .map(|e| dir.join(e.name))
+      .collect(),
+    Err(_) => vec![],
+  };
+  out.sort();
+  out
+}
+
+/// The language `variant`, a version of `asked`, is in, going by whether
+/// what's between the name and extension is a language tag, like `de` or
+/// `pt-BR` in `index.de.html` or `index.pt-BR.html`. Only two-letter
+/// languages count, so that e.g. `app.min.js` isn't taken to be in `min`.
+pub(crate) fn language(variant: &Path, asked: &Path) -> Option<String> {
+  let prefix = format!("{}.", stem(asked)?.to_string_lossy());
+  let middle = variant.file_stem()?.to_str()?.strip_prefix(&prefix)?;
+  let mut parts = middle.split('-');
+  let primary = parts.next()?;
+  let is_tag = primary.len() == 2
+    && primary.chars().all(|c| c.is_ascii_alphabetic())
+    && parts.all(|p| {
+      (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric())
+    });
+  match is_tag {
+    true => Some(middle.into()),
+    false => None,
+  }
+}
+
+/// The best of `variants` for a request for `asked`, if the client wants any
+/// of them at all. The most wanted wins, then whichever was asked for by
+/// name, then the server's favourite type, then the first by name, so it's
+/// always the same.
+pub(crate) fn choose(
+  mut variants: Vec<Variant>,
+  asked: &Path,
+) -> Option<Variant> {
+  let preference = |v: &Variant| {
+    let mime = v.mime.split(';').next().unwrap_or("");
+    let rank = PREFERENCE.iter().position(|p| *p == mime);
+    (v.path != asked, rank.unwrap_or(PREFERENCE.len()))
+  };
+  variants.sort_by(|a, b| {
+    b.quality
+      .total_cmp(&a.quality)
+      .then_with(|| preference(a).cmp(&preference(b)))
+      .then_with(|| a.path.cmp(&b.path))
+  });
+  variants.into_iter().next().filter(|v| v.quality > 0.0)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn accept() {
+    let chrome = Accept::parse(Some(
+      "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8",
+    ));
+    assert_eq!(chrome.quality("image/avif"), 1.0);
+    assert_eq!(chrome.quality("image/png"), 0.02, "image/* wasn't fiddled");
+    assert_eq!(chrome.quality("text/html;charset=utf-8"), 0.8);
+    let picky = Accept::parse(Some("text/html, image/PNG; q=0.5, */*;q=0"));
+    assert_eq!(picky.quality("image/png"), 0.5, "case mattered");
+    assert_eq!(picky.quality("text/html"), 1.0);
+    assert_eq!(picky.quality("image/webp"), 0.0);
+    assert_eq!(
+      Accept::parse(Some("image/png;q=0")).quality("image/png"),
+      0.0
+    );
+    assert_eq!(Accept::parse(None).quality("x/y"), 0.01);
+    assert_eq!(Accept::parse(Some("garbage")).quality("x/y"), 0.0);
+  }
+
+  #[test]
+  fn languages() {
+    let austrian = Languages::parse(Some("de-AT, en;q=0.5, fr;q=0"));
+    let default = Some("fr");
+    let q = |lang: Option<&str>| austrian.quality(lang, default);
+    assert_eq!(q(Some("de")), 1.0, "no prefix matching");
+    assert_eq!(q(Some("de-at")), 1.0);
+    assert_eq!(q(Some("de-CH")), OTHER);
+    let en = q(Some("en-GB"));
+    assert!(en > 0.49 && en < 0.5, "no prefix matching the other way");
+    assert_eq!(q(Some("fr")), 0.0, "q=0 ignored");
+    assert_eq!(q(Some("es")), OTHER);
+    assert_eq!(q(None), UNTAGGED);
+    let nothing = Languages::parse(None);
+    assert_eq!(nothing.quality(Some("FR"), default), DEFAULT);
+    assert_eq!(nothing.quality(Some("es"), default), OTHER);
+    let any = Languages::parse(Some("es;q=0, *;q=0.2"));
+    let it = any.quality(Some("it"), default);
+    assert!(it > 0.19 && it < 0.2, "* not used");
+    assert_eq!(any.quality(Some("es"), default), 0.0, "* beat a q=0");
+
+    let lang = |v: &str, asked: &str| language(Path::new(v), Path::new(asked));
+    assert_eq!(lang("index.de.html", "index.html"), Some("de".into()));
+    assert_eq!(lang("about.pt-BR.html", "about"), Some("pt-BR".into()));
+    assert_eq!(lang("about.html", "about"), None);
+    assert_eq!(lang("app.min.js", "app.js"), None);
+    assert_eq!(lang("a.de.x.html", "a"), None);
+  }
+
+  #[test]
+  fn choosing() {
+    let accept = Accept::parse(Some("image/webp,image/*;q=0.9"));
+    let variant = |name: &str, mime: &str| Variant {
+      path: name.into(),
+      mime: mime.into(),
+      lang: None,
+      quality: accept.quality(mime),
+    };
+    let all = vec![
+      variant("hero.png", "image/png"),
+      variant("hero.webp", "image/webp"),
+      variant("hero.avif", "image/avif"),
+    ];
+    let asked = Path::new("hero");
+    let best = choose(all.clone(), asked).expect("nothing chosen");
+    assert_eq!(best.path, Path::new("hero.webp"), "q-values ignored");
+    let best = choose(vec![all[0].clone(), all[2].clone()], asked).unwrap();
+    assert_eq!(best.path, Path::new("hero.avif"), "preference ignored");
+    let asked = Path::new("hero.png");
+    let best = choose(vec![all[0].clone(), all[2].clone()], asked).unwrap();
+    assert_eq!(best.path, asked, "what was asked for didn't win a tie");
+    let unwanted = variant("hero.txt", "text/plain");
+    assert_eq!(choose(vec![unwanted], asked), None, "chose the unwanted");
+    assert_eq!(choose(vec![], asked), None);
+  }
+
+  #[test]
+  fn finding_siblings() {
+    let mut fs = crate::MemoryFs::new();
+    for name in ["hero.png", "hero.webp", "heroic.png", "hero.d/x", "other"] {
+      fs.insert_static(&format!("img/{}", name), b"");
+    }
+    let names = |path: &str| -> Vec<PathBuf> { siblings(&fs, Path::new(path)) };
+    let both = vec![PathBuf::from("img/hero.png"), "img/hero.webp".into()];
+    assert_eq!(names("img/hero"), both);
+    assert_eq!(names("img/hero.png"), both);
+    assert_eq!(names("img/hero.gif"), both, "needn't exist");
+    assert!(names("img/nope").is_empty());
+    assert!(names("nodir/hero").is_empty());
+  }
+}
@@ -0,0 +1,59 @@
+use std::{net::SocketAddr, thread::JoinHandle};
+
+use crate::{ShutdownHandle, Summary};
+
+/// A server running on a background thread, from `Httpserv::spawn`. It's shut
+/// down when dropped, waiting for requests in progress like `ShutdownHandle`.
+#[derive(Debug)]
+pub struct RunningServer {
+  pub(crate) urls: Vec<String>,
+  pub(crate) locals: Vec<SocketAddr>,
+  pub(crate) handle: ShutdownHandle,
+  pub(crate) thread: Option<JoinHandle<Summary>>,
+}
+
+impl RunningServer {
+  /// The URL of the root, on the first address listened on
+  pub fn url(&self) -> &str {
+    &self.urls[0]
+  }
+
+  /// The URLs of the root, one per address listened on
+  pub fn urls(&self) -> &[String] {
+    &self.urls
+  }
+
+  /// The first address listened on
+  pub fn local_addr(&self) -> SocketAddr {
+    self.locals[0]
+  }
+
+  /// Every address listened on
+  pub fn local_addrs(&self) -> &[SocketAddr] {
+    &self.locals
+  }
+
+  /// A handle to shut down the server from elsewhere
+  pub fn shutdown_handle(&self) -> ShutdownHandle {
+    self.handle.clone()
+  }
+
+  /// Shut down and wait for the server thread to finish.
+  pub fn shutdown(mut self) -> Summary {
+    self.stop()
+  }
+
+  fn stop(&mut self) -> Summary {
+    self.handle.shutdown();
+    match self.thread.take().map(JoinHandle::join) {
+      Some(Ok(summary)) => summary,
+      _ => Summary::default(),
+    }
+  }
+}
+
+impl Drop for RunningServer {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
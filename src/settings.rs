@@ -0,0 +1,368 @@
+//! Settings from a project's `httpserv.toml`, and keeping track of where each
+//! of the effective settings came from.
+//!
+//! The file is a small subset of TOML: `key = value` lines, where the keys are
+//! the same as the long command-line options and values are strings, numbers,
+//! `true`/`false`, or arrays of those for options that can be repeated. MIME
+//! types go in a `[mime]` section, one `ext = "type"` per line.
+
+use std::{
+  collections::BTreeMap,
+  fmt,
+  path::{Path, PathBuf},
+};
+
+/// Where the file is looked for, in the served directory and then the current
+/// one.
+pub const FILE_NAME: &str = "httpserv.toml";
+
+/// Options whose values are paths, which are relative to the file they're in
+const PATHS: &[&str] = &[
+  "root",
+  "mime-types",
+  "auth-file",
+  "log-file",
+  "tls-cert",
+  "tls-key",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+  Default,
+  File(PathBuf),
+  /// The environment variable with this name
+  Env(String),
+  /// The positional arguments
+  Args,
+  /// The named options on the command line
+  Flags,
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Source::Default => write!(f, "default"),
+      Source::File(path) => write!(f, "{}", path.display()),
+      Source::Env(var) => write!(f, "${}", var),
+      Source::Args | Source::Flags => write!(f, "command line"),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct FileFail {
+  pub line: usize,
+  pub msg: String,
+}
+
+impl fmt::Display for FileFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.msg)
+  }
+}
+
+/// One `key = value` from the file, with the line it's on. Arrays become
+/// one setting per element.
+pub struct Line {
+  pub line: usize,
+  pub key: String,
+  pub value: String,
+}
+
+/// Parse a config file's contents. Relative paths are made relative to `dir`,
+/// the directory the file is in.
+pub fn parse(text: &str, dir: &Path) -> Result<Vec<Line>, FileFail> {
+  let mut out = vec![];
+  let mut section = String::new();
+  for (idx, raw) in text.lines().enumerate() {
+    let fail = |msg: &str| FileFail {
+      line: idx + 1,
+      msg: msg.into(),
+    };
+    let line = raw.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    if let Some(name) = line.strip_prefix('[') {
+      let name = name
+        .split('#')
+        .next()
+        .and_then(|n| n.trim().strip_suffix(']'))
+        .ok_or_else(|| fail("unclosed section header"))?;
+      section = match name.trim() {
+        "mime" => "mime".into(),
+        other => return Err(fail(&format!("unknown section '{}'", other))),
+      };
+      continue;
+    }
+    let (key, rest) = line
+      .split_once('=')
+      .ok_or_else(|| fail("expected `key = value`"))?;
+    let key = match value(key.trim()) {
+      Ok((key, "")) => key,
+      _ => return Err(fail("invalid key")),
+    };
+    let values = values(rest.trim()).map_err(fail)?;
+    for v in values {
+      let (key, value) = match &section[..] {
+        "mime" => ("mime".into(), format!("{}={}", key, v)),
+        _ => {
+          let key = key.replace('_', "-");
+          let v = relative(&key, v, dir);
+          (key, v)
+        }
+      };
+      out.push(Line {
+        line: idx + 1,
+        key,
+        value,
+      });
+    }
+  }
+  Ok(out)
+}
+
+/// `value` for `key`, with any path in it made relative to `dir`
+fn relative(key: &str, value: String, dir: &Path) -> String {
+  let join = |p: &str| match Path::new(p).is_relative() {
+    true => dir.join(p).to_string_lossy().into_owned(),
+    false => p.into(),
+  };
+  match key {
+    k if PATHS.contains(&k) => join(&value),
+    "mount" => match value.split_once('=') {
+      Some((prefix, path)) => format!("{}={}", prefix, join(path)),
+      None => value,
+    },
+    _ => value,
+  }
+}
+
+/// Everything on the right of the `=`: a single value or an array of them,
+/// then maybe a comment
+fn values(mut rest: &str) -> Result<Vec<String>, &'static str> {
+  let mut out = vec![];
+  let array = rest.starts_with('[');
+  if array {
+    rest = rest[1..].trim_start();
+    loop {
+      if let Some(after) = rest.strip_prefix(']') {
+        rest = after;
+        break;
+      }
+      let (v, after) = value(rest)?;
+      out.push(v);
+      rest = after.trim_start();
+      if let Some(after) = rest.strip_prefix(',') {
+        rest = after.trim_start();
+      } else if !rest.starts_with(']') {
+        return Err("expected `,` or `]` in array");
+      }
+    }
+  } else {
+    let (v, after) = value(rest)?;
+    out.push(v);
+    rest = after;
+  }
+  match rest.trim_start() {
+    r if r.is_empty() || r.starts_with('#') => Ok(out),
+    _ => Err("unexpected text after value"),
+  }
+}
+
+/// One string, number, or boolean, and whatever's after it
+fn value(s: &str) -> Result<(String, &str), &'static str> {
+  let mut chars = s.char_indices();
+  match chars.next() {
+    Some((_, '"')) => {
+      let mut out = String::new();
+      while let Some((i, c)) = chars.next() {
+        match c {
+          '"' => return Ok((out, &s[i + 1..])),
+          '\\' => match chars.next() {
+            Some((_, 'n')) => out.push('\n'),
+            Some((_, 't')) => out.push('\t'),
+            Some((_, c @ ('"' | '\\'))) => out.push(c),
+            _ => return Err("invalid escape in string"),
+          },
+          c => out.push(c),
+        }
+      }
+      Err("unclosed string")
+    }
+    Some((_, '\'')) => match s[1..].find('\'') {
+      Some(end) => Ok((s[1..end + 1].into(), &s[end + 2..])),
+      None => Err("unclosed string"),
+    },
+    Some(_) => {
+      let end = s
+        .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+        .unwrap_or(s.len());
+      Ok((s[..end].into(), &s[end..]))
+    }
+    None => Err("missing value"),
+  }
+}
+
+/// Quote a string so `value` reads it back the same
+fn quote(s: &str) -> String {
+  let escaped = s
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+    .replace('\t', "\\t");
+  format!("\"{}\"", escaped)
+}
+
+/// Each setting that's been given, with where from. Settings are grouped by
+/// what they control, so e.g. `--quiet` and `--verbose` are both
+/// `verbosity`, and each MIME type is its own `mime.ext`.
+#[derive(Debug, Default)]
+pub struct Sources {
+  groups: BTreeMap<String, (Source, Vec<String>)>,
+}
+
+impl Sources {
+  /// Note `value` being given for `group` by `source`. Returns whether
+  /// anything given for it before came from somewhere else, and so should be
+  /// forgotten.
+  pub fn set(&mut self, group: &str, source: &Source, value: String) -> bool {
+    match self.groups.get_mut(group) {
+      Some((old, values)) if old == source => {
+        values.push(value);
+        false
+      }
+      Some(entry) => {
+        *entry = (source.clone(), vec![value]);
+        true
+      }
+      None => {
+        let entry = (source.clone(), vec![value]);
+        self.groups.insert(group.into(), entry);
+        false
+      }
+    }
+  }
+
+  /// Forget what was given for `group`, unless it came from `source`.
+  /// Returns whether anything was forgotten.
+  pub fn forget_unless(&mut self, group: &str, source: &Source) -> bool {
+    match self.groups.get(group) {
+      Some((old, _)) if old != source => self.groups.remove(group).is_some(),
+      _ => false,
+    }
+  }
+
+  /// Every setting, as a config file which would give the same ones, with
+  /// comments saying where each came from
+  pub fn print(&self, lists: &[&str]) -> String {
+    let mut out = String::new();
+    let mut mime = String::new();
+    for (group, (source, values)) in &self.groups {
+      let shown = match (&values[..], lists.contains(&&group[..])) {
+        ([one], false) if one == "true" || one == "false" => one.clone(),
+        ([one], false) if one.parse::<u64>().is_ok() => one.clone(),
+        (values, false) => quote(values.last().map_or("", |v| &v[..])),
+        (values, true) => {
+          let quoted: Vec<_> = values.iter().map(|v| quote(v)).collect();
+          format!("[{}]", quoted.join(", "))
+        }
+      };
+      match group.strip_prefix("mime.") {
+        Some(ext) => {
+          let line = format!("{} = {} # {}\n", quote(ext), shown, source);
+          mime.push_str(&line);
+        }
+        None => {
+          out.push_str(&format!("{} = {} # {}\n", group, shown, source));
+        }
+      }
+    }
+    if !mime.is_empty() {
+      out.push_str("\n[mime]\n");
+      out.push_str(&mime);
+    }
+    out
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn parsed(text: &str) -> Result<Vec<(String, String)>, FileFail> {
+    let lines = parse(text, Path::new("proj"))?;
+    Ok(lines.into_iter().map(|l| (l.key, l.value)).collect())
+  }
+
+  #[test]
+  fn file_format() {
+    let text = r#"
+      # a comment
+      root = "site" # another
+      port = 3000
+      port_fallback = true
+      bind = ['localhost', "[::1]"]
+      mount = "/static=../assets"
+      auth = "a\"b:c"
+
+      [mime]
+      foo = "text/foo"
+      "tar.gz" = application/gzip
+    "#;
+    let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+    assert_eq!(
+      parsed(text).expect("failed to parse"),
+      vec![
+        pair("root", "proj/site"),
+        pair("port", "3000"),
+        pair("port-fallback", "true"),
+        pair("bind", "localhost"),
+        pair("bind", "[::1]"),
+        pair("mount", "/static=proj/../assets"),
+        pair("auth", "a\"b:c"),
+        pair("mime", "foo=text/foo"),
+        pair("mime", "tar.gz=application/gzip"),
+      ]
+    );
+    for bad in [
+      "root",
+      "root = \"site",
+      "root = \"a\" b",
+      "bind = [\"a\" \"b\"]",
+      "[nope]",
+      "[mime",
+    ] {
+      assert!(parsed(bad).is_err(), "accepted {}", bad);
+    }
+    assert_eq!(parsed("\n\nroot = 'x").unwrap_err().line, 3, "wrong line");
+  }
+
+  #[test]
+  fn printing() {
+    let mut sources = Sources::default();
+    let file = Source::File("httpserv.toml".into());
+    sources.set("root", &Source::Default, ".".into());
+    assert!(
+      sources.set("root", &file, "site".into()),
+      "source not changed"
+    );
+    sources.set("bind", &Source::Flags, "a:1".into());
+    assert!(!sources.set("bind", &Source::Flags, "b:2".into()));
+    sources.set("metrics", &file, "true".into());
+    sources.set("mime.foo", &Source::Args, "text/\"foo\"".into());
+    let printed = sources.print(&["bind"]);
+    assert_eq!(
+      printed,
+      "bind = [\"a:1\", \"b:2\"] # command line\n\
+       metrics = true # httpserv.toml\n\
+       root = \"site\" # httpserv.toml\n\
+       \n\
+       [mime]\n\
+       \"foo\" = \"text/\\\"foo\\\"\" # command line\n"
+    );
+    // what's printed can be read back
+    let lines = parse(&printed, Path::new("")).expect("can't read back");
+    assert_eq!(lines.len(), 5);
+  }
+}
@@ -0,0 +1,138 @@
+use std::{
+  collections::HashMap,
+  io,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, OnceLock, Weak,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use crate::conn::{Listener, Transport};
+
+/// How often to check on busy connections while draining
+const DRAIN_POLL: Duration = Duration::from_millis(50);
+
+/// Tells a running server to stop. Clones all control the same server, so
+/// it's fine to hand them out to signal handlers, other threads, etc.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+  state: Arc<State>,
+}
+
+#[derive(Debug)]
+struct State {
+  grace: Duration,
+  deadline: OnceLock<Instant>,
+  /// To wake up when shutting down. They're closed once the server stops,
+  /// even if a handle's still around.
+  listeners: Vec<Weak<dyn Listener>>,
+}
+
+impl ShutdownHandle {
+  pub(crate) fn new(grace: Duration, listeners: &[Arc<dyn Listener>]) -> Self {
+    ShutdownHandle {
+      state: Arc::new(State {
+        grace,
+        deadline: OnceLock::new(),
+        listeners: listeners.iter().map(Arc::downgrade).collect(),
+      }),
+    }
+  }
+
+  /// Stop accepting connections, and give the requests already in progress
+  /// until the configured grace period is up to finish. Calling it again
+  /// doesn't extend the deadline.
+  pub fn shutdown(&self) {
+    let deadline = Instant::now() + self.state.grace;
+    if self.state.deadline.set(deadline).is_err() {
+      return;
+    }
+    // accepting blocks, so poke each listener to make it notice
+    for listener in self.state.listeners.iter().filter_map(Weak::upgrade) {
+      listener.wake();
+    }
+  }
+
+  /// Whether `shutdown` has been called
+  pub fn is_shutdown(&self) -> bool {
+    self.state.deadline.get().is_some()
+  }
+
+  pub(crate) fn deadline(&self) -> Option<Instant> {
+    self.state.deadline.get().copied()
+  }
+}
+
+/// What a server got up to, returned once `Httpserv::run` finishes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+  /// Responses completely sent
+  pub requests: u64,
+  /// Connections still busy when the grace period ran out, which were cut off
+  pub unfinished: usize,
+}
+
+/// Every open connection, with how many requests it's in the middle of, so
+/// shutting down knows what to wait for.
+#[derive(Default)]
+pub(crate) struct Tracker {
+  open: Mutex<Open>,
+}
+
+#[derive(Default)]
+struct Open {
+  next: u64,
+  conns: HashMap<u64, (Box<dyn Transport>, Arc<AtomicUsize>)>,
+}
+
+impl Tracker {
+  /// Start tracking a connection. Returns its ID, and the count of requests
+  /// in progress for whatever serves it to keep up to date.
+  pub fn open(
+    &self,
+    conn: &dyn Transport,
+  ) -> io::Result<(u64, Arc<AtomicUsize>)> {
+    let busy = Arc::new(AtomicUsize::new(0));
+    let mut open = self.open.lock().unwrap();
+    let id = open.next;
+    open.next += 1;
+    open.conns.insert(id, (conn.try_clone()?, busy.clone()));
+    Ok((id, busy))
+  }
+
+  pub fn close(&self, id: u64) {
+    self.open.lock().unwrap().conns.remove(&id);
+  }
+
+  /// Close idle connections as soon as they're idle, until they've all closed
+  /// or `deadline` passes, then cut off the rest. Returns how many were still
+  /// busy at that point.
+  pub fn drain(&self, deadline: Instant) -> usize {
+    loop {
+      let open = self.open.lock().unwrap();
+      let mut busy = 0;
+      for (conn, requests) in open.conns.values() {
+        if requests.load(Ordering::SeqCst) == 0 {
+          let _ = conn.shutdown();
+        } else {
+          busy += 1;
+        }
+      }
+      if open.conns.is_empty() {
+        return 0;
+      }
+      if Instant::now() >= deadline {
+        for (conn, _) in open.conns.values() {
+          let _ = conn.shutdown();
+        }
+        return busy;
+      }
+      drop(open);
+      thread::sleep(
+        DRAIN_POLL.min(deadline.saturating_duration_since(Instant::now())),
+      );
+    }
+  }
+}
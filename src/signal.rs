@@ -0,0 +1,52 @@
+//! Turning Ctrl-C and `kill` into a graceful shutdown, without pulling in libc.
+
+#[cfg(unix)]
+use std::{
+  sync::atomic::{AtomicUsize, Ordering},
+  thread,
+  time::Duration,
+};
+
+#[cfg(unix)]
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+mod sys {
+  use std::os::raw::c_int;
+
+  pub const SIGINT: c_int = 2;
+  pub const SIGTERM: c_int = 15;
+
+  extern "C" {
+    pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    pub fn _exit(status: c_int) -> !;
+  }
+
+  pub extern "C" fn on_signal(_: c_int) {
+    // only async-signal-safe things can happen in here, so the actual work is
+    // left to the thread watching `RECEIVED`
+    if super::RECEIVED.fetch_add(1, super::Ordering::SeqCst) > 0 {
+      unsafe { _exit(130) }
+    }
+  }
+}
+
+/// Run `stop` when SIGINT or SIGTERM first arrives. If another comes in, give
+/// up on stopping gracefully and exit immediately.
+#[cfg(unix)]
+pub fn on_stop(stop: impl FnOnce() + Send + 'static) {
+  unsafe {
+    sys::signal(sys::SIGINT, sys::on_signal);
+    sys::signal(sys::SIGTERM, sys::on_signal);
+  }
+  thread::spawn(move || {
+    while RECEIVED.load(Ordering::SeqCst) == 0 {
+      thread::sleep(Duration::from_millis(100));
+    }
+    stop();
+  });
+}
+
+/// Elsewhere, the default of exiting immediately will have to do.
+#[cfg(not(unix))]
+pub fn on_stop(_stop: impl FnOnce() + Send + 'static) {}
@@ -0,0 +1,371 @@
+//! Guessing a file's MIME type from its contents, for when its extension
+//! doesn't say, mostly following the WHATWG MIME Sniffing Standard, and which
+//! charset text is in.
+
+use std::{
+  convert::TryInto,
+  io::{self, Read, Seek, SeekFrom},
+};
+
+use crate::http::Body;
+
+/// How much of a file is looked at, which is as much as the standard says
+/// any sniffing needs
+const HEAD_LEN: u64 = 1445;
+
+/// Read the start of `body`, for `sniff`, giving it back along with a body
+/// which still starts at the beginning.
+pub(crate) fn peek(body: Body) -> io::Result<(Vec<u8>, Body)> {
+  let mut head = vec![];
+  let body = match body {
+    Body::File(mut file) => {
+      let start = file.stream_position()?;
+      (&mut file).take(HEAD_LEN).read_to_end(&mut head)?;
+      file.seek(SeekFrom::Start(start))?;
+      Body::File(file)
+    }
+    Body::Bytes(mut bytes) => {
+      let start = bytes.position();
+      (&mut bytes).take(HEAD_LEN).read_to_end(&mut head)?;
+      bytes.set_position(start);
+      Body::Bytes(bytes)
+    }
+    Body::Stream(mut stream) => {
+      (&mut stream).take(HEAD_LEN).read_to_end(&mut head)?;
+      let again = io::Cursor::new(head.clone()).chain(stream);
+      Body::Stream(Box::new(again))
+    }
+  };
+  Ok((head, body))
+}
+
+/// Whitespace, as far as the standard's concerned
+fn is_space(b: u8) -> bool {
+  matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+/// Bytes which never turn up in text
+fn is_binary(b: u8) -> bool {
+  matches!(b, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f)
+}
+
+/// Tags which make something HTML, if they start it
+const HTML: &[&[u8]] = &[
+  b"<!DOCTYPE HTML",
+  b"<HTML",
+  b"<HEAD",
+  b"<SCRIPT",
+  b"<IFRAME",
+  b"<H1",
+  b"<DIV",
+  b"<FONT",
+  b"<TABLE",
+  b"<A",
+  b"<STYLE",
+  b"<TITLE",
+  b"<B",
+  b"<BODY",
+  b"<BR",
+  b"<P",
+  b"<!--",
+];
+
+/// Types recognized by what the file starts with
+const MAGIC: &[(&[u8], &str)] = &[
+  (b"%PDF-", "application/pdf"),
+  (b"%!PS-Adobe-", "application/postscript"),
+  (b"\xfe\xff", "text/plain"),
+  (b"\xff\xfe", "text/plain"),
+  (b"\xef\xbb\xbf", "text/plain"),
+  (b"\x00\x00\x01\x00", "image/x-icon"),
+  (b"\x00\x00\x02\x00", "image/x-icon"),
+  (b"BM", "image/bmp"),
+  (b"GIF87a", "image/gif"),
+  (b"GIF89a", "image/gif"),
+  (b"\x89PNG\r\n\x1a\n", "image/png"),
+  (b"\xff\xd8\xff", "image/jpeg"),
+  (b"ID3", "audio/mpeg"),
+  (b"OggS\x00", "application/ogg"),
+  (b"MThd\x00\x00\x00\x06", "audio/midi"),
+  (b"\x00asm", "application/wasm"),
+  (b"\x1f\x8b\x08", "application/gzip"),
+  (b"PK\x03\x04", "application/zip"),
+  (b"Rar!\x1a\x07\x00", "application/vnd.rar"),
+];
+
+/// `RIFF` containers, recognized by the type after the size
+const RIFF: &[(&[u8], &str)] = &[
+  (b"WEBPVP", "image/webp"),
+  (b"WAVE", "audio/wav"),
+  (b"AVI ", "video/x-msvideo"),
+];
+
+/// The MIME type `head`, the start of a file, looks like. Anything that
+/// isn't recognized is `text/plain` if it looks like text, and
+/// `application/octet-stream` otherwise.
+pub(crate) fn sniff(head: &[u8]) -> &'static str {
+  let start = head
+    .iter()
+    .position(|&b| !is_space(b))
+    .unwrap_or(head.len());
+  let trimmed = &head[start..];
+  let html = HTML.iter().any(|tag| {
+    let terminated = matches!(trimmed.get(tag.len()), Some(b' ' | b'>'));
+    let upper = trimmed.get(..tag.len()).map(|t| t.to_ascii_uppercase());
+    terminated && upper.as_deref() == Some(tag)
+  });
+  if html {
+    return "text/html";
+  }
+  if trimmed.starts_with(b"<?xml") {
+    return "text/xml";
+  }
+  if let Some(&(_, mime)) = MAGIC.iter().find(|(m, _)| head.starts_with(m)) {
+    return mime;
+  }
+  if head.starts_with(b"RIFF") {
+    let kind = head.get(8..).unwrap_or(&[]);
+    if let Some(&(_, mime)) = RIFF.iter().find(|(k, _)| kind.starts_with(k)) {
+      return mime;
+    }
+  }
+  if let Some(mime) = ftyp(head) {
+    return mime;
+  }
+  if head.starts_with(b"\x1a\x45\xdf\xa3") && webm(head) {
+    return "video/webm";
+  }
+  if head.iter().any(|&b| is_binary(b)) {
+    return "application/octet-stream";
+  }
+  if json(trimmed) {
+    return "application/json";
+  }
+  "text/plain"
+}
+
+/// An ISO media file, i.e. MP4 or AVIF, going by its `ftyp` box's brands
+fn ftyp(head: &[u8]) -> Option<&'static str> {
+  let size = u32::from_be_bytes(head.get(..4)?.try_into().ok()?) as usize;
+  if size < 12 || !size.is_multiple_of(4) || head.len() < size {
+    return None;
+  }
+  if &head[4..8] != b"ftyp" {
+    return None;
+  }
+  // the major brand, then the compatible ones, skipping the version
+  let compatible = head.get(16..size).unwrap_or(&[]).chunks(4);
+  std::iter::once(&head[8..12]).chain(compatible).find_map(
+    |brand| match brand {
+      b"avif" | b"avis" => Some("image/avif"),
+      _ if brand.starts_with(b"mp4") => Some("video/mp4"),
+      _ => None,
+    },
+  )
+}
+
+/// Whether an EBML file is WebM, i.e. says so early on
+fn webm(head: &[u8]) -> bool {
+  let early = &head[4..head.len().min(38)];
+  let doc_type = b"\x42\x82\x84webm";
+  early.windows(doc_type.len()).any(|w| w == doc_type)
+}
+
+/// Whether text, with leading whitespace removed, looks like JSON: an object
+/// or array, and not e.g. an INI file's `[section]`.
+fn json(text: &[u8]) -> bool {
+  let after = |rest: &[u8]| -> Vec<u8> {
+    rest
+      .iter()
+      .copied()
+      .skip_while(|&b| is_space(b))
+      .take(5)
+      .collect()
+  };
+  match text.split_first() {
+    Some((b'{', rest)) => matches!(after(rest).first(), Some(b'"' | b'}')),
+    Some((b'[', rest)) => {
+      let next = after(rest);
+      match next.first() {
+        Some(b'"' | b'{' | b'[' | b']' | b'-' | b'0'..=b'9') => true,
+        _ => ["true", "false", "null"]
+          .iter()
+          .any(|word| next.starts_with(word.as_bytes())),
+      }
+    }
+    _ => false,
+  }
+}
+
+/// Whether `mime` is a text type which should have a charset added, i.e. it
+/// doesn't have one already
+pub(crate) fn wants_charset(mime: &str) -> bool {
+  let mime = mime.to_ascii_lowercase();
+  mime.starts_with("text/") && !mime.contains("charset=")
+}
+
+/// The charset a byte order mark at the start of `head` says it's in
+pub(crate) fn bom(head: &[u8]) -> Option<&'static str> {
+  match head {
+    [0xef, 0xbb, 0xbf, ..] => Some("utf-8"),
+    [0xfe, 0xff, ..] => Some("utf-16be"),
+    [0xff, 0xfe, ..] => Some("utf-16le"),
+    _ => None,
+  }
+}
+
+/// The charset HTML says it's in, with `<meta charset="...">` or
+/// `<meta http-equiv="Content-Type" content="...; charset=...">` in the first
+/// 1024 bytes, which is as far as browsers look.
+pub(crate) fn meta_charset(head: &[u8]) -> Option<String> {
+  let head = head[..head.len().min(1024)].to_ascii_lowercase();
+  let mut rest = &head[..];
+  while let Some(start) = find(rest, b"<meta") {
+    let tag = &rest[start + 5..];
+    let end = tag.iter().position(|&b| b == b'>').unwrap_or(tag.len());
+    let tag = &tag[..end];
+    rest = &rest[start + 5 + end..];
+    let after = match find(tag, b"charset") {
+      Some(at) => &tag[at + 7..],
+      None => continue,
+    };
+    let value = match after.iter().position(|&b| !is_space(b)) {
+      Some(eq) if after[eq] == b'=' => &after[eq + 1..],
+      _ => continue,
+    };
+    let value = match value.iter().position(|&b| !is_space(b)) {
+      Some(at) => &value[at..],
+      None => continue,
+    };
+    let value = value.strip_prefix(b"\"").unwrap_or(value);
+    let value = value.strip_prefix(b"'").unwrap_or(value);
+    let end = value
+      .iter()
+      .position(|&b| matches!(b, b'"' | b'\'' | b';' | b'/') || is_space(b))
+      .unwrap_or(value.len());
+    match std::str::from_utf8(&value[..end]) {
+      // it had to be ASCII-compatible to be read, so it's not really UTF-16
+      Ok(charset) if charset.starts_with("utf-16") => {
+        return Some("utf-8".into())
+      }
+      Ok(charset) if !charset.is_empty() => return Some(charset.into()),
+      _ => (),
+    }
+  }
+  None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// The charset text which doesn't say is most likely in: UTF-8 if it's
+/// valid, or else Windows-1252, which is what browsers assume old pages are
+/// in, and which covers Latin-1.
+pub(crate) fn guess_charset(head: &[u8]) -> &'static str {
+  match std::str::from_utf8(head) {
+    Ok(_) => "utf-8",
+    // cut off partway through a character, since it's only the start
+    Err(e) if e.error_len().is_none() && head.len() as u64 == HEAD_LEN => {
+      "utf-8"
+    }
+    Err(_) => "windows-1252",
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn sniffing() {
+    let cases: &[(&[u8], &str)] = &[
+      (b"", "text/plain"),
+      (b"just some words\n", "text/plain"),
+      (b"  \n<!doctype html>\n<html>", "text/html"),
+      (b"<p>hi</p>", "text/html"),
+      (b"<pre>not html</pre>", "text/plain"),
+      (b"<?xml version=\"1.0\"?><a/>", "text/xml"),
+      (b"%PDF-1.7\n", "application/pdf"),
+      (b"\xef\xbb\xbfbom", "text/plain"),
+      (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/png"),
+      (b"\xff\xd8\xff\xe0\0\x10JFIF", "image/jpeg"),
+      (b"GIF89a\x01\0\x01\0", "image/gif"),
+      (b"RIFF\x24\0\0\0WEBPVP8 ", "image/webp"),
+      (b"RIFF\x24\0\0\0WAVEfmt ", "audio/wav"),
+      (b"\0asm\x01\0\0\0", "application/wasm"),
+      (b"PK\x03\x04\x14\0", "application/zip"),
+      (b"\x1f\x8b\x08\0\0\0", "application/gzip"),
+      (b"\0\0\0\x14ftypisom\0\0\0\0mp41", "video/mp4"),
+      (b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf", "image/avif"),
+      (
+        b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm",
+        "video/webm",
+      ),
+      (b"{\"a\": 1}", "application/json"),
+      (b"\n[\n  {\"a\": 1}\n]", "application/json"),
+      (b"[true, false]", "application/json"),
+      (b"[section]\nkey = 1", "text/plain"),
+      (b"{ not json }", "text/plain"),
+      (b"\x7fELF\x02\x01\x01\0", "application/octet-stream"),
+    ];
+    for (head, mime) in cases {
+      assert_eq!(sniff(head), *mime, "wrong type for {:?}", head);
+    }
+  }
+
+  #[test]
+  fn charsets() {
+    assert!(wants_charset("text/html"));
+    assert!(!wants_charset("text/html; Charset=shift_jis"));
+    assert!(!wants_charset("image/png"));
+
+    assert_eq!(bom(b"\xef\xbb\xbfhi"), Some("utf-8"));
+    assert_eq!(bom(b"\xff\xfeh\0"), Some("utf-16le"));
+    assert_eq!(bom(b"\xfe\xff\0h"), Some("utf-16be"));
+    assert_eq!(bom(b"hi"), None);
+
+    let metas: &[(&[u8], Option<&str>)] = &[
+      (b"<meta charset=\"ISO-8859-1\">", Some("iso-8859-1")),
+      (b"<head><META CHARSET = 'koi8-r'/>", Some("koi8-r")),
+      (b"<meta charset=shift_jis>", Some("shift_jis")),
+      (
+        b"<meta http-equiv=\"Content-Type\" \
+          content=\"text/html; charset=windows-1251\">",
+        Some("windows-1251"),
+      ),
+      (b"<meta name=x><meta charset=\"utf-16le\">", Some("utf-8")),
+      (b"<meta name=\"charset\"><p>charset=x</p>", None),
+      (b"<p>no meta</p>", None),
+    ];
+    for (html, charset) in metas {
+      assert_eq!(meta_charset(html).as_deref(), *charset, "for {:?}", html);
+    }
+    let late = [&[b' '; 1024][..], b"<meta charset=big5>"].concat();
+    assert_eq!(meta_charset(&late), None, "looked too far");
+
+    assert_eq!(guess_charset(b"plain"), "utf-8");
+    assert_eq!(guess_charset("caf\u{e9}".as_bytes()), "utf-8");
+    assert_eq!(guess_charset(b"caf\xe9!"), "windows-1252");
+    assert_eq!(guess_charset(b"ends \xe2\x82"), "windows-1252");
+    let mut cut = vec![b' '; HEAD_LEN as usize - 2];
+    cut.extend_from_slice(b"\xe2\x82");
+    assert_eq!(guess_charset(&cut), "utf-8", "cut off");
+  }
+
+  #[test]
+  fn peeking() {
+    let long: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+    let bodies = [
+      Body::Bytes(io::Cursor::new(long.clone())),
+      Body::Stream(Box::new(io::Cursor::new(long.clone()))),
+    ];
+    for body in bodies {
+      let (head, mut body) = peek(body).expect("failed to peek");
+      assert_eq!(head, &long[..HEAD_LEN as usize]);
+      let mut all = vec![];
+      body.read_to_end(&mut all).unwrap();
+      assert_eq!(all, long, "peeking lost some of the body");
+    }
+  }
+}
@@ -0,0 +1,210 @@
+use std::{
+  env, fmt, fs, io,
+  net::{IpAddr, SocketAddr, UdpSocket},
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use rustls::{
+  crypto::ring,
+  pki_types::{pem, pem::PemObject, CertificateDer, PrivateKeyDer},
+  ServerConfig,
+};
+
+/// Where the certificate for HTTPS comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tls {
+  /// A PEM certificate chain and private key
+  Files { cert: PathBuf, key: PathBuf },
+  /// A self-signed certificate for localhost and this machine's IPs, generated
+  /// on first run and cached for later ones
+  SelfSigned,
+}
+
+#[derive(Debug)]
+pub enum TlsFail {
+  Io(io::Error),
+  Pem(PathBuf, pem::Error),
+  Rustls(rustls::Error),
+  Generate(rcgen::Error),
+}
+
+impl fmt::Display for TlsFail {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TlsFail::Io(e) => write!(f, "{}", e),
+      TlsFail::Pem(p, e) => {
+        write!(f, "failed to load '{}': {}", p.display(), e)
+      }
+      TlsFail::Rustls(e) => write!(f, "TLS error: {}", e),
+      TlsFail::Generate(e) => {
+        write!(f, "failed to generate certificate: {}", e)
+      }
+    }
+  }
+}
+
+impl From<io::Error> for TlsFail {
+  fn from(ioe: io::Error) -> Self {
+    Self::Io(ioe)
+  }
+}
+impl From<rustls::Error> for TlsFail {
+  fn from(re: rustls::Error) -> Self {
+    Self::Rustls(re)
+  }
+}
+impl From<rcgen::Error> for TlsFail {
+  fn from(ge: rcgen::Error) -> Self {
+    Self::Generate(ge)
+  }
+}
+
+impl Tls {
+  /// Build the rustls config for a server listening on `locals`.
+  pub(crate) fn server_config(
+    &self,
+    locals: &[SocketAddr],
+  ) -> Result<Arc<ServerConfig>, TlsFail> {
+    let (cert, key) = match self {
+      Tls::Files { cert, key } => (cert.clone(), key.clone()),
+      Tls::SelfSigned => self_signed(&cache_dir(), locals)?,
+    };
+    let chain = CertificateDer::pem_file_iter(&cert)
+      .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+      .map_err(|e| TlsFail::Pem(cert.clone(), e))?;
+    let key =
+      PrivateKeyDer::from_pem_file(&key).map_err(|e| TlsFail::Pem(key, e))?;
+    let mut config =
+      ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+  }
+}
+
+/// Where the generated certificate is kept between runs.
+fn cache_dir() -> PathBuf {
+  let base = env::var_os("XDG_CACHE_HOME")
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+    .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".cache")))
+    .unwrap_or_else(env::temp_dir);
+  base.join("httpserv")
+}
+
+/// The addresses other machines are likely to use to reach this one. Connecting
+/// a UDP socket doesn't send anything, but does make the OS pick the address
+/// it'd route from.
+fn machine_ips() -> Vec<IpAddr> {
+  let probes = [("0.0.0.0:0", "192.0.2.1:9"), ("[::]:0", "[2001:db8::1]:9")];
+  probes
+    .iter()
+    .filter_map(|(bind, target)| {
+      let sock = UdpSocket::bind(bind).ok()?;
+      sock.connect(target).ok()?;
+      Some(sock.local_addr().ok()?.ip())
+    })
+    .collect()
+}
+
+/// Get the paths to a self-signed certificate and its key in `dir` which
+/// covers every name we can think of for this machine, generating a new one if
+/// the cached one is missing or doesn't cover all of them.
+fn self_signed(
+  dir: &Path,
+  locals: &[SocketAddr],
+) -> Result<(PathBuf, PathBuf), TlsFail> {
+  let (cert, key, names_file) = (
+    dir.join("cert.pem"),
+    dir.join("key.pem"),
+    dir.join("names.txt"),
+  );
+
+  let mut names =
+    vec!["localhost".to_string(), "127.0.0.1".into(), "::1".into()];
+  let mut ips = machine_ips();
+  ips.extend(
+    locals
+      .iter()
+      .map(|l| l.ip())
+      .filter(|ip| !ip.is_unspecified()),
+  );
+  for ip in ips {
+    if !names.contains(&ip.to_string()) {
+      names.push(ip.to_string());
+    }
+  }
+
+  let cached = fs::read_to_string(&names_file).unwrap_or_default();
+  let mut cached: Vec<String> = cached.lines().map(Into::into).collect();
+  if cert.exists() && key.exists() && names.iter().all(|n| cached.contains(n)) {
+    return Ok((cert, key));
+  }
+
+  // keep the old names too, so moving between networks doesn't keep
+  // invalidating the certificate the browser was told to trust
+  for name in names {
+    if !cached.contains(&name) {
+      cached.push(name);
+    }
+  }
+  let generated = rcgen::generate_simple_self_signed(cached.clone())?;
+  fs::create_dir_all(dir)?;
+  fs::write(&cert, generated.cert.pem())?;
+  write_private(&key, generated.key_pair.serialize_pem().as_bytes())?;
+  fs::write(&names_file, cached.join("\n"))?;
+  Ok((cert, key))
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+  use std::{io::Write, os::unix::fs::OpenOptionsExt};
+  fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(path)?
+    .write_all(data)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+  fs::write(path, data)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn self_signed_cached() {
+    let cache = env::temp_dir().join("httpserv-tls-test");
+    let _ = fs::remove_dir_all(&cache);
+    let local = ["127.0.0.1:0".parse().unwrap()];
+
+    let (cert, key) = self_signed(&cache, &local).expect("failed to generate");
+    let first = fs::read(&cert).unwrap();
+    let (cert, _) = self_signed(&cache, &local).expect("failed to reuse");
+    assert_eq!(fs::read(&cert).unwrap(), first, "cached cert regenerated");
+
+    let names = fs::read_to_string(cache.join("names.txt")).unwrap();
+    assert!(names.lines().any(|n| n == "localhost"), "no localhost SAN");
+    let elsewhere = ["10.11.12.13:443".parse().unwrap()];
+    self_signed(&cache, &elsewhere).expect("failed to regenerate");
+    assert_ne!(fs::read(&cert).unwrap(), first, "new IP didn't regenerate");
+    let names = fs::read_to_string(cache.join("names.txt")).unwrap();
+    assert!(
+      names.lines().any(|n| n == "10.11.12.13"),
+      "new IP not added"
+    );
+
+    Tls::Files { cert, key }
+      .server_config(&local)
+      .expect("generated files don't load");
+    let _ = fs::remove_dir_all(&cache);
+  }
+}
@@ -0,0 +1,224 @@
+//! Dumping everything about a request and how it was answered, for when a
+//! client misbehaves and the access log isn't enough.
+
+use std::{
+  fmt,
+  fmt::Write as _,
+  io::{self, Write},
+  sync::Mutex,
+};
+
+use crate::http::{percent_encode, Request, Response};
+
+/// Headers which only make sense for one connection, so aren't worth
+/// reproducing
+const HOP_BY_HOP: &[&str] = &["connection", "upgrade", "http2-settings", "te"];
+
+/// Headers carrying credentials, which mustn't end up in scrollback or logs
+const SECRET: &[&str] = &[
+  "authorization",
+  "proxy-authorization",
+  "cookie",
+  "set-cookie",
+];
+
+/// Whether a header is one of those
+fn secret(name: &str) -> bool {
+  SECRET.contains(&&name.to_ascii_lowercase()[..])
+}
+
+/// A header's value, unless it's a secret
+fn shown<'a>(name: &str, value: &'a str) -> &'a str {
+  match secret(name) {
+    true => "<redacted>",
+    false => value,
+  }
+}
+
+/// A request being traced, collecting notes on how it's resolved until the
+/// response is ready.
+pub(crate) struct Trace {
+  request: String,
+  curl: String,
+  notes: Mutex<Vec<String>>,
+  protocol: &'static str,
+}
+
+impl Trace {
+  pub fn new(request: &Request, protocol: &'static str, tls: bool) -> Trace {
+    let mut dump =
+      format!("> {} {} {}\n", request.method, target(request), protocol);
+    for (name, value) in &request.headers {
+      let _ = writeln!(dump, "> {}: {}", name, shown(name, value));
+    }
+    Trace {
+      request: dump,
+      curl: curl(request, protocol, tls),
+      notes: Mutex::new(vec![]),
+      protocol,
+    }
+  }
+
+  /// Explain a decision made while resolving the request.
+  pub fn note(&self, why: fmt::Arguments) {
+    let mut notes = self.notes.lock().unwrap_or_else(|e| e.into_inner());
+    notes.push(why.to_string());
+  }
+
+  /// Everything traced, once `response` is ready to be sent
+  pub fn finish(self, response: &io::Result<Response>) -> String {
+    let mut out = self.request;
+    let notes = self.notes.into_inner().unwrap_or_else(|e| e.into_inner());
+    for note in notes {
+      let _ = writeln!(out, "* {}", note);
+    }
+    match response {
+      Ok(response) => {
+        let _ = writeln!(out, "< {} {}", self.protocol, response.status());
+        if self.protocol.starts_with("HTTP/1") {
+          out.push_str("< Connection: close\n");
+        }
+        for (name, value) in response.headers() {
+          let _ = writeln!(out, "< {}: {}", name, shown(&name, &value));
+        }
+      }
+      Err(e) => {
+        let _ = writeln!(out, "< (failed: {})", e);
+      }
+    }
+    let _ = writeln!(out, "$ {}\n", self.curl);
+    out
+  }
+
+  /// Print the trace, all at once so concurrent ones don't mix.
+  pub fn print(self, response: &io::Result<Response>) {
+    let out = self.finish(response);
+    let _ = io::stderr().lock().write_all(out.as_bytes());
+  }
+}
+
+/// The path and query, encoded again as they'd have been sent
+fn target(request: &Request) -> String {
+  let path: Vec<_> = request.path.split('/').map(percent_encode).collect();
+  match &request.query[..] {
+    "" => format!("/{}", path.join("/")),
+    query => format!("/{}?{}", path.join("/"), query),
+  }
+}
+
+/// Quote for a POSIX shell
+fn quote(s: &str) -> String {
+  format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// A `curl` command which sends the same request, apart from any secret
+/// headers, which are only named in a comment after it
+fn curl(request: &Request, protocol: &str, tls: bool) -> String {
+  let mut cmd = String::from("curl -i");
+  match (protocol, tls) {
+    ("HTTP/2.0", true) => cmd.push_str(" --http2"),
+    ("HTTP/2.0", false) => cmd.push_str(" --http2-prior-knowledge"),
+    (_, true) => cmd.push_str(" --http1.1"),
+    (_, false) => (),
+  }
+  match &request.method[..] {
+    "GET" => (),
+    "HEAD" => cmd.push_str(" --head"),
+    method => cmd.push_str(&format!(" -X {}", quote(method))),
+  }
+  let mut left_out = vec![];
+  for (name, value) in &request.headers {
+    let lower = name.to_ascii_lowercase();
+    if lower == "host" || HOP_BY_HOP.contains(&&lower[..]) {
+      continue;
+    }
+    if secret(name) {
+      left_out.push(&name[..]);
+      continue;
+    }
+    let header = format!("{}: {}", name, value);
+    cmd.push_str(&format!(" -H {}", quote(&header)));
+  }
+  let scheme = if tls { "https" } else { "http" };
+  let host = request.header("Host").unwrap_or("localhost");
+  let url = format!("{}://{}{}", scheme, host, target(request));
+  cmd.push(' ');
+  cmd.push_str(&quote(&url));
+  if !left_out.is_empty() {
+    cmd.push_str(&format!(" # plus {} (redacted)", left_out.join(", ")));
+  }
+  cmd
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn request() -> Request {
+    Request {
+      method: "GET".into(),
+      path: "a b/c.html".into(),
+      query: "x=1".into(),
+      headers: vec![
+        ("Host".into(), "localhost:8080".into()),
+        ("User-Agent".into(), "it's me".into()),
+        ("Connection".into(), "keep-alive".into()),
+      ],
+      peer: None,
+    }
+  }
+
+  #[test]
+  fn curl_command() {
+    let mut request = request();
+    assert_eq!(
+      curl(&request, "HTTP/1.1", false),
+      "curl -i -H 'User-Agent: it'\\''s me' \
+       'http://localhost:8080/a%20b/c.html?x=1'"
+    );
+    request.method = "DELETE".into();
+    request.headers.retain(|(n, _)| n != "Host");
+    assert_eq!(
+      curl(&request, "HTTP/2.0", true),
+      "curl -i --http2 -X 'DELETE' -H 'User-Agent: it'\\''s me' \
+       'https://localhost/a%20b/c.html?x=1'"
+    );
+    request.headers = vec![
+      ("Authorization".into(), "Basic dXNlcjpwYXNz".into()),
+      ("cookie".into(), "session=secret".into()),
+    ];
+    let cmd = curl(&request, "HTTP/1.1", false);
+    assert_eq!(
+      cmd,
+      "curl -i -X 'DELETE' 'http://localhost/a%20b/c.html?x=1' \
+       # plus Authorization, cookie (redacted)"
+    );
+    let out = Trace::new(&request, "HTTP/1.1", false)
+      .finish(&Ok(Response::bytes("text/plain", "")));
+    assert!(!out.contains("dXNlcjpwYXNz"), "credentials in the dump");
+    assert!(!out.contains("session=secret"), "cookie in the dump");
+  }
+
+  #[test]
+  fn dump() {
+    let trace = Trace::new(&request(), "HTTP/1.1", false);
+    trace.note(format_args!("chose {}", "c.html"));
+    let out = trace.finish(&Ok(Response::bytes("text/html", "hi")));
+    assert_eq!(
+      out,
+      "> GET /a%20b/c.html?x=1 HTTP/1.1\n\
+       > Host: localhost:8080\n\
+       > User-Agent: it's me\n\
+       > Connection: keep-alive\n\
+       * chose c.html\n\
+       < HTTP/1.1 200 OK\n\
+       < Connection: close\n\
+       < Cache-Control: no-cache\n\
+       < Content-Type: text/html\n\
+       < X-Content-Type-Options: nosniff\n\
+       < Content-Length: 2\n\
+       $ curl -i -H 'User-Agent: it'\\''s me' \
+       'http://localhost:8080/a%20b/c.html?x=1'\n\n"
+    );
+  }
+}
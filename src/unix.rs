@@ -0,0 +1,85 @@
+use std::{
+  fs, io,
+  net::{IpAddr, Shutdown},
+  os::unix::{
+    fs::{FileTypeExt, MetadataExt},
+    net::{UnixListener, UnixStream},
+  },
+  path::{Path, PathBuf},
+};
+
+use crate::conn::{Listener, Transport};
+
+impl Transport for UnixStream {
+  fn peer(&self) -> Option<IpAddr> {
+    None
+  }
+
+  fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+    Ok(Box::new(UnixStream::try_clone(self)?))
+  }
+
+  fn shutdown(&self) -> io::Result<()> {
+    UnixStream::shutdown(self, Shutdown::Both)
+  }
+}
+
+/// A Unix domain socket, whose file is removed once it's closed.
+#[derive(Debug)]
+pub(crate) struct UnixSocket {
+  listener: UnixListener,
+  path: PathBuf,
+  /// The socket file's device and inode, so it's only removed if it's still
+  /// the one this created
+  id: (u64, u64),
+}
+
+impl UnixSocket {
+  /// Listen at `path`, replacing a socket file left behind by a server that's
+  /// no longer running, but not one that's still in use or any other file.
+  pub fn bind(path: &Path) -> io::Result<UnixSocket> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+      if !meta.file_type().is_socket() {
+        return Err(io::Error::new(
+          io::ErrorKind::AlreadyExists,
+          format!("{} exists and isn't a socket", path.display()),
+        ));
+      }
+      if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+          io::ErrorKind::AddrInUse,
+          format!("something's already listening on {}", path.display()),
+        ));
+      }
+      fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let meta = fs::metadata(path)?;
+    Ok(UnixSocket {
+      listener,
+      path: path.into(),
+      id: (meta.dev(), meta.ino()),
+    })
+  }
+}
+
+impl Listener for UnixSocket {
+  fn accept(&self) -> io::Result<Box<dyn Transport>> {
+    let (stream, _) = self.listener.accept()?;
+    Ok(Box::new(stream))
+  }
+
+  fn wake(&self) {
+    let _ = UnixStream::connect(&self.path);
+  }
+}
+
+impl Drop for UnixSocket {
+  fn drop(&mut self) {
+    if let Ok(meta) = fs::metadata(&self.path) {
+      if (meta.dev(), meta.ino()) == self.id {
+        let _ = fs::remove_file(&self.path);
+      }
+    }
+  }
+}
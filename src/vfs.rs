@@ -0,0 +1,283 @@
+//! Where files are actually served from: the disk by default, or memory, e.g.
+//! for assets embedded in the binary with `include_bytes!`.
+
+use std::{
+  collections::BTreeMap,
+  fmt, fs,
+  io::{self, Read},
+  path::{Component, Path},
+  sync::Arc,
+};
+
+use crate::http::Body;
+
+/// What `Response::to` needs to know about a file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+  pub is_dir: bool,
+  pub len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+  pub name: String,
+  pub is_dir: bool,
+}
+
+/// A tree of files which can be served. Paths are relative to the root of the
+/// mount they're served from, already checked not to escape it.
+pub trait Vfs: fmt::Debug + Send + Sync {
+  fn open(&self, path: &Path) -> io::Result<Body>;
+  fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+  fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+}
+
+/// The real filesystem.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Disk;
+
+impl Vfs for Disk {
+  fn open(&self, path: &Path) -> io::Result<Body> {
+    fs::File::open(path).map(Body::File)
+  }
+
+  fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+    let meta = fs::metadata(path)?;
+    Ok(Metadata {
+      is_dir: meta.is_dir(),
+      len: meta.len(),
+    })
+  }
+
+  fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(path)? {
+      let entry = entry?;
+      entries.push(DirEntry {
+        name: entry.file_name().to_string_lossy().into(),
+        // follow symlinks, like serving does
+        is_dir: entry.path().is_dir(),
+      });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+  }
+}
+
+#[derive(Clone)]
+enum Data {
+  Static(&'static [u8]),
+  Owned(Arc<Vec<u8>>),
+}
+
+impl Data {
+  fn bytes(&self) -> &[u8] {
+    match self {
+      Data::Static(b) => b,
+      Data::Owned(b) => b,
+    }
+  }
+}
+
+/// Reads out of a file in a `MemoryFs` without copying it.
+struct DataReader {
+  data: Data,
+  pos: usize,
+}
+
+impl Read for DataReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut rest = &self.data.bytes()[self.pos..];
+    let read = rest.read(buf)?;
+    self.pos += read;
+    Ok(read)
+  }
+}
+
+/// Files kept in memory. Directories exist wherever there are files in them.
+///
+/// ```
+/// let mut assets = httpserv::MemoryFs::new();
+/// assets.insert_static("index.html", b"<h1>hi</h1>");
+/// assets.insert("data/generated.json", b"{}".to_vec());
+/// ```
+#[derive(Default, Clone)]
+pub struct MemoryFs {
+  files: BTreeMap<String, Data>,
+}
+
+impl fmt::Debug for MemoryFs {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_set().entries(self.files.keys()).finish()
+  }
+}
+
+/// Turn a path into a key like `a/b/c`, as files in memory or in archives are
+/// named.
+pub(crate) fn key(path: &Path) -> String {
+  let mut out = String::new();
+  for part in path.components() {
+    if let Component::Normal(name) = part {
+      if !out.is_empty() {
+        out.push('/');
+      }
+      out.push_str(&name.to_string_lossy());
+    }
+  }
+  out
+}
+
+/// The rest of `name` after `dir`, if it's inside it. Both are keys.
+fn inside<'a>(name: &'a str, dir: &str) -> Option<&'a str> {
+  match dir {
+    "" => Some(name),
+    _ => name.strip_prefix(dir)?.strip_prefix('/'),
+  }
+}
+
+/// Whether anything in a tree of keys is inside `dir`, making it a directory.
+/// The root always is one, even if it's empty.
+pub(crate) fn has_dir<'a>(
+  mut keys: impl Iterator<Item = &'a str>,
+  dir: &str,
+) -> bool {
+  dir.is_empty() || keys.any(|k| inside(k, dir).is_some())
+}
+
+/// What's directly inside `dir`, given every key in a tree and whether each
+/// is a directory. Directories can be listed themselves, or just implied by
+/// what's in them.
+pub(crate) fn list<'a>(
+  keys: impl Iterator<Item = (&'a str, bool)>,
+  dir: &str,
+) -> Vec<DirEntry> {
+  let mut found = BTreeMap::new();
+  for (key, is_dir) in keys {
+    let rest = match inside(key, dir) {
+      Some(rest) if !rest.is_empty() => rest,
+      _ => continue,
+    };
+    match rest.split_once('/') {
+      Some((name, _)) => found.insert(name, true),
+      None => found.insert(rest, is_dir),
+    };
+  }
+  found
+    .into_iter()
+    .map(|(name, is_dir)| DirEntry {
+      name: name.into(),
+      is_dir,
+    })
+    .collect()
+}
+
+fn not_found() -> io::Error {
+  io::Error::new(io::ErrorKind::NotFound, "not in memory")
+}
+
+impl MemoryFs {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add a file, without copying it; e.g. `include_bytes!("../dist/app.js")`
+  pub fn insert_static(&mut self, path: &str, data: &'static [u8]) {
+    self.files.insert(key(Path::new(path)), Data::Static(data));
+  }
+
+  pub fn insert(&mut self, path: &str, data: Vec<u8>) {
+    self
+      .files
+      .insert(key(Path::new(path)), Data::Owned(Arc::new(data)));
+  }
+}
+
+impl Vfs for MemoryFs {
+  fn open(&self, path: &Path) -> io::Result<Body> {
+    let data = self.files.get(&key(path)).ok_or_else(not_found)?;
+    Ok(Body::Stream(Box::new(DataReader {
+      data: data.clone(),
+      pos: 0,
+    })))
+  }
+
+  fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+    let key = key(path);
+    if let Some(data) = self.files.get(&key) {
+      return Ok(Metadata {
+        is_dir: false,
+        len: data.bytes().len() as u64,
+      });
+    }
+    match has_dir(self.files.keys().map(|k| &k[..]), &key) {
+      true => Ok(Metadata {
+        is_dir: true,
+        len: 0,
+      }),
+      false => Err(not_found()),
+    }
+  }
+
+  fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+    let key = key(path);
+    if !self.metadata(path)?.is_dir {
+      return Err(io::Error::other("not a directory"));
+    }
+    let keys = self.files.keys().map(|k| (&k[..], false));
+    Ok(list(keys, &key))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn memory() {
+    let mut fs = MemoryFs::new();
+    fs.insert_static("index.html", b"root");
+    fs.insert("/sub/a.txt", b"aaa".to_vec());
+    fs.insert("sub/deeper/b.txt", b"b".to_vec());
+    fs.insert("sub/a/c.txt", b"c".to_vec());
+
+    let mut body = String::new();
+    let path = Path::new("./sub/a.txt");
+    fs.open(path).unwrap().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "aaa", "wrong contents");
+    let meta = fs.metadata(path).unwrap();
+    assert_eq!((meta.is_dir, meta.len), (false, 3), "wrong file metadata");
+    assert!(
+      fs.metadata(Path::new("sub")).unwrap().is_dir,
+      "no implicit dir"
+    );
+    assert!(fs.metadata(Path::new("")).unwrap().is_dir, "no root");
+    assert!(
+      fs.metadata(Path::new("su")).is_err(),
+      "partial name matched"
+    );
+
+    let names = |p| {
+      fs.read_dir(Path::new(p))
+        .unwrap()
+        .into_iter()
+        .map(|e| (e.name, e.is_dir))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      names(""),
+      vec![("index.html".into(), false), ("sub".into(), true)]
+    );
+    assert_eq!(
+      names("sub"),
+      vec![
+        ("a".into(), true),
+        ("a.txt".into(), false),
+        ("deeper".into(), true)
+      ]
+    );
+    assert!(
+      fs.read_dir(Path::new("index.html")).is_err(),
+      "listed a file"
+    );
+  }
+}
@@ -0,0 +1,34 @@
+use std::{
+  io::{Read, Write},
+  net::TcpStream,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn setup_httpserv() -> RunningServer {
+  Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:0".into()],
+    deny: vec!["127.0.0.0/8".parse().unwrap()],
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+}
+
+#[test]
+fn test_denied_peer() {
+  let server = setup_httpserv();
+  let mut stream =
+    TcpStream::connect(server.local_addr()).expect("failed to connect");
+  write!(stream, "GET /file HTTP/1.1\n\n").expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  assert!(
+    resp.starts_with("HTTP/1.1 403 Forbidden\n"),
+    "wrong status reply"
+  );
+  assert!(!resp.ends_with("2\n"), "body leaked");
+}
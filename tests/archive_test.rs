@@ -0,0 +1,76 @@
+use std::{
+  collections::HashMap,
+  io::{Read, Write},
+  net::TcpStream,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn setup_httpserv() -> RunningServer {
+  let mut mappings = HashMap::new();
+  mappings.insert("html".into(), "text/html".into());
+  mappings.insert("css".into(), "text/css".into());
+  Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:0".into()],
+    mappings,
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+}
+
+fn request(server: &RunningServer, url: &str) -> String {
+  let mut stream =
+    TcpStream::connect(server.local_addr()).expect("failed to connect");
+  write!(stream, "GET {} HTTP/1.1\n\n", url).expect("failed to write");
+  let mut resp = Vec::new();
+  stream
+    .read_to_end(&mut resp)
+    .expect("failed to get response");
+  String::from_utf8_lossy(&resp).into()
+}
+
+#[test]
+fn test_inside_archives() {
+  let server = setup_httpserv();
+  for archive in ["bundle.zip", "bundle.tar"] {
+    let resp = request(&server, &format!("/{}/", archive));
+    assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "wrong status");
+    assert!(
+      resp.contains("\nContent-Type: text/html;charset=utf-8\n"),
+      "wrong type"
+    );
+    assert!(resp.ends_with("\n\n<h1>bundled</h1>\n"), "index not served");
+
+    let resp = request(&server, &format!("/{}/style.css", archive));
+    assert!(
+      resp.contains("\nContent-Type: text/css;charset=utf-8\n"),
+      "wrong inner type"
+    );
+    assert!(resp.contains("\nContent-Length: 19\n"), "wrong length");
+
+    let resp = request(&server, &format!("/{}/docs", archive));
+    assert!(resp.starts_with("HTTP/1.1 301"), "no redirect to directory");
+
+    let resp = request(&server, &format!("/{}/docs/", archive));
+    assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "no listing");
+    assert!(
+      resp.contains("<a href=\"readme.txt\">readme.txt</a>"),
+      "listing missing file"
+    );
+
+    let resp = request(&server, &format!("/{}/docs/nope", archive));
+    assert!(resp.starts_with("HTTP/1.1 404"), "missing entry found");
+  }
+}
+
+#[test]
+fn test_archive_download() {
+  let server = setup_httpserv();
+  let resp = request(&server, "/bundle.zip");
+  assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "wrong status");
+  let len = std::fs::metadata("tests/webroot/bundle.zip").unwrap().len();
+  let header = format!("\nContent-Length: {}\n", len);
+  assert!(resp.contains(&header), "archive itself not served");
+}
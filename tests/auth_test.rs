@@ -0,0 +1,67 @@
+use std::{
+  io::{Read, Write},
+  net::TcpStream,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn setup_httpserv() -> RunningServer {
+  let mut auth = Auth::new("test realm");
+  auth.add_user("user", "pass");
+  Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["localhost:0".into()],
+    auth: Some(auth),
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+}
+
+fn request(url: &str, headers: &str) -> String {
+  let server = setup_httpserv();
+  let mut stream =
+    TcpStream::connect(server.local_addr()).expect("failed to connect");
+  write!(stream, "GET {} HTTP/1.1\n{}\n", url, headers)
+    .expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  resp
+}
+
+#[test]
+fn test_no_credentials() {
+  let response = request("/file", "");
+  assert!(
+    response.starts_with("HTTP/1.1 401 Unauthorized\n"),
+    "wrong status reply"
+  );
+  assert!(
+    response.contains("WWW-Authenticate: Basic realm=\"test realm\""),
+    "missing challenge"
+  );
+  assert!(!response.ends_with("2\n"), "body leaked");
+}
+
+#[test]
+fn test_wrong_credentials() {
+  // user:wrong
+  let response = request("/file", "Authorization: Basic dXNlcjp3cm9uZw==\n");
+  assert!(
+    response.starts_with("HTTP/1.1 401 Unauthorized\n"),
+    "wrong status reply"
+  );
+}
+
+#[test]
+fn test_right_credentials() {
+  // user:pass
+  let response = request("/file", "authorization: Basic dXNlcjpwYXNz\n");
+  assert!(
+    response.starts_with("HTTP/1.1 200 OK\n"),
+    "wrong status reply"
+  );
+  assert!(response.ends_with("\n\n2\n"), "wrong body");
+}
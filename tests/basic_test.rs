@@ -1,37 +1,25 @@
-use std::{
-  thread::spawn,
-  collections::HashMap,
-  net::TcpStream,
-  sync::Once,
-};
+use std::{collections::HashMap, net::TcpStream};
 
 extern crate httpserv;
 use httpserv::*;
 
-static SETUP: Once = Once::new();
-
-fn setup_httpserv() {
-  SETUP.call_once(|| {
-    spawn(|| {
-      Httpserv::new(Config {
-        root: "./tests/webroot".into(),
-        hostname: "localhost:18203".into(),
-        mappings: HashMap::new(),
-        log: false,
-      }).expect("Failed to start httpserv").run();
-    });
-  });
+fn setup_httpserv() -> RunningServer {
+  Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["localhost:0".into()],
+    mappings: HashMap::new(),
+    verbosity: Verbosity::Quiet,
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
 }
 
 fn request(url: &str) -> String {
   use std::io::{Read, Write};
 
-  let mut stream = loop {
-    match TcpStream::connect("localhost:18203") {
-      Ok(s) => break s,
-      Err(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
-    }
-  };
+  let server = setup_httpserv();
+  let mut stream =
+    TcpStream::connect(server.local_addr()).expect("failed to connect");
   // When we need to send headers, maybe just trim off that last \n?
   // then the caller can send it on its own when ready
   write!(stream, "GET {} HTTP/1.1\n\n", url).expect("failed to write");
@@ -65,7 +53,6 @@ fn strip_headers(mut resp: String, ctype: &str, len: usize) -> (String, String)
 
 #[test]
 fn test_404() {
-  setup_httpserv();
   let response = request("/nonexistent_asdkjakdjd");
   let (first, _) = strip_headers(response, "text/plain", 0);
   assert_eq!(first, "HTTP/1.1 404 Not Found", "wrong status reply");
@@ -73,25 +60,22 @@ fn test_404() {
 
 #[test]
 fn test_index() {
-  setup_httpserv();
   let response = request("/");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/html;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "1\n", "wrong body");
 }
 
 #[test]
 fn test_file() {
-  setup_httpserv();
   let response = request("/file");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "2\n", "wrong body");
 }
 
 #[test]
 fn test_subdir_redirect() {
-  setup_httpserv();
   let response = request("/subdir");
   let (first, _) = strip_headers(response, "text/plain", 0);
   assert_eq!(first, "HTTP/1.1 301 Moved Permanently", "wrong status reply");
@@ -99,86 +83,76 @@ fn test_subdir_redirect() {
 
 #[test]
 fn test_subdir() {
-  setup_httpserv();
   let response = request("/subdir/");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/html;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "3\n", "wrong body");
 }
 
 #[test]
 fn test_subdir_file() {
-  setup_httpserv();
   let response = request("/subdir/file");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "4\n", "wrong body");
 }
 
 #[test]
 fn test_malicious() {
-  setup_httpserv();
   let response = request("/subdir/../../basic_test.rs");
   assert_eq!(response, "");
 }
 
 #[test]
 fn test_nonmalicious_anchor() {
-  setup_httpserv();
   let response = request("/file#../..");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "2\n", "wrong body");
 }
 
 #[test]
 fn test_nonmalicious_query() {
-  setup_httpserv();
   let response = request("/file?../..");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "2\n", "wrong body");
 }
 
 #[test]
 fn test_no_leading_slash() {
-  setup_httpserv();
   let response = request("");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/html;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "1\n", "wrong body");
 }
 
 #[test]
 fn test_no_leading_slash_file() {
-  setup_httpserv();
   let response = request("file");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "2\n", "wrong body");
 }
 
 #[test]
 fn test_no_leading_slash_subdir() {
-  setup_httpserv();
   let response = request("subdir/file");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "4\n", "wrong body");
 }
 
 #[test]
 fn test_pct_encode() {
-  setup_httpserv();
   let response = request("f%69le"); // file
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "2\n", "wrong body");
 }
 
 #[test]
 fn test_pct_encode_404() {
-  setup_httpserv();
   let response = request("f%6Ble"); // fkle
   let (first, _) = strip_headers(response, "text/plain", 0);
   assert_eq!(first, "HTTP/1.1 404 Not Found", "wrong status reply");
@@ -186,7 +160,6 @@ fn test_pct_encode_404() {
 
 #[test]
 fn test_pct_encode_slash() {
-  setup_httpserv();
   let response = request("subdir%2Ffile"); // %2F shouldn't split like real /
   assert_eq!(response, "");
 }
@@ -194,9 +167,8 @@ fn test_pct_encode_slash() {
 // TODO: Reenable once I'm off WSL
 #[test]
 fn test_symlink_path() {
-  setup_httpserv();
   let response = request("/subdir_ln/file");
-  let (first, body) = strip_headers(response, "text/plain", 2);
+  let (first, body) = strip_headers(response, "text/plain;charset=utf-8", 2);
   assert_eq!(first, "HTTP/1.1 200 OK", "wrong status reply");
   assert_eq!(body, "4\n", "wrong body");
 }
@@ -0,0 +1,168 @@
+use std::{
+  collections::HashMap,
+  io::{Read, Write},
+  net::TcpStream,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn setup_httpserv() -> RunningServer {
+  Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["localhost:0".into()],
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+}
+
+fn connect(server: &RunningServer) -> TcpStream {
+  TcpStream::connect(server.local_addr()).expect("failed to connect")
+}
+
+fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+  let mut out = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+  out.push(kind);
+  out.push(flags);
+  out.extend_from_slice(&stream.to_be_bytes());
+  out.extend_from_slice(payload);
+  out
+}
+
+/// A GET for `path`, HPACK-encoded without Huffman or the dynamic table
+fn get(stream: u32, path: &str) -> Vec<u8> {
+  // :method GET, :scheme http, then :path as a literal with an indexed name
+  let mut block = vec![0x82, 0x86, 0x04, path.len() as u8];
+  block.extend_from_slice(path.as_bytes());
+  // END_STREAM | END_HEADERS
+  frame(1, 0x5, stream, &block)
+}
+
+/// Read frames until every stream in `streams` has ended, returning the first
+/// byte of each one's header block (the indexed `:status`) and its body.
+fn responses(
+  conn: &mut TcpStream,
+  streams: &[u32],
+) -> HashMap<u32, (u8, Vec<u8>)> {
+  let mut out: HashMap<u32, (u8, Vec<u8>)> = HashMap::new();
+  let mut ended = 0;
+  while ended < streams.len() {
+    let mut head = [0; 9];
+    conn.read_exact(&mut head).expect("connection closed early");
+    let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+    let (kind, flags) = (head[3], head[4]);
+    let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+    let mut payload = vec![0; len];
+    conn.read_exact(&mut payload).unwrap();
+    match kind {
+      0 => out.get_mut(&stream).unwrap().1.extend(payload),
+      1 => {
+        out.insert(stream, (payload[0], vec![]));
+      }
+      3 => panic!("stream {} reset", stream),
+      7 => panic!("connection closed with GOAWAY"),
+      _ => continue,
+    }
+    if flags & 0x1 != 0 {
+      ended += 1;
+    }
+  }
+  out
+}
+
+#[test]
+fn test_prior_knowledge() {
+  let server = setup_httpserv();
+  let mut conn = connect(&server);
+  conn.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+  conn.write_all(&frame(4, 0, 0, &[])).unwrap();
+  conn.write_all(&get(1, "/file")).unwrap();
+  conn.write_all(&get(3, "/subdir/file")).unwrap();
+  conn.write_all(&get(5, "/nonexistent")).unwrap();
+  let resp = responses(&mut conn, &[1, 3, 5]);
+  // 0x88 is :status 200, 0x8d is :status 404
+  assert_eq!(resp[&1], (0x88, b"2\n".to_vec()), "wrong /file");
+  assert_eq!(resp[&3], (0x88, b"4\n".to_vec()), "wrong /subdir/file");
+  assert_eq!(resp[&5], (0x8d, vec![]), "wrong /nonexistent");
+}
+
+#[test]
+fn test_h2c_upgrade() {
+  let server = setup_httpserv();
+  let mut conn = connect(&server);
+  write!(
+    conn,
+    concat!(
+      "GET /subdir/ HTTP/1.1\r\n",
+      "Connection: Upgrade, HTTP2-Settings\r\n",
+      "Upgrade: h2c\r\n",
+      "HTTP2-Settings: AAMAAABkAAQCAAAAAAIAAAAA\r\n\r\n"
+    )
+  )
+  .unwrap();
+  let mut switching = [0; 33];
+  conn.read_exact(&mut switching).unwrap();
+  assert_eq!(&switching[..], &b"HTTP/1.1 101 Switching Protocols\n"[..]);
+  let mut rest = vec![];
+  while !rest.ends_with(b"\n\n") {
+    let mut byte = [0];
+    conn.read_exact(&mut byte).unwrap();
+    rest.push(byte[0]);
+  }
+  conn.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+  conn.write_all(&frame(4, 0, 0, &[])).unwrap();
+  let resp = responses(&mut conn, &[1]);
+  assert_eq!(resp[&1], (0x88, b"3\n".to_vec()), "wrong upgraded response");
+}
+
+#[test]
+fn test_malicious_stream() {
+  let server = setup_httpserv();
+  let mut conn = connect(&server);
+  conn.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+  conn.write_all(&frame(4, 0, 0, &[])).unwrap();
+  conn
+    .write_all(&get(1, "/subdir/../../basic_test.rs"))
+    .unwrap();
+  loop {
+    let mut head = [0; 9];
+    conn.read_exact(&mut head).expect("connection closed early");
+    let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+    let mut payload = vec![0; len];
+    conn.read_exact(&mut payload).unwrap();
+    assert_ne!(head[3], 1, "got a response");
+    if head[3] == 3 {
+      break;
+    }
+  }
+}
+
+#[test]
+fn test_unfinished_trailers() {
+  let server = setup_httpserv();
+  let mut conn = connect(&server);
+  conn.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+  conn.write_all(&frame(4, 0, 0, &[])).unwrap();
+  let mut request = get(1, "/file");
+  // END_HEADERS only, so trailers can follow
+  request[4] = 0x4;
+  conn.write_all(&request).unwrap();
+  // trailers have to end the stream, so this one's reset
+  conn.write_all(&frame(1, 0x4, 1, &[])).unwrap();
+  loop {
+    let mut head = [0; 9];
+    conn.read_exact(&mut head).expect("connection closed early");
+    let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+    let mut payload = vec![0; len];
+    conn.read_exact(&mut payload).unwrap();
+    assert_ne!(head[3], 7, "connection closed with GOAWAY");
+    if head[3] == 3 {
+      assert_eq!(payload, 1u32.to_be_bytes(), "not a PROTOCOL_ERROR");
+      break;
+    }
+  }
+  // and the connection carries on
+  conn.write_all(&get(3, "/file")).unwrap();
+  let resp = responses(&mut conn, &[3]);
+  assert_eq!(resp[&3], (0x88, b"2\n".to_vec()), "wrong /file");
+}
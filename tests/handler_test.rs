@@ -0,0 +1,86 @@
+use std::{
+  io::{Read, Write},
+  net::TcpStream,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn setup_httpserv() -> RunningServer {
+  Httpserv::new(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:0".into()],
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+  .layer(|req: Request, next: &dyn Handler| {
+    Ok(next.handle(req)?.with_header("X-Layer", "yes"))
+  })
+  .layer(|mut req: Request, next: &dyn Handler| {
+    if req.path == "old" {
+      req.path = "file".into();
+    }
+    next.handle(req)
+  })
+  .route("GET", "/hello", |_| {
+    Ok(Response::bytes("text/plain", "hi\n"))
+  })
+  .route("GET", "/count/*", |req: Request| {
+    let n: usize = req.path["count/".len()..].parse().unwrap_or(0);
+    let digits = (0..n).map(|i| b'0' + (i % 10) as u8).collect::<Vec<_>>();
+    Ok(Response::stream("text/plain", std::io::Cursor::new(digits)))
+  })
+  .start()
+}
+
+fn request(server: &RunningServer, url: &str) -> String {
+  let mut stream =
+    TcpStream::connect(server.local_addr()).expect("failed to connect");
+  write!(stream, "GET {} HTTP/1.1\n\n", url).expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  resp
+}
+
+#[test]
+fn test_route() {
+  let server = setup_httpserv();
+  let resp = request(&server, "/hello");
+  assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "wrong status");
+  assert!(resp.contains("\nContent-Length: 3\n"), "wrong length");
+  assert!(resp.contains("\nX-Layer: yes\n"), "layer didn't run");
+  assert!(resp.ends_with("\n\nhi\n"), "wrong body");
+}
+
+#[test]
+fn test_stream() {
+  let server = setup_httpserv();
+  let resp = request(&server, "/count/12");
+  assert!(!resp.contains("Content-Length"), "length of a stream given");
+  assert!(resp.ends_with("\n\n012345678901"), "wrong body");
+}
+
+#[test]
+fn test_rewrite_and_fallback() {
+  let server = setup_httpserv();
+  let resp = request(&server, "/old");
+  assert!(resp.ends_with("\n\n2\n"), "rewrite didn't reach files");
+  let resp = request(&server, "/subdir/file");
+  assert!(resp.ends_with("\n\n4\n"), "files not served");
+  assert!(resp.contains("\nX-Layer: yes\n"), "layer skipped for files");
+}
+
+#[test]
+fn test_custom_handler() {
+  let server = Httpserv::new(Config {
+    hostnames: vec!["127.0.0.1:0".into()],
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+  .handler(|req: Request| Ok(Response::bytes("text/plain", req.method)))
+  .start();
+  let resp = request(&server, "/file");
+  assert!(resp.ends_with("\n\nGET"), "handler not replaced");
+}
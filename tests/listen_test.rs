@@ -0,0 +1,156 @@
+use std::{
+  io::{self, Read, Write},
+  net::{SocketAddr, TcpListener, TcpStream},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  thread::spawn,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn get_file(addr: SocketAddr) -> String {
+  let mut stream = TcpStream::connect(addr).expect("failed to connect");
+  write!(stream, "GET /file HTTP/1.1\n\n").expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  resp
+}
+
+#[test]
+fn test_every_address() {
+  let mut serv = Httpserv::new(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:0".into(), "localhost:0".into()],
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv");
+  let addrs = serv.local_addrs().to_vec();
+  assert!(addrs.len() >= 2, "not every hostname bound");
+  assert!(addrs.iter().all(|a| a.port() != 0), "port 0 not resolved");
+  assert_eq!(serv.local_addr(), addrs[0], "local_addr isn't the first");
+  spawn(move || serv.run());
+  for addr in addrs {
+    let resp = get_file(addr);
+    assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "{} failed", addr);
+    assert!(resp.ends_with("\n2\n"), "{} sent the wrong body", addr);
+  }
+}
+
+#[test]
+fn test_port_fallback() {
+  let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+  let hostname = taken.local_addr().unwrap().to_string();
+  let serv = Httpserv::new(Config {
+    hostnames: vec![hostname.clone()],
+    ..Default::default()
+  });
+  assert!(serv.is_err(), "bound a port that's in use");
+  let serv = Httpserv::new(Config {
+    hostnames: vec![hostname],
+    port_fallback: true,
+    ..Default::default()
+  })
+  .expect("didn't fall back to another port");
+  let port = taken.local_addr().unwrap().port();
+  assert!(serv.local_addr().port() > port, "didn't walk upwards");
+  assert_eq!(
+    serv.urls(),
+    vec![format!("http://127.0.0.1:{}/", serv.local_addr().port())],
+    "wrong URL"
+  );
+}
+
+#[test]
+fn test_unresolvable() {
+  let serv = Httpserv::new(Config {
+    hostnames: vec!["127.0.0.1:0".into(), "no.such.host.invalid:80".into()],
+    ..Default::default()
+  });
+  assert!(serv.is_err(), "bad hostname ignored");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket() {
+  use std::os::unix::net::{UnixListener, UnixStream};
+
+  let dir = std::env::temp_dir();
+  let path = dir.join(format!("httpserv-test-{}.sock", std::process::id()));
+  // a socket left behind by something that's since exited
+  drop(UnixListener::bind(&path).expect("failed to make stale socket"));
+  let cfg = || Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec![format!("unix:{}", path.display())],
+    ..Default::default()
+  };
+  let server = Httpserv::spawn(cfg()).expect("stale socket not replaced");
+  let encoded = path.to_str().unwrap().replace('/', "%2F");
+  assert_eq!(server.url(), format!("http+unix://{}/", encoded));
+
+  let mut stream = UnixStream::connect(&path).expect("failed to connect");
+  write!(stream, "GET /file HTTP/1.1\n\n").expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "wrong status");
+  assert!(resp.ends_with("\n\n2\n"), "wrong body");
+
+  assert!(Httpserv::new(cfg()).is_err(), "took over a live socket");
+  assert!(path.exists(), "failed bind removed the live socket");
+  server.shutdown();
+  assert!(!path.exists(), "socket left behind");
+
+  std::fs::write(&path, "not a socket").unwrap();
+  assert!(Httpserv::new(cfg()).is_err(), "replaced a regular file");
+  assert!(path.exists(), "regular file removed");
+  std::fs::remove_file(&path).unwrap();
+}
+
+/// Connections over TCP, but counted, to show they came through here
+#[derive(Debug)]
+struct Counted {
+  tcp: TcpListener,
+  accepted: Arc<AtomicUsize>,
+}
+
+impl Listener for Counted {
+  fn accept(&self) -> io::Result<Box<dyn Transport>> {
+    let (conn, _) = self.tcp.accept()?;
+    self.accepted.fetch_add(1, Ordering::SeqCst);
+    Ok(Box::new(conn))
+  }
+
+  fn wake(&self) {
+    let _ = TcpStream::connect(self.tcp.local_addr().unwrap());
+  }
+}
+
+#[test]
+fn test_own_listener() {
+  let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = tcp.local_addr().unwrap();
+  let accepted = Arc::new(AtomicUsize::new(0));
+  let listener = Counted {
+    tcp,
+    accepted: accepted.clone(),
+  };
+  let cfg = Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec![],
+    ..Default::default()
+  };
+  let serv = Httpserv::with_listeners(cfg, vec![Box::new(listener)])
+    .expect("Failed to start httpserv");
+  assert!(serv.local_addrs().is_empty(), "listened somewhere else too");
+  let server = serv.start();
+  let resp = get_file(addr);
+  assert!(resp.ends_with("\n2\n"), "wrong response");
+  assert_eq!(accepted.load(Ordering::SeqCst), 1, "listener not used");
+  server.shutdown();
+}
@@ -0,0 +1,57 @@
+use std::{
+  io::{Read, Write},
+  net::TcpStream,
+  thread,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+#[test]
+fn test_log_file() {
+  let path = std::env::temp_dir()
+    .join(format!("httpserv-test-{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let server = Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:0".into()],
+    verbosity: Verbosity::Normal,
+    log_format: LogFormat::Combined,
+    log_file: Some(path.clone()),
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv");
+
+  // lines from requests at the same time shouldn't get mixed up
+  let addr = server.local_addr();
+  let clients: Vec<_> = (0..8)
+    .map(|i| {
+      thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        write!(
+          stream,
+          "GET /file?n={} HTTP/1.1\nUser-Agent: test \"{}\"\n\n",
+          i, i
+        )
+        .expect("failed to write");
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).expect("failed to read");
+      })
+    })
+    .collect();
+  for client in clients {
+    client.join().unwrap();
+  }
+  server.shutdown();
+
+  let log = std::fs::read_to_string(&path).expect("no log written");
+  std::fs::remove_file(&path).unwrap();
+  let lines: Vec<_> = log.lines().collect();
+  assert_eq!(lines.len(), 8, "wrong number of lines:\n{}", log);
+  for line in lines {
+    assert!(line.starts_with("127.0.0.1 - - ["), "bad line {}", line);
+    let n = line.split("?n=").nth(1).expect("no query").as_bytes()[0] as char;
+    let rest = format!(" HTTP/1.1\" 200 2 \"-\" \"test \\\"{}\\\"\"", n);
+    assert!(line.ends_with(&rest), "bad line {}", line);
+  }
+}
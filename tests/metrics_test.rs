@@ -0,0 +1,70 @@
+use std::{
+  io::{Read, Write},
+  sync::Arc,
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+fn request(server: &RunningServer, url: &str) -> String {
+  let mut stream = std::net::TcpStream::connect(server.local_addr())
+    .expect("failed to connect");
+  write!(stream, "GET {} HTTP/1.1\n\n", url).expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  resp
+}
+
+fn spawn(metrics: bool, vfs: Option<Arc<dyn Vfs>>) -> RunningServer {
+  let root = match vfs {
+    Some(_) => "",
+    None => "./tests/webroot",
+  };
+  Httpserv::spawn(Config {
+    root: root.into(),
+    vfs,
+    hostnames: vec!["127.0.0.1:0".into()],
+    metrics,
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv")
+}
+
+#[test]
+fn test_metrics() {
+  let server = spawn(true, None);
+  request(&server, "/file");
+  request(&server, "/nonexistent");
+
+  let resp = request(&server, "/__httpserv/metrics");
+  assert!(resp.starts_with("HTTP/1.1 200 OK\n"), "no metrics");
+  assert!(resp.contains("\nContent-Type: text/plain; version=0.0.4"));
+  assert!(resp.contains("\nhttpserv_responses_total{status=\"200\"} 1\n"));
+  assert!(resp.contains("\nhttpserv_responses_total{status=\"404\"} 1\n"));
+  assert!(resp.contains("\nhttpserv_open_connections 1\n"));
+
+  let resp = request(&server, "/__httpserv/metrics?format=json");
+  assert!(
+    resp.contains("\nContent-Type: application/json\n"),
+    "not JSON"
+  );
+  assert!(resp.contains("\"responses\":{\"200\":2,\"404\":1}"));
+}
+
+#[test]
+fn test_metrics_disabled() {
+  let server = spawn(false, None);
+  let resp = request(&server, "/__httpserv/metrics");
+  assert!(resp.starts_with("HTTP/1.1 404"), "metrics on by default");
+}
+
+#[test]
+fn test_metrics_not_shadowing() {
+  let mut fs = MemoryFs::new();
+  fs.insert_static("__httpserv/metrics", b"real file");
+  let server = spawn(true, Some(Arc::new(fs)));
+  let resp = request(&server, "/__httpserv/metrics");
+  assert!(resp.ends_with("\n\nreal file"), "real file shadowed");
+}
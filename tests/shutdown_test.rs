@@ -0,0 +1,76 @@
+use std::{
+  io::{Read, Write},
+  net::TcpStream,
+  thread::spawn,
+  time::{Duration, Instant},
+};
+
+extern crate httpserv;
+use httpserv::*;
+
+#[test]
+fn test_shutdown() {
+  let mut serv = Httpserv::new(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:0".into()],
+    shutdown_grace: Duration::from_secs(30),
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv");
+  let (addr, handle) = (serv.local_addr(), serv.shutdown_handle());
+  let running = spawn(move || serv.run());
+
+  let mut stream = TcpStream::connect(addr).expect("failed to connect");
+  write!(stream, "GET /file HTTP/1.1\n\n").expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  assert!(resp.ends_with("\n2\n"), "wrong response");
+
+  // an idle connection shouldn't hold up shutting down
+  let mut idle = TcpStream::connect(addr).expect("failed to connect");
+  let begin = Instant::now();
+  handle.shutdown();
+  assert!(handle.is_shutdown(), "handle doesn't know it's shut down");
+  let summary = running.join().expect("run panicked");
+  assert!(
+    begin.elapsed() < Duration::from_secs(10),
+    "waited on idle conn"
+  );
+  assert_eq!(summary.requests, 1, "wrong request count");
+  assert_eq!(summary.unfinished, 0, "wrong unfinished count");
+  assert_eq!(idle.read(&mut [0]).unwrap_or(0), 0, "idle conn left open");
+  assert!(TcpStream::connect(addr).is_err(), "still listening");
+}
+
+#[test]
+fn test_running_server() {
+  let server = Httpserv::spawn(Config {
+    root: "./tests/webroot".into(),
+    hostnames: vec!["127.0.0.1:8080".into()],
+    ..Default::default()
+  })
+  .expect("Failed to start httpserv");
+  let addr = server.local_addr();
+  assert_eq!(server.url(), format!("http://{}/", addr), "wrong URL");
+
+  // what the URL says is where the server is
+  let host = server
+    .url()
+    .trim_start_matches("http://")
+    .trim_end_matches('/');
+  let mut stream = TcpStream::connect(host).expect("failed to connect");
+  write!(stream, "GET /subdir/file HTTP/1.1\n\n").expect("failed to write");
+  let mut resp = String::new();
+  stream
+    .read_to_string(&mut resp)
+    .expect("failed to get response");
+  assert!(resp.ends_with("\n4\n"), "wrong response");
+
+  drop(server);
+  assert!(
+    TcpStream::connect(addr).is_err(),
+    "still listening after drop"
+  );
+}